@@ -10,6 +10,8 @@ pub struct Register {
     pub stdout: String,
     pub stderr: String,
     pub rc: i32,
+    pub changed: bool,
+    pub failed: bool,
 }
 
 // `mode:` values must be quoted strings: unquoted YAML `0600` is parsed as the
@@ -21,6 +23,14 @@ where
     deserializer.deserialize_any(ModeVisitor).map(Some)
 }
 
+// Keeps an explicit `value: null` apart from a missing `value:`.
+fn de_present<'de, D>(deserializer: D) -> Result<Option<serde_yaml::Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    serde_yaml::Value::deserialize(deserializer).map(Some)
+}
+
 fn de_required_mode<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -28,6 +38,23 @@ where
     deserializer.deserialize_any(ModeVisitor)
 }
 
+// `status_code:` takes one code or a list of them.
+fn de_status_codes<'de, D>(deserializer: D) -> Result<Option<Vec<u16>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StatusCodes {
+        One(u16),
+        Many(Vec<u16>),
+    }
+    Ok(Some(match StatusCodes::deserialize(deserializer)? {
+        StatusCodes::One(code) => vec![code],
+        StatusCodes::Many(codes) => codes,
+    }))
+}
+
 struct ModeVisitor;
 
 impl serde::de::Visitor<'_> for ModeVisitor {
@@ -59,6 +86,8 @@ pub struct Task {
     pub name: String,
     pub shell: Option<String>,
     pub command: Option<String>,
+    pub script: Option<ScriptSpec>,
+    pub git: Option<GitSpec>,
     pub creates: Option<String>,
     pub removes: Option<String>,
     pub register: Option<String>,
@@ -67,6 +96,15 @@ pub struct Task {
     pub vars: Option<IndexMap<String, Value>>,
     pub chdir: Option<String>,
     pub when: Option<String>,
+    pub ignore_errors: Option<bool>,
+    pub failed_when: Option<String>,
+    pub changed_when: Option<String>,
+    pub until: Option<String>,
+    pub retries: Option<u32>,
+    pub delay: Option<u64>,
+    pub timeout: Option<u64>,
+    pub r#async: Option<u64>,
+    pub poll: Option<u64>,
     pub r#loop: Option<Value>,
     pub include_tasks: Option<String>,
     pub login_shell: Option<bool>,
@@ -77,10 +115,195 @@ pub struct Task {
     pub template: Option<TemplateSpec>,
     pub copy: Option<CopySpec>,
     pub file: Option<FileSpec>,
+    pub lineinfile: Option<LineInFileSpec>,
+    pub blockinfile: Option<BlockInFileSpec>,
+    pub ini_file: Option<IniFileSpec>,
+    pub json_patch: Option<PatchSpec>,
+    pub yaml_patch: Option<PatchSpec>,
+    pub fetch: Option<FetchSpec>,
+    pub unarchive: Option<UnarchiveSpec>,
+    pub archive: Option<ArchiveSpec>,
+    pub stat: Option<StatSpec>,
+    pub get_url: Option<GetUrlSpec>,
+    pub uri: Option<UriSpec>,
+    pub wait_for: Option<WaitForSpec>,
     pub env_file: Option<EnvFileSpec>,
+    pub group: Option<GroupSpec>,
+    pub user: Option<UserSpec>,
+    pub cron: Option<CronSpec>,
+    pub sysctl: Option<SysctlSpec>,
     pub systemd: Option<SystemdSpec>,
+    pub compose: Option<ComposeSpec>,
     pub verify: Option<VerifySpec>,
+    pub async_status: Option<AsyncStatusSpec>,
+    pub assert: Option<AssertSpec>,
+    pub fail: Option<FailSpec>,
+    pub pause: Option<PauseSpec>,
     pub tags: Option<Vec<String>>,
+    pub block: Option<Vec<Task>>,
+    pub rescue: Option<Vec<Task>>,
+    pub always: Option<Vec<Task>>,
+}
+
+impl Task {
+    // Key combinations serde can't reject on its own. A block only groups
+    // tasks: its modifiers are inherited by the children, so anything that
+    // needs a single action of its own is rejected.
+    pub fn check_keys(&self) -> Result<(), String> {
+        if self.register.is_none() {
+            if self.failed_when.is_some() {
+                return Err("failed_when: requires register:".to_string());
+            }
+            if self.changed_when.is_some() {
+                return Err("changed_when: requires register:".to_string());
+            }
+            if self.until.is_some() {
+                return Err("until: requires register:".to_string());
+            }
+            if self.stat.is_some() {
+                return Err("stat: requires register:".to_string());
+            }
+        }
+        if self.delay.is_some() && self.retries.is_none() && self.until.is_none() {
+            return Err("delay: requires retries: or until:".to_string());
+        }
+        if self.timeout.is_some() || self.r#async.is_some() {
+            if self.shell.is_none() && self.command.is_none() {
+                return Err("timeout: and async: require shell: or command:".to_string());
+            }
+            if self.timeout.is_some() && self.r#async.is_some() {
+                return Err(
+                    "timeout: cannot be combined with async: (async: is the job's time limit)"
+                        .to_string(),
+                );
+            }
+            if self.timeout == Some(0) || self.r#async == Some(0) {
+                return Err("timeout: and async: must be at least 1 second".to_string());
+            }
+        }
+        if self.poll.is_some() && self.r#async.is_none() {
+            return Err("poll: requires async:".to_string());
+        }
+
+        if self.block.is_none() {
+            if self.rescue.is_some() || self.always.is_some() {
+                return Err("rescue: and always: require block:".to_string());
+            }
+            return Ok(());
+        }
+
+        let has_action = self.shell.is_some()
+            || self.command.is_some()
+            || self.script.is_some()
+            || self.git.is_some()
+            || self.debug.is_some()
+            || self.include_tasks.is_some()
+            || self.template.is_some()
+            || self.copy.is_some()
+            || self.file.is_some()
+            || self.lineinfile.is_some()
+            || self.blockinfile.is_some()
+            || self.ini_file.is_some()
+            || self.json_patch.is_some()
+            || self.yaml_patch.is_some()
+            || self.fetch.is_some()
+            || self.unarchive.is_some()
+            || self.archive.is_some()
+            || self.stat.is_some()
+            || self.get_url.is_some()
+            || self.uri.is_some()
+            || self.wait_for.is_some()
+            || self.env_file.is_some()
+            || self.group.is_some()
+            || self.user.is_some()
+            || self.cron.is_some()
+            || self.sysctl.is_some()
+            || self.systemd.is_some()
+            || self.compose.is_some()
+            || self.verify.is_some()
+            || self.async_status.is_some()
+            || self.assert.is_some()
+            || self.fail.is_some()
+            || self.pause.is_some();
+        if has_action {
+            return Err("block: cannot be combined with an action".to_string());
+        }
+        if self.r#loop.is_some() {
+            return Err("loop: cannot be used on a block".to_string());
+        }
+        if self.register.is_some() {
+            return Err("register: cannot be used on a block".to_string());
+        }
+        if self.retries.is_some() || self.until.is_some() {
+            return Err("retries: and until: cannot be used on a block".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AsyncStatusSpec {
+    pub job_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssertSpec {
+    pub that: Vec<String>,
+    pub fail_msg: Option<String>,
+    pub success_msg: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailSpec {
+    pub msg: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PauseSpec {
+    pub seconds: Option<u64>,
+    pub prompt: Option<String>,
+    pub echo: Option<bool>,
+    pub default: Option<String>,
+    #[serde(default)]
+    pub non_interactive: NonInteractive,
+}
+
+// What `pause:` does when stdin is not a terminal, e.g. under CI.
+#[derive(Debug, Default, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum NonInteractive {
+    #[default]
+    Fail,
+    Default,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptSpec {
+    pub src: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub executable: Option<String>,
+    #[serde(default)]
+    pub template: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GitSpec {
+    pub repo: String,
+    pub dest: String,
+    pub version: Option<String>,
+    pub depth: Option<u32>,
+    #[serde(default)]
+    pub force: bool,
+    pub key_file: Option<String>,
+    #[serde(default)]
+    pub accept_hostkey: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -113,6 +336,165 @@ pub struct FileSpec {
     pub group: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LineInFileSpec {
+    pub path: String,
+    pub regexp: Option<String>,
+    pub line: Option<String>,
+    #[serde(default)]
+    pub state: EditState,
+    pub insertafter: Option<String>,
+    pub insertbefore: Option<String>,
+    #[serde(default)]
+    pub create: bool,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockInFileSpec {
+    pub path: String,
+    #[serde(default)]
+    pub block: String,
+    pub marker: Option<String>,
+    #[serde(default)]
+    pub state: EditState,
+    pub insertafter: Option<String>,
+    pub insertbefore: Option<String>,
+    #[serde(default)]
+    pub create: bool,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IniFileSpec {
+    pub path: String,
+    pub section: Option<String>,
+    pub option: String,
+    pub value: Option<String>,
+    #[serde(default)]
+    pub state: EditState,
+    #[serde(default)]
+    pub create: bool,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchSpec {
+    pub path: String,
+    pub operations: Vec<PatchOperation>,
+    #[serde(default)]
+    pub create: bool,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub mode: Option<String>,
+}
+
+// One `set:` or `delete:` at a JSON pointer (`/a/b/0`) or dotted (`a.b.0`)
+// path.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchOperation {
+    pub set: Option<String>,
+    pub delete: Option<String>,
+    #[serde(default, deserialize_with = "de_present")]
+    pub value: Option<serde_yaml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetchSpec {
+    pub src: String,
+    pub dest: String,
+    #[serde(default)]
+    pub flat: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnarchiveSpec {
+    pub src: String,
+    pub dest: String,
+    #[serde(default)]
+    pub remote_src: bool,
+    pub strip_components: Option<u32>,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub mode: Option<String>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveSpec {
+    pub path: String,
+    pub dest: String,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatSpec {
+    pub path: String,
+    #[serde(default)]
+    pub checksum: bool,
+}
+
+// Where an HTTP action runs curl: on the target (default), or on the machine
+// running deploy-helper.
+#[derive(Debug, Default, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RunFrom {
+    #[default]
+    Target,
+    Control,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetUrlSpec {
+    pub url: String,
+    pub dest: String,
+    pub checksum: Option<String>,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub mode: Option<String>,
+    pub headers: Option<IndexMap<String, String>>,
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub from: RunFrom,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UriSpec {
+    pub url: String,
+    pub method: Option<String>,
+    pub headers: Option<IndexMap<String, String>>,
+    pub body: Option<String>,
+    pub json: Option<Value>,
+    #[serde(default, deserialize_with = "de_status_codes")]
+    pub status_code: Option<Vec<u16>>,
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub from: RunFrom,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EditState {
+    #[default]
+    Present,
+    Absent,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct EnvFileSpec {
@@ -151,15 +533,93 @@ pub enum VarsFileProvider {
     Sops,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserSpec {
+    pub name: String,
+    pub uid: Option<u32>,
+    pub group: Option<String>,
+    pub groups: Option<Vec<String>>,
+    pub shell: Option<String>,
+    pub home: Option<String>,
+    #[serde(default)]
+    pub system: bool,
+    pub create_home: Option<bool>,
+    #[serde(default)]
+    pub state: EditState,
+    pub authorized_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupSpec {
+    pub name: String,
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub system: bool,
+    #[serde(default)]
+    pub state: EditState,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CronSpec {
+    pub name: String,
+    pub minute: Option<String>,
+    pub hour: Option<String>,
+    pub day: Option<String>,
+    pub month: Option<String>,
+    pub weekday: Option<String>,
+    pub special_time: Option<CronSpecialTime>,
+    pub job: Option<String>,
+    pub user: Option<String>,
+    pub cron_file: Option<String>,
+    pub env: Option<IndexMap<String, String>>,
+    #[serde(default)]
+    pub state: EditState,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SysctlSpec {
+    pub name: String,
+    pub value: String,
+    pub sysctl_file: Option<String>,
+    pub reload: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CronSpecialTime {
+    Reboot,
+    Yearly,
+    Annually,
+    Monthly,
+    Weekly,
+    Daily,
+    Hourly,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct SystemdSpec {
     #[serde(default)]
     pub daemon_reload: bool,
+    #[serde(default)]
+    pub scope: SystemdScope,
+    pub journal_lines: Option<usize>,
     pub units: Vec<SystemdUnitSpec>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemdScope {
+    #[default]
+    System,
+    User,
+}
+
+#[derive(Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct SystemdUnitSpec {
     pub name: String,
@@ -170,6 +630,21 @@ pub struct SystemdUnitSpec {
     #[serde(default)]
     pub assert_active: bool,
     pub assert_result: Option<String>,
+    pub wait_active: Option<SystemdWaitActive>,
+    #[serde(default)]
+    pub assert_scheduled: bool,
+    #[serde(default)]
+    pub run_now: bool,
+    pub content: Option<String>,
+    pub template: Option<String>,
+    pub dropins: Option<IndexMap<String, String>>,
+    pub masked: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SystemdWaitActive {
+    pub timeout_seconds: u64,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -181,19 +656,117 @@ pub enum SystemdUnitState {
     Reloaded,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeSpec {
+    pub project_dir: String,
+    pub files: Option<Vec<String>>,
+    #[serde(default)]
+    pub state: ComposeState,
+    pub services: Option<Vec<String>>,
+    pub pull: Option<ComposePull>,
+    #[serde(default)]
+    pub remove_orphans: bool,
+    pub wait: Option<ComposeWait>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ComposeState {
+    #[default]
+    Up,
+    Down,
+    Restarted,
+    Pulled,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ComposePull {
+    Always,
+    Missing,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeWait {
+    pub timeout_seconds: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaitForSpec {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub path: Option<String>,
+    pub search_regex: Option<String>,
+    pub state: Option<WaitForState>,
+    pub timeout: Option<u64>,
+    pub delay: Option<u64>,
+    pub sleep: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum WaitForState {
+    Started,
+    Stopped,
+    Present,
+    Absent,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct VerifySpec {
-    pub command: String,
+    pub command: Option<String>,
+    pub http: Option<VerifyHttp>,
+    pub tcp: Option<VerifyTcp>,
     pub expect: Option<VerifyExpectation>,
     pub retry: Option<VerifyRetry>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
+pub struct VerifyHttp {
+    pub url: String,
+    pub method: Option<String>,
+    pub headers: Option<IndexMap<String, String>>,
+    #[serde(default, deserialize_with = "de_status_codes")]
+    pub status_code: Option<Vec<u16>>,
+    pub json: Option<IndexMap<String, Value>>,
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub from: RunFrom,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyTcp {
+    pub host: Option<String>,
+    pub port: u16,
+    #[serde(default)]
+    pub from: RunFrom,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct VerifyExpectation {
     pub equals: Option<String>,
     pub regex: Option<String>,
+    pub contains: Option<String>,
+    pub not_contains: Option<String>,
+    pub json: Option<IndexMap<String, Value>>,
+    pub rc: Option<i32>,
+    pub stderr: Option<VerifyTextExpectation>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyTextExpectation {
+    pub equals: Option<String>,
+    pub regex: Option<String>,
+    pub contains: Option<String>,
+    pub not_contains: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -252,6 +825,16 @@ mod tests {
         assert_eq!(spec.mode, Some("0600".to_string()));
     }
 
+    #[test]
+    fn lineinfile_state_defaults_to_present() {
+        let spec: LineInFileSpec = serde_yaml::from_str("path: /etc/hosts\nline: x\n").unwrap();
+        assert_eq!(spec.state, EditState::Present);
+        assert!(!spec.create);
+        let result: Result<BlockInFileSpec, _> =
+            serde_yaml::from_str("path: /etc/hosts\nstate: removed\n");
+        assert!(result.is_err());
+    }
+
     #[test]
     fn copy_mode_absent_is_none() {
         let yaml = "src: a\ndest: b\n";
@@ -367,6 +950,21 @@ mod tests {
         assert_eq!(spec.units[1].assert_result.as_deref(), Some("success"));
     }
 
+    #[test]
+    fn systemd_spec_parses_wait_active_and_timer_options() {
+        let yaml = "units:\n  - name: app.service\n    state: restarted\n    wait_active:\n      timeout_seconds: 30\n  - name: app.timer\n    assert_scheduled: true\n    run_now: true\n";
+        let spec: SystemdSpec = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            spec.units[0]
+                .wait_active
+                .as_ref()
+                .map(|wait| wait.timeout_seconds),
+            Some(30)
+        );
+        assert!(spec.units[1].assert_scheduled);
+        assert!(spec.units[1].run_now);
+    }
+
     #[test]
     fn systemd_spec_rejects_unknown_state() {
         let yaml = "units:\n  - name: app.service\n    state: running\n";
@@ -374,6 +972,42 @@ mod tests {
         assert!(err.to_string().contains("unknown variant `running`"));
     }
 
+    #[test]
+    fn systemd_spec_parses_user_scope_and_journal_lines() {
+        let yaml = "scope: user
+journal_lines: 50
+units:
+  - name: app.service
+    state: started
+";
+        let spec: SystemdSpec = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(spec.scope, SystemdScope::User);
+        assert_eq!(spec.journal_lines, Some(50));
+        let spec: SystemdSpec = serde_yaml::from_str("units: []\n").unwrap();
+        assert_eq!(spec.scope, SystemdScope::System);
+    }
+
+    #[test]
+    fn compose_spec_parses_project_options() {
+        let yaml = "project_dir: /srv/app\nfiles: [compose.yml, compose.prod.yml]\nservices: [web]\npull: missing\nremove_orphans: true\nwait:\n  timeout_seconds: 120\n";
+        let spec: ComposeSpec = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(spec.state, ComposeState::Up);
+        assert_eq!(spec.pull, Some(ComposePull::Missing));
+        assert_eq!(spec.files.as_ref().map(Vec::len), Some(2));
+        assert!(spec.remove_orphans);
+        assert_eq!(spec.wait.map(|wait| wait.timeout_seconds), Some(120));
+    }
+
+    #[test]
+    fn patch_spec_keeps_explicit_null_values() {
+        let yaml =
+            "path: config.json\noperations:\n  - set: a.b\n    value: null\n  - delete: /c\n";
+        let spec: PatchSpec = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(spec.operations[0].value, Some(serde_yaml::Value::Null));
+        assert_eq!(spec.operations[1].value, None);
+        assert_eq!(spec.operations[1].delete.as_deref(), Some("/c"));
+    }
+
     #[test]
     fn verify_spec_parses_namespaced_matcher_and_retry() {
         let yaml = "command: docker inspect app\nexpect:\n  equals: healthy\nretry:\n  attempts: 12\n  delay_seconds: 5\n  max_elapsed_seconds: 60\n";
@@ -384,10 +1018,119 @@ mod tests {
         assert_eq!(retry.max_elapsed_seconds, Some(60));
     }
 
+    #[test]
+    fn verify_spec_parses_http_and_tcp_probes() {
+        let yaml = "http:\n  url: http://127.0.0.1:8080/health\n  status_code: [200, 204]\n  json:\n    $.status: ok\n  from: control\n";
+        let spec: VerifySpec = serde_yaml::from_str(yaml).unwrap();
+        assert!(spec.command.is_none());
+        let http = spec.http.unwrap();
+        assert_eq!(http.status_code, Some(vec![200, 204]));
+        assert_eq!(http.json.unwrap()["$.status"], Value::from("ok"));
+        assert_eq!(http.from, RunFrom::Control);
+
+        let spec: VerifySpec = serde_yaml::from_str("tcp:\n  port: 5432\n").unwrap();
+        let tcp = spec.tcp.unwrap();
+        assert_eq!(
+            (tcp.host, tcp.port, tcp.from),
+            (None, 5432, RunFrom::Target)
+        );
+    }
+
+    #[test]
+    fn verify_spec_parses_combined_matchers() {
+        let yaml = "command: ./bin/check\nexpect:\n  rc: 1\n  contains: degraded\n  not_contains: panic\n  json:\n    $.state: degraded\n  stderr:\n    regex: retrying\n";
+        let expect = serde_yaml::from_str::<VerifySpec>(yaml)
+            .unwrap()
+            .expect
+            .unwrap();
+        assert_eq!(expect.rc, Some(1));
+        assert_eq!(expect.contains.as_deref(), Some("degraded"));
+        assert_eq!(expect.not_contains.as_deref(), Some("panic"));
+        assert_eq!(expect.json.unwrap()["$.state"], Value::from("degraded"));
+        assert_eq!(expect.stderr.unwrap().regex.as_deref(), Some("retrying"));
+    }
+
     #[test]
     fn verify_spec_rejects_old_flat_matcher() {
         let yaml = "command: echo healthy\nexpect_regex: healthy\n";
         let err = serde_yaml::from_str::<VerifySpec>(yaml).unwrap_err();
         assert!(err.to_string().contains("unknown field `expect_regex`"));
     }
+
+    #[test]
+    fn task_parses_block_with_rescue_and_always() {
+        let yaml = "name: Migrate\nblock:\n  - name: Run\n    command: migrate\nrescue:\n  - name: Roll back\n    command: rollback\nalways:\n  - name: Unlock\n    command: unlock\n";
+        let task: Task = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(task.block.as_ref().unwrap()[0].name, "Run");
+        assert_eq!(task.rescue.as_ref().unwrap()[0].name, "Roll back");
+        assert_eq!(task.always.as_ref().unwrap()[0].name, "Unlock");
+        assert!(task.check_keys().is_ok());
+    }
+
+    #[test]
+    fn check_keys_rejects_misplaced_keys() {
+        let cases = [
+            (
+                "name: X\nshell: echo hi\nrescue:\n  - name: R\n    command: true\n",
+                "require block:",
+            ),
+            (
+                "name: X\nshell: echo hi\nblock:\n  - name: B\n    command: true\n",
+                "cannot be combined with an action",
+            ),
+            (
+                "name: X\nloop: [1, 2]\nblock:\n  - name: B\n    command: true\n",
+                "loop: cannot be used on a block",
+            ),
+            (
+                "name: X\nregister: out\nblock:\n  - name: B\n    command: true\n",
+                "register: cannot be used on a block",
+            ),
+            (
+                "name: X\ncommand: grep -q x f\nfailed_when: rc > 1\n",
+                "failed_when: requires register:",
+            ),
+            (
+                "name: X\ncommand: true\nchanged_when: false\n",
+                "changed_when: requires register:",
+            ),
+            (
+                "name: X\ncommand: true\nuntil: out.rc == 0\n",
+                "until: requires register:",
+            ),
+            (
+                "name: X\nstat:\n  path: /etc/hosts\n",
+                "stat: requires register:",
+            ),
+            (
+                "name: X\ncommand: true\ndelay: 5\n",
+                "delay: requires retries: or until:",
+            ),
+            (
+                "name: X\nretries: 2\nblock:\n  - name: B\n    command: true\n",
+                "retries: and until: cannot be used on a block",
+            ),
+            (
+                "name: X\ndebug:\n  msg: hi\ntimeout: 5\n",
+                "timeout: and async: require shell: or command:",
+            ),
+            (
+                "name: X\ncommand: sleep 60\ntimeout: 5\nasync: 60\n",
+                "cannot be combined with async:",
+            ),
+            (
+                "name: X\nshell: sleep 60\ntimeout: 0\n",
+                "must be at least 1 second",
+            ),
+            (
+                "name: X\nshell: sleep 60\npoll: 5\n",
+                "poll: requires async:",
+            ),
+        ];
+        for (yaml, expected) in cases {
+            let task: Task = serde_yaml::from_str(yaml).unwrap();
+            let err = task.check_keys().unwrap_err();
+            assert!(err.contains(expected), "{}: {}", yaml, err);
+        }
+    }
 }
@@ -11,6 +11,14 @@ use serde_json::Value;
 use ssh2::Session;
 use std::path::Path;
 use std::process::exit;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// `until:` without `retries:` gets a few retries rather than none.
+const DEFAULT_UNTIL_RETRIES: u32 = 3;
+
+// How often an `async:` task checks on its job when `poll:` is not set.
+const DEFAULT_ASYNC_POLL_SECONDS: u64 = 10;
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -72,6 +80,7 @@ pub(crate) struct Deployment {
 }
 
 struct RunContext<'a> {
+    host: &'a str,
     is_localhost: bool,
     session: Option<&'a Session>,
     vars_map: &'a mut IndexMap<String, Value>,
@@ -80,6 +89,7 @@ struct RunContext<'a> {
     filter_config: &'a filter::FilterConfig,
     filter_state: &'a mut filter::GateState,
     step_state: &'a mut modules::step::StepState,
+    run_state: &'a mut modules::run_state::RunState,
 }
 
 pub(crate) fn apply_deployment_vars(
@@ -128,10 +138,24 @@ fn process_tasks(
     dep_become: Option<bool>,
     dep_become_method: Option<&str>,
     ancestor_tags: &[String],
+    parent_path: &[usize],
 ) -> Result<(), Box<dyn std::error::Error>> {
-    for task in tasks {
-        let no_log = task.no_log.unwrap_or(false);
+    for (index, task) in tasks.iter().enumerate() {
+        let no_log = task.no_log.unwrap_or(false)
+            || task.pause.as_ref().is_some_and(modules::pause::is_secret);
         let task_name = utils::replace_placeholders(&task.name, ctx.vars_map);
+        let task_path = [parent_path, &[index]].concat();
+
+        task.check_keys()
+            .map_err(|error| utils::task_error(&task_name, error.into()))?;
+
+        if ctx.run_state.skips_task(&task_path) {
+            println!(
+                "{}",
+                format!("Skipping task: {} (completed before resume)\n", task_name).yellow()
+            );
+            continue;
+        }
 
         let effective_tags = filter::merge_tags(ancestor_tags, task.tags.as_deref());
 
@@ -263,150 +287,48 @@ fn process_tasks(
             }
         }
 
+        if let Some(block) = &task.block {
+            let result = process_block(
+                ctx,
+                task,
+                &task_name,
+                block,
+                task_chdir.as_deref(),
+                use_login_shell,
+                task_shell_defaults,
+                task_environment.as_ref(),
+                task.r#become.or(dep_become),
+                task.become_method.as_deref().or(dep_become_method),
+                &effective_tags,
+                &task_path,
+            );
+            apply_ignore_errors(task, result)?;
+            complete_task(ctx, task, &task_path, &task_name, no_log)?;
+            println!();
+            continue;
+        }
+
         let loop_items = resolve_loop_items(task.r#loop.as_ref(), ctx.vars_map, no_log)
             .map_err(|error| utils::task_error(&task_name, error))?;
 
-        for item in loop_items {
+        let settings = TaskSettings {
+            chdir: task_chdir.as_deref(),
+            login_shell: use_login_shell,
+            shell_defaults: task_shell_defaults,
+            environment: task_environment.as_ref(),
+            r#become: task_become,
+            become_method: &task_become_method,
+        };
+
+        for (iteration, item) in loop_items.into_iter().enumerate() {
             ctx.vars_map.shift_remove("item");
 
             if !item.is_null() {
                 ctx.vars_map.insert("item".to_string(), item.clone());
             }
 
-            if let Some(debug) = &task.debug {
-                modules::debug::process(debug, ctx.vars_map, no_log);
-            }
-
-            let task_become_password = ctx.become_password.as_deref().filter(|s| !s.is_empty());
-
-            if let Some(shell_command) = &task.shell {
-                let display_segments = utils::split_commands(shell_command);
-                modules::command::process_shell_block(
-                    shell_command,
-                    display_segments,
-                    task_shell_defaults,
-                    task_environment.as_ref(),
-                    ctx.is_localhost,
-                    ctx.session,
-                    task_chdir.as_deref(),
-                    task.register.as_ref(),
-                    use_login_shell,
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    no_log,
-                )?;
-            }
-
-            if let Some(command) = &task.command {
-                let commands = utils::split_commands(command);
-                modules::command::process_command(
-                    commands,
-                    task_environment.as_ref(),
-                    ctx.is_localhost,
-                    ctx.session,
-                    task_chdir.as_deref(),
-                    task.register.as_ref(),
-                    use_login_shell,
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    no_log,
-                )?;
-            }
-
-            if let Some(spec) = &task.template {
-                modules::template::process(
-                    &task_name,
-                    spec,
-                    ctx.deploy_file_dir,
-                    ctx.is_localhost,
-                    ctx.session,
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    task.register.as_ref(),
-                )?;
-            }
-
-            if let Some(spec) = &task.copy {
-                modules::copy::process(
-                    &task_name,
-                    spec,
-                    ctx.deploy_file_dir,
-                    ctx.is_localhost,
-                    ctx.session,
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    task.register.as_ref(),
-                )?;
-            }
-
-            if let Some(spec) = &task.file {
-                modules::file::process(
-                    &task_name,
-                    spec,
-                    ctx.is_localhost,
-                    ctx.session,
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    task.register.as_ref(),
-                )?;
-            }
-
-            if let Some(spec) = &task.env_file {
-                modules::env_file::process(
-                    &task_name,
-                    spec,
-                    ctx.is_localhost,
-                    ctx.session,
-                    task_chdir.as_deref(),
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    task.register.as_ref(),
-                )?;
-            }
-
-            if let Some(spec) = &task.systemd {
-                modules::systemd::process(
-                    &task_name,
-                    spec,
-                    ctx.is_localhost,
-                    ctx.session,
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    task.register.as_ref(),
-                )?;
-            }
-
-            if let Some(spec) = &task.verify {
-                modules::verify::process(
-                    &task_name,
-                    spec,
-                    task_environment.as_ref(),
-                    ctx.is_localhost,
-                    ctx.session,
-                    task_chdir.as_deref(),
-                    task.register.as_ref(),
-                    use_login_shell,
-                    ctx.vars_map,
-                    task_become,
-                    &task_become_method,
-                    task_become_password,
-                    no_log,
-                )?;
-            }
+            let result = run_task_attempts(ctx, task, &task_name, &settings, no_log);
+            apply_ignore_errors(task, result)?;
 
             if let Some(include_file) = &task.include_tasks {
                 println!(
@@ -416,6 +338,13 @@ fn process_tasks(
                 let include_file_path = ctx.deploy_file_dir.join(include_file);
                 let included_tasks =
                     modules::include_tasks::process(include_file_path.to_str().unwrap());
+                // Each iteration of a looped include gets its own position,
+                // so `--resume` does not treat later iterations as done.
+                let include_path = if task.r#loop.is_some() {
+                    [task_path.as_slice(), &[iteration]].concat()
+                } else {
+                    task_path.clone()
+                };
                 process_tasks(
                     ctx,
                     &included_tasks,
@@ -426,42 +355,773 @@ fn process_tasks(
                     dep_become,
                     dep_become_method,
                     &effective_tags,
+                    &include_path,
                 )?;
             }
         }
 
+        complete_task(ctx, task, &task_path, &task_name, no_log)?;
+
         println!();
     }
 
     Ok(())
 }
 
-fn finish_task_sections(
-    main_error: Option<Box<dyn std::error::Error>>,
-    on_failure_error: Option<Box<dyn std::error::Error>>,
-    always_error: Option<Box<dyn std::error::Error>>,
+// Settings a task's actions run with, after falling back to the deployment
+// (or enclosing include/block) values.
+struct TaskSettings<'a> {
+    chdir: Option<&'a str>,
+    login_shell: bool,
+    shell_defaults: Option<&'a str>,
+    environment: Option<&'a IndexMap<String, String>>,
+    r#become: bool,
+    become_method: &'a str,
+}
+
+fn run_task_actions(
+    ctx: &mut RunContext,
+    task: &common::Task,
+    task_name: &str,
+    settings: &TaskSettings,
+    no_log: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let error_count = usize::from(main_error.is_some())
-        + usize::from(on_failure_error.is_some())
-        + usize::from(always_error.is_some());
+    if let Some(debug) = &task.debug {
+        modules::debug::process(debug, ctx.vars_map, no_log);
+    }
 
-    if error_count == 0 {
-        return Ok(());
+    let task_become_password = ctx.become_password.as_deref().filter(|s| !s.is_empty());
+    let limits = modules::command::Limits {
+        timeout: task.timeout,
+        r#async: task.r#async.map(|limit| modules::async_job::Job {
+            limit,
+            poll: task.poll.unwrap_or(DEFAULT_ASYNC_POLL_SECONDS),
+        }),
+    };
+
+    if let Some(shell_command) = &task.shell {
+        let display_segments = utils::split_commands(shell_command);
+        modules::command::process_shell_block(
+            shell_command,
+            display_segments,
+            settings.shell_defaults,
+            settings.environment,
+            ctx.is_localhost,
+            ctx.session,
+            settings.chdir,
+            task.register.as_ref(),
+            settings.login_shell,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            no_log,
+            limits,
+        )?;
+    }
+
+    if let Some(command) = &task.command {
+        let commands = utils::split_commands(command);
+        modules::command::process_command(
+            commands,
+            settings.environment,
+            ctx.is_localhost,
+            ctx.session,
+            settings.chdir,
+            task.register.as_ref(),
+            settings.login_shell,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            no_log,
+            limits,
+        )?;
+    }
+
+    if let Some(spec) = &task.script {
+        modules::script::process(
+            task_name,
+            spec,
+            ctx.deploy_file_dir,
+            settings.environment,
+            ctx.is_localhost,
+            ctx.session,
+            settings.chdir,
+            task.register.as_ref(),
+            settings.login_shell,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            no_log,
+        )?;
+    }
+
+    if let Some(spec) = &task.git {
+        modules::git::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.template {
+        modules::template::process(
+            task_name,
+            spec,
+            ctx.deploy_file_dir,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.copy {
+        modules::copy::process(
+            task_name,
+            spec,
+            ctx.deploy_file_dir,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.file {
+        modules::file::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.lineinfile {
+        modules::lineinfile::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.blockinfile {
+        modules::blockinfile::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.ini_file {
+        modules::ini_file::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.json_patch {
+        modules::patch::process(
+            task_name,
+            spec,
+            modules::patch::Format::Json,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+            no_log,
+        )?;
+    }
+
+    if let Some(spec) = &task.yaml_patch {
+        modules::patch::process(
+            task_name,
+            spec,
+            modules::patch::Format::Yaml,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+            no_log,
+        )?;
+    }
+
+    if let Some(spec) = &task.fetch {
+        modules::fetch::process(
+            task_name,
+            spec,
+            ctx.host,
+            ctx.deploy_file_dir,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.unarchive {
+        modules::archive::process_unarchive(
+            task_name,
+            spec,
+            ctx.deploy_file_dir,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.archive {
+        modules::archive::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.stat {
+        modules::stat::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.get_url {
+        modules::get_url::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+            no_log,
+        )?;
+    }
+
+    if let Some(spec) = &task.uri {
+        modules::uri::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            task.register.as_ref(),
+            no_log,
+        )?;
+    }
+
+    if let Some(spec) = &task.env_file {
+        modules::env_file::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            settings.chdir,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.group {
+        modules::user::process_group(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.user {
+        modules::user::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.cron {
+        modules::cron::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.sysctl {
+        modules::sysctl::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.systemd {
+        modules::systemd::process(
+            task_name,
+            spec,
+            ctx.deploy_file_dir,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
+    }
+
+    if let Some(spec) = &task.compose {
+        modules::compose::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
     }
-    if error_count == 1 {
-        return Err(main_error.or(on_failure_error).or(always_error).unwrap());
+
+    if let Some(spec) = &task.wait_for {
+        modules::wait_for::process(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+        )?;
     }
 
-    let mut messages = Vec::new();
-    if let Some(error) = main_error {
-        messages.push(format!("main tasks failed: {}", error));
+    if let Some(spec) = &task.verify {
+        modules::verify::process(
+            task_name,
+            spec,
+            settings.environment,
+            ctx.is_localhost,
+            ctx.session,
+            settings.chdir,
+            task.register.as_ref(),
+            settings.login_shell,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            no_log,
+        )?;
     }
-    if let Some(error) = on_failure_error {
-        messages.push(format!("on_failure tasks failed: {}", error));
+
+    if let Some(spec) = &task.async_status {
+        modules::async_job::process_status(
+            task_name,
+            spec,
+            ctx.is_localhost,
+            ctx.session,
+            ctx.vars_map,
+            settings.r#become,
+            settings.become_method,
+            task_become_password,
+            task.register.as_ref(),
+            no_log,
+        )?;
     }
-    if let Some(error) = always_error {
-        messages.push(format!("always tasks failed: {}", error));
+
+    if let Some(spec) = &task.assert {
+        modules::assert::process(
+            task_name,
+            spec,
+            ctx.vars_map,
+            task.register.as_ref(),
+            no_log,
+        )?;
+    }
+
+    if let Some(spec) = &task.fail {
+        modules::assert::process_fail(
+            task_name,
+            spec,
+            ctx.vars_map,
+            task.register.as_ref(),
+            no_log,
+        )?;
+    }
+
+    if let Some(spec) = &task.pause {
+        modules::pause::process(
+            task_name,
+            spec,
+            ctx.vars_map,
+            task.register.as_ref(),
+            no_log,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Runs a task's actions once, or under `retries:`/`until:` until an attempt
+// succeeds. A failed attempt and an unmet `until:` are both retried; the
+// error of the last attempt is reported once the retries run out.
+fn run_task_attempts(
+    ctx: &mut RunContext,
+    task: &common::Task,
+    task_name: &str,
+    settings: &TaskSettings,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let retries = task
+        .retries
+        .or_else(|| task.until.as_ref().map(|_| DEFAULT_UNTIL_RETRIES));
+    let Some(retries) = retries else {
+        let result = run_task_actions(ctx, task, task_name, settings, no_log);
+        return apply_result_modifiers(ctx, task, task_name, no_log, result);
+    };
+
+    let attempts = retries + 1;
+    let delay_seconds = task.delay.unwrap_or(0);
+    let started = Instant::now();
+    for attempt in 1..=attempts {
+        println!("{}", format!("Attempt {}/{}", attempt, attempts).blue());
+        let result = run_task_actions(ctx, task, task_name, settings, no_log);
+        let result = apply_result_modifiers(ctx, task, task_name, no_log, result).and_then(|()| {
+            if modules::when::process(&task.until, ctx.vars_map, no_log)? {
+                Ok(())
+            } else {
+                Err("until condition not met".into())
+            }
+        });
+        if let Some(Value::Object(registered)) = task
+            .register
+            .as_ref()
+            .and_then(|register| ctx.vars_map.get_mut(register))
+        {
+            registered.insert("attempts".to_string(), Value::from(attempt));
+        }
+
+        let error = match result {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        if attempt == attempts {
+            return Err(utils::task_error(
+                task_name,
+                format!("failed after {} attempts: {}", attempts, error).into(),
+            ));
+        }
+
+        // Same wait policy as verify:, wait_for: and compose:. Task retries
+        // have no elapsed-time limit, so this is always `delay:`.
+        let wait =
+            modules::verify::retry_wait(delay_seconds, None, started.elapsed()).unwrap_or_default();
+        if no_log {
+            println!(
+                "{}",
+                format!("Attempt {}/{} failed", attempt, attempts).yellow()
+            );
+        } else {
+            println!(
+                "{}",
+                format!("Attempt {}/{} failed: {}", attempt, attempts, error).yellow()
+            );
+        }
+        println!(
+            "{}",
+            format!(
+                "Retrying in {} second{}\n",
+                wait.as_secs(),
+                if wait == Duration::from_secs(1) {
+                    ""
+                } else {
+                    "s"
+                }
+            )
+            .yellow()
+        );
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
     }
+
+    Ok(())
+}
+
+// Applies `failed_when:` and `changed_when:` to the result of one run of a
+// task's actions. Both conditions see the just-registered result; a command
+// that exited non-zero has registered its output too.
+fn apply_result_modifiers(
+    ctx: &mut RunContext,
+    task: &common::Task,
+    task_name: &str,
+    no_log: bool,
+    result: Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut result = result;
+
+    if let Some(register) = &task.register {
+        let exit_failure = matches!(
+            &result,
+            Err(error) if error.is::<modules::command::ExitStatusError>()
+        );
+        if task.failed_when.is_some() && (result.is_ok() || exit_failure) {
+            let failed = modules::when::process(&task.failed_when, ctx.vars_map, no_log)
+                .map_err(|error| utils::task_error(task_name, error))?;
+            result = if failed {
+                Err(utils::task_error(
+                    task_name,
+                    "failed_when condition matched".into(),
+                ))
+            } else {
+                Ok(())
+            };
+        }
+
+        let changed = if task.changed_when.is_some() && result.is_ok() {
+            modules::when::process(&task.changed_when, ctx.vars_map, no_log)
+                .map_err(|error| utils::task_error(task_name, error))?
+        } else {
+            ctx.vars_map
+                .get(register)
+                .and_then(|registered| registered.get("changed"))
+                .and_then(Value::as_bool)
+                .unwrap_or(false)
+        };
+        if let Some(Value::Object(registered)) = ctx.vars_map.get_mut(register) {
+            registered.insert("changed".to_string(), Value::Bool(changed));
+            registered.insert("failed".to_string(), Value::Bool(result.is_err()));
+        }
+    }
+
+    result
+}
+
+fn apply_ignore_errors(
+    task: &common::Task,
+    result: Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match result {
+        Err(error) if task.ignore_errors.unwrap_or(false) => {
+            // Command errors end with a note that the run stops, which is
+            // not true once the error is ignored.
+            let message = error.to_string().replace(" Stopping further tasks.", "");
+            println!("{}", format!("Ignoring error: {}", message).yellow());
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn complete_task(
+    ctx: &mut RunContext,
+    task: &common::Task,
+    task_path: &[usize],
+    task_name: &str,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut completed_keys: Vec<&str> = Vec::new();
+    if let Some(vars) = &task.vars {
+        completed_keys.extend(vars.keys().map(String::as_str));
+    }
+    if let Some(register) = &task.register {
+        completed_keys.push(register);
+    }
+    ctx.run_state
+        .complete_task(task_path, task_name, &completed_keys, ctx.vars_map, no_log)
+}
+
+// Runs a task-level `block:`. The children inherit the block's resolved
+// settings the same way included tasks do. A failure runs `rescue:`; if the
+// rescue tasks succeed the block counts as recovered and the run continues.
+// `always:` runs either way. Like deployment recovery, rescue and always
+// tasks get the `always` tag and are not recorded for `--resume`.
+#[allow(clippy::too_many_arguments)]
+fn process_block(
+    ctx: &mut RunContext,
+    task: &common::Task,
+    task_name: &str,
+    block: &[common::Task],
+    chdir: Option<&str>,
+    login_shell: bool,
+    shell_defaults: Option<&str>,
+    environment: Option<&IndexMap<String, String>>,
+    r#become: Option<bool>,
+    become_method: Option<&str>,
+    tags: &[String],
+    task_path: &[usize],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let recovery_tags = filter::merge_tags(tags, Some(&["always".to_string()]));
+    let run = |ctx: &mut RunContext, tasks: &[common::Task], section_tags: &[String]| {
+        process_tasks(
+            ctx,
+            tasks,
+            chdir,
+            login_shell,
+            shell_defaults,
+            environment,
+            r#become,
+            become_method,
+            section_tags,
+            task_path,
+        )
+    };
+
+    println!();
+    let mut block_error = run(ctx, block, tags).err();
+
+    let recording = ctx.run_state.set_recording(false);
+    let mut rescue_error = None;
+    if let Some(error) = block_error.take() {
+        match task.rescue.as_deref() {
+            Some(rescue) if !rescue.is_empty() => {
+                println!("{}", format!("Block failed: {}\n", error).red());
+                println!(
+                    "{}",
+                    format!("Running rescue tasks for: {}\n", task_name).yellow()
+                );
+                rescue_error = run(ctx, rescue, &recovery_tags).err();
+                if rescue_error.is_some() {
+                    block_error = Some(error);
+                }
+            }
+            _ => block_error = Some(error),
+        }
+    }
+
+    let always_error = match task.always.as_deref() {
+        Some(always) if !always.is_empty() => {
+            println!(
+                "{}",
+                format!("Running always tasks for: {}\n", task_name).yellow()
+            );
+            run(ctx, always, &recovery_tags).err()
+        }
+        _ => None,
+    };
+    ctx.run_state.set_recording(recording);
+
+    finish_task_sections(
+        ["block", "rescue", "always"],
+        [block_error, rescue_error, always_error],
+    )
+}
+
+fn finish_task_sections(
+    labels: [&str; 3],
+    errors: [Option<Box<dyn std::error::Error>>; 3],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failed: Vec<(&str, Box<dyn std::error::Error>)> = labels
+        .into_iter()
+        .zip(errors)
+        .filter_map(|(label, error)| error.map(|error| (label, error)))
+        .collect();
+
+    if failed.is_empty() {
+        return Ok(());
+    }
+    if failed.len() == 1 {
+        return Err(failed.remove(0).1);
+    }
+
+    let messages: Vec<String> = failed
+        .iter()
+        .map(|(label, error)| format!("{} tasks failed: {}", label, error))
+        .collect();
     Err(std::io::Error::other(messages.join("; ")).into())
 }
 
@@ -483,10 +1143,13 @@ fn process_deployment_task_sections(
             dep.r#become,
             dep.become_method.as_deref(),
             section_tags,
+            &[],
         )
     };
 
+    ctx.run_state.set_recording(true);
     let main_error = run(ctx, &dep.tasks, ancestor_tags).err();
+    ctx.run_state.set_recording(false);
 
     let on_failure_error = if main_error.is_some() && !dep.on_failure.is_empty() {
         println!("{}", "Running on_failure tasks:\n".yellow());
@@ -502,7 +1165,10 @@ fn process_deployment_task_sections(
         None
     };
 
-    finish_task_sections(main_error, on_failure_error, always_error)
+    finish_task_sections(
+        ["main", "on_failure", "always"],
+        [main_error, on_failure_error, always_error],
+    )
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -561,6 +1227,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Skip tasks until one whose name matches exactly; then run from there")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .help("Continue after the last task completed by the previous failed run")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("start_at_task"),
+        )
         .arg(
             Arg::new("step")
                 .long("step")
@@ -613,6 +1286,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     let step_enabled = matches.get_flag("step");
+    let resume_enabled = matches.get_flag("resume");
     let list_tasks_enabled = matches.get_flag("list_tasks");
 
     if !Path::new(server_file).exists() {
@@ -682,8 +1356,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut vars_map = extra_vars_map.clone();
     let mut filter_state = filter::GateState::new(&filter_config);
     let mut step_state = modules::step::StepState::new(step_enabled);
+    let run_state_file = modules::run_state::state_file_path(deploy_file);
+    let mut run_state = if resume_enabled {
+        modules::run_state::RunState::resume(run_state_file)?
+    } else {
+        modules::run_state::RunState::new(run_state_file)
+    };
+    let deployments_len = deployments.len();
 
-    for dep in deployments {
+    for (dep_index, dep) in deployments.into_iter().enumerate() {
         step_state.reset_for_deployment();
 
         modules::vars_file::load_all(
@@ -695,6 +1376,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         apply_deployment_vars(dep.vars.as_ref(), &mut vars_map, &extra_vars_map)?;
 
         let dep_name = utils::replace_placeholders(&dep.name, &vars_map);
+        if run_state.skips_deployment(dep_index) {
+            println!(
+                "{}",
+                format!(
+                    "Skipping deployment: {} (completed before resume)\n",
+                    dep_name
+                )
+                .yellow()
+            );
+            continue;
+        }
         if let Some(chdir) = &dep.chdir {
             let resolved = utils::replace_placeholders(chdir, &vars_map);
             println!("{}", format!("Starting deployment: {}", dep_name).green());
@@ -710,6 +1402,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let hosts_len = hosts.len();
 
         for host in hosts {
+            if run_state.skips_host(dep_index, host) {
+                continue;
+            }
             if hosts_len > 1 {
                 println!("{}", format!("Processing host: {}\n", host).blue());
             }
@@ -739,7 +1434,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     None
                 };
 
+                run_state.start_host(dep_index, host, &mut vars_map)?;
+
                 let mut ctx = RunContext {
+                    host,
                     is_localhost,
                     session: session.as_ref(),
                     vars_map: &mut vars_map,
@@ -748,6 +1446,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     filter_config: &filter_config,
                     filter_state: &mut filter_state,
                     step_state: &mut step_state,
+                    run_state: &mut run_state,
                 };
                 process_deployment_task_sections(&mut ctx, &dep, &dep_ancestor_tags)?;
             } else {
@@ -757,7 +1456,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 );
             }
         }
+
+        run_state.check_resume_reached(dep_index + 1)?;
     }
 
+    run_state.check_resume_reached(deployments_len)?;
+    run_state.finish()?;
+
     Ok(())
 }
@@ -0,0 +1,717 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+use std::fs;
+use std::path::Path;
+
+use crate::common::{ArchiveSpec, Register, UnarchiveSpec};
+use crate::utils;
+
+const SUPPORTED_FORMATS: &str = ".tar, .tar.gz, .tgz, .tar.bz2, .tbz2, .tar.xz, .txz or .zip";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Tar,
+    TarGz,
+    TarBz2,
+    TarXz,
+    Zip,
+}
+
+impl Format {
+    fn from_path(path: &str) -> Option<Format> {
+        let name = path.to_ascii_lowercase();
+        [
+            (".tar.gz", Format::TarGz),
+            (".tgz", Format::TarGz),
+            (".tar.bz2", Format::TarBz2),
+            (".tbz2", Format::TarBz2),
+            (".tar.xz", Format::TarXz),
+            (".txz", Format::TarXz),
+            (".tar", Format::Tar),
+            (".zip", Format::Zip),
+        ]
+        .into_iter()
+        .find(|(extension, _)| name.ends_with(extension))
+        .map(|(_, format)| format)
+    }
+
+    // The tar compression flag; `None` for zip.
+    fn tar_flag(self) -> Option<&'static str> {
+        match self {
+            Format::Tar => Some(""),
+            Format::TarGz => Some("z"),
+            Format::TarBz2 => Some("j"),
+            Format::TarXz => Some("J"),
+            Format::Zip => None,
+        }
+    }
+}
+
+// `unarchive:` extracts a tarball or zip into `dest` on the target. A local
+// `src` is uploaded to a temp file first; `remote_src: true` extracts a file
+// already on the target. The archive is listed before anything is extracted,
+// and an entry that would land outside `dest` fails the task.
+#[allow(clippy::too_many_arguments)]
+pub fn process_unarchive(
+    task_name: &str,
+    spec: &UnarchiveSpec,
+    deploy_file_dir: &Path,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rendered = |value: &str| utils::replace_placeholders(value, vars_map);
+    let src = rendered(&spec.src);
+    let dest = rendered(&spec.dest);
+    let mode = spec.mode.as_deref().map(rendered);
+    let owner = spec.owner.as_deref().map(rendered);
+    let group = spec.group.as_deref().map(rendered);
+    if let Some(m) = &mode {
+        utils::validate_mode(m).map_err(|e| format!("Task '{}': {}", task_name, e))?;
+    }
+    let format = Format::from_path(&src).ok_or_else(|| {
+        format!(
+            "Task '{}': unarchive cannot tell the format of '{}' (expected {})",
+            task_name, src, SUPPORTED_FORMATS
+        )
+    })?;
+    let strip = spec.strip_components.unwrap_or(0);
+    if strip > 0 && format == Format::Zip {
+        return Err(format!(
+            "Task '{}': unarchive strip_components is not supported for zip archives",
+            task_name
+        )
+        .into());
+    }
+
+    if spec.remote_src {
+        return extract(
+            task_name,
+            format,
+            &src,
+            &src,
+            &dest,
+            strip,
+            mode.as_deref(),
+            chown_spec(owner.as_deref(), group.as_deref()).as_deref(),
+            is_localhost,
+            session,
+            vars_map,
+            become_enabled,
+            become_method,
+            become_password,
+            register,
+        );
+    }
+
+    let resolved_src = utils::resolve_src_path(deploy_file_dir, &src);
+    let bytes = fs::read(&resolved_src).map_err(|_| {
+        format!(
+            "Task '{}': unarchive source not found: {}",
+            task_name,
+            resolved_src.to_string_lossy().replace('\\', "/")
+        )
+    })?;
+    let temp_path =
+        utils::make_temp_file_on_target("deploy-helper-unarchive", is_localhost, session)
+            .map_err(|e| format!("Task '{}': {}", task_name, e))?;
+    let result = utils::write_to_target(
+        &bytes,
+        &temp_path,
+        is_localhost,
+        session,
+        false,
+        "",
+        None,
+        None,
+    )
+    .and_then(|()| {
+        extract(
+            task_name,
+            format,
+            &src,
+            &temp_path,
+            &dest,
+            strip,
+            mode.as_deref(),
+            chown_spec(owner.as_deref(), group.as_deref()).as_deref(),
+            is_localhost,
+            session,
+            vars_map,
+            become_enabled,
+            become_method,
+            become_password,
+            register,
+        )
+    });
+    utils::remove_temp_file_on_target(&temp_path, is_localhost, session);
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+fn extract(
+    task_name: &str,
+    format: Format,
+    src: &str,
+    archive: &str,
+    dest: &str,
+    strip: u32,
+    mode: Option<&str>,
+    chown: Option<&str>,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let archive_arg = utils::shell_escape(archive);
+    let list = |command: &str| -> Result<String, Box<dyn std::error::Error>> {
+        let (stdout, stderr, rc) = utils::run_shell_on_target(
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?;
+        if rc != 0 {
+            return Err(format!(
+                "Task '{}': failed to list {}: exit {}: {}",
+                task_name,
+                src,
+                rc,
+                format!("{} {}", stdout.trim(), stderr.trim()).trim()
+            )
+            .into());
+        }
+        Ok(stdout)
+    };
+    let members = match format.tar_flag() {
+        Some(flag) => tar_members(&list(&format!("tar -tv{}f {}", flag, archive_arg))?),
+        None => {
+            let mut members = zip_members(
+                &list(&format!("unzip -Z1 {}", archive_arg))?,
+                &list(&format!("unzip -Z {}", archive_arg))?,
+            );
+            // zipinfo does not show where a symlink points; the target is the
+            // member's content.
+            let reads: Vec<String> = members
+                .iter()
+                .filter(|member| matches!(member.link, Some(Link::Symlink(_))))
+                .map(|member| {
+                    format!(
+                        "unzip -p {} {}; echo",
+                        archive_arg,
+                        utils::shell_escape(&member.path)
+                    )
+                })
+                .collect();
+            if !reads.is_empty() {
+                let targets = list(&reads.join("; "))?;
+                let mut targets = targets.lines();
+                for member in &mut members {
+                    if let Some(Link::Symlink(target)) = &mut member.link {
+                        *target = targets.next().unwrap_or_default().to_string();
+                    }
+                }
+            }
+            members
+        }
+    };
+    let entries =
+        top_level_entries(&members, strip).map_err(|e| format!("Task '{}': {}", task_name, e))?;
+
+    let dest_arg = utils::shell_escape(dest);
+    let mut command = format!("mkdir -p {}", dest_arg);
+    if let Some(m) = mode {
+        command.push_str(&format!(" && chmod {} {}", m, dest_arg));
+    }
+    match format.tar_flag() {
+        Some(flag) => {
+            command.push_str(&format!(
+                " && tar -x{}f {} -C {}",
+                flag, archive_arg, dest_arg
+            ));
+            if strip > 0 {
+                command.push_str(&format!(" --strip-components={}", strip));
+            }
+        }
+        None => command.push_str(&format!(" && unzip -q -o {} -d {}", archive_arg, dest_arg)),
+    }
+    if let Some(chown) = chown {
+        command.push_str(&format!(" && chown {} {}", chown, dest_arg));
+        if !entries.is_empty() {
+            let paths: Vec<String> = entries
+                .iter()
+                .map(|entry| utils::shell_escape(&format!("{}/{}", dest, entry)))
+                .collect();
+            command.push_str(&format!(" && chown -R {} {}", chown, paths.join(" ")));
+        }
+    }
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &command,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    if rc != 0 {
+        return Err(format!(
+            "Task '{}': failed to extract {} into {}: exit {}: {}",
+            task_name,
+            src,
+            dest,
+            rc,
+            format!("{} {}", stdout.trim(), stderr.trim()).trim()
+        )
+        .into());
+    }
+
+    println!(
+        "{}",
+        format!(
+            "> [unarchive] {} -> {} ({} top-level entr{})",
+            src,
+            dest,
+            entries.len(),
+            if entries.len() == 1 { "y" } else { "ies" }
+        )
+        .magenta()
+    );
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed: true,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("dest".to_string(), Value::from(dest));
+            fields.insert("entries".to_string(), Value::from(entries));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+// The `owner[:group]` argument for chown, already shell-escaped.
+fn chown_spec(owner: Option<&str>, group: Option<&str>) -> Option<String> {
+    match (owner, group) {
+        (Some(o), Some(g)) => Some(utils::shell_escape(&format!("{}:{}", o, g))),
+        (Some(o), None) => Some(utils::shell_escape(o)),
+        (None, Some(g)) => Some(utils::shell_escape(&format!(":{}", g))),
+        (None, None) => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Link {
+    // Resolved against the directory holding the link.
+    Symlink(String),
+    // Names another member, so it is resolved against `dest`.
+    Hardlink(String),
+}
+
+// One archive member, and what it points at when it is a link.
+#[derive(Debug, Clone, PartialEq)]
+struct Member {
+    path: String,
+    link: Option<Link>,
+}
+
+// What is left of a listing line after its first `count` whitespace-separated
+// fields.
+fn after_fields(line: &str, count: usize) -> Option<&str> {
+    let mut rest = line.trim_start();
+    for _ in 0..count {
+        let end = rest.find(char::is_whitespace)?;
+        rest = rest[end..].trim_start();
+    }
+    (!rest.is_empty()).then_some(rest)
+}
+
+// Members from `tar -tv`, where the name follows mode, owner, size, date and
+// time. Symlinks read `name -> target`, hardlinks `name link to target`.
+fn tar_members(listing: &str) -> Vec<Member> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_end_matches('\r');
+            let rest = after_fields(line, 5)?;
+            let member = match line.chars().next() {
+                Some('l') => rest.split_once(" -> ").map(|(path, target)| Member {
+                    path: path.to_string(),
+                    link: Some(Link::Symlink(target.to_string())),
+                }),
+                Some('h') => rest.split_once(" link to ").map(|(path, target)| Member {
+                    path: path.to_string(),
+                    link: Some(Link::Hardlink(target.to_string())),
+                }),
+                _ => None,
+            };
+            Some(member.unwrap_or_else(|| Member {
+                path: rest.to_string(),
+                link: None,
+            }))
+        })
+        .collect()
+}
+
+// Members from `unzip -Z1` (one name per line), marked as symlinks where the
+// `unzip -Z` line for that name has an `l` mode. Zip has no hardlinks, and
+// the symlink targets are filled in by the caller.
+fn zip_members(names: &str, details: &str) -> Vec<Member> {
+    let symlinks: Vec<&str> = details
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| line.starts_with('l'))
+        .filter_map(|line| after_fields(line, 8))
+        .collect();
+    names
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|name| !name.is_empty())
+        .map(|name| Member {
+            path: name.to_string(),
+            link: symlinks
+                .contains(&name)
+                .then(|| Link::Symlink(String::new())),
+        })
+        .collect()
+}
+
+// The components of `path` after `strip` leading ones, the way `tar
+// --strip-components` drops them. `None` if the path is absolute or climbs
+// out with `..`.
+fn stripped_components(path: &str, strip: u32) -> Option<Vec<&str>> {
+    let parts: Vec<&str> = path
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    if path.starts_with('/') || parts.contains(&"..") {
+        return None;
+    }
+    Some(parts.into_iter().skip(strip as usize).collect())
+}
+
+// Whether `target`, followed from the directory `base` (both relative to
+// `dest`), stays inside `dest`. Every link is checked on its own, so going
+// through another link keeps inside `dest` too, unless `..` then climbs out
+// of it; stepping back out of one of the `symlinks` is refused for that reason.
+fn stays_inside(base: &[&str], target: &str, symlinks: &[Vec<&str>]) -> bool {
+    if target.starts_with('/') {
+        return false;
+    }
+    let mut path: Vec<&str> = base.to_vec();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if path.is_empty() || symlinks.contains(&path) => return false,
+            ".." => {
+                path.pop();
+            }
+            _ => path.push(part),
+        }
+    }
+    true
+}
+
+// The distinct first path components of an archive's members, after dropping
+// `strip` leading components the way `tar --strip-components` does. An
+// absolute entry, one with a `..` component, or a link that points outside
+// `dest` is refused before extraction.
+fn top_level_entries(members: &[Member], strip: u32) -> Result<Vec<String>, String> {
+    let symlinks: Vec<Vec<&str>> = members
+        .iter()
+        .filter(|member| matches!(member.link, Some(Link::Symlink(_))))
+        .filter_map(|member| stripped_components(&member.path, strip))
+        .collect();
+    let mut entries: Vec<String> = Vec::new();
+    for member in members {
+        let Some(parts) = stripped_components(&member.path, strip) else {
+            return Err(format!(
+                "archive entry '{}' would be extracted outside dest",
+                member.path
+            ));
+        };
+        let escaping_target = match &member.link {
+            Some(Link::Symlink(target))
+                if !parts.is_empty()
+                    && !stays_inside(&parts[..parts.len() - 1], target, &symlinks) =>
+            {
+                Some(target)
+            }
+            // tar strips hardlink targets along with the names.
+            Some(Link::Hardlink(target)) if stripped_components(target, strip).is_none() => {
+                Some(target)
+            }
+            _ => None,
+        };
+        if let Some(target) = escaping_target {
+            return Err(format!(
+                "archive entry '{}' links to '{}', outside dest",
+                member.path, target
+            ));
+        }
+        if let Some(top) = parts.first() {
+            if !entries.iter().any(|existing| existing == top) {
+                entries.push(top.to_string());
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// `archive:` packs `path` on the target into the tarball `dest`, for example
+// a backup of the current release before an upgrade. The tarball is written
+// next to `dest` and renamed into place, so a failed run never leaves a
+// truncated archive behind.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &ArchiveSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = utils::replace_placeholders(&spec.path, vars_map);
+    let dest = utils::replace_placeholders(&spec.dest, vars_map);
+    let mode = spec
+        .mode
+        .as_deref()
+        .map(|m| utils::replace_placeholders(m, vars_map));
+    if let Some(m) = &mode {
+        utils::validate_mode(m).map_err(|e| format!("Task '{}': {}", task_name, e))?;
+    }
+    let Some(flag) = Format::from_path(&dest).and_then(Format::tar_flag) else {
+        return Err(format!(
+            "Task '{}': archive dest must end in .tar, .tar.gz, .tgz, .tar.bz2, .tbz2, .tar.xz or .txz (got '{}')",
+            task_name, dest
+        )
+        .into());
+    };
+
+    let excludes: String = spec
+        .exclude
+        .iter()
+        .map(|pattern| {
+            format!(
+                " --exclude={}",
+                utils::shell_escape(&utils::replace_placeholders(pattern, vars_map))
+            )
+        })
+        .collect();
+    let chmod = mode
+        .map(|m| format!("chmod {} \"$tmp\" && ", m))
+        .unwrap_or_default();
+    let command = format!(
+        r#"p={}; d={}
+if [ ! -e "$p" ]; then echo missing; exit 0; fi
+tmp="$d.tmp.$$"
+if tar -c{}f "$tmp"{} -C "$(dirname "$p")" "$(basename "$p")" && {}mv -f "$tmp" "$d"; then
+  wc -c < "$d"
+else
+  rm -f "$tmp"; exit 1
+fi"#,
+        utils::shell_escape(&path),
+        utils::shell_escape(&dest),
+        flag,
+        excludes,
+        chmod
+    );
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &command,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    let size = match (rc, stdout.trim()) {
+        (0, "missing") => {
+            return Err(format!("Task '{}': archive path not found: {}", task_name, path).into());
+        }
+        (0, output) => output.parse::<u64>().ok(),
+        (rc, _) => {
+            return Err(format!(
+                "Task '{}': failed to archive {} into {}: exit {}: {}",
+                task_name,
+                path,
+                dest,
+                rc,
+                format!("{} {}", stdout.trim(), stderr.trim()).trim()
+            )
+            .into());
+        }
+    };
+
+    let size_note = size
+        .map(|size| format!(" ({} bytes)", size))
+        .unwrap_or_default();
+    println!(
+        "{}",
+        format!("> [archive] {} -> {}{}", path, dest, size_note).magenta()
+    );
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed: true,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("dest".to_string(), Value::from(dest));
+            fields.insert("size".to_string(), size.map_or(Value::Null, Value::from));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_comes_from_the_extension() {
+        assert_eq!(Format::from_path("app-1.2.tar.gz"), Some(Format::TarGz));
+        assert_eq!(Format::from_path("APP.TGZ"), Some(Format::TarGz));
+        assert_eq!(Format::from_path("app.tar.xz"), Some(Format::TarXz));
+        assert_eq!(Format::from_path("app.zip"), Some(Format::Zip));
+        assert_eq!(Format::from_path("app.gz"), None);
+    }
+
+    // `tar -tv` lines for the given names, as plain files.
+    fn files(names: &[&str]) -> Vec<Member> {
+        let listing: String = names
+            .iter()
+            .map(|name| format!("-rw-r--r-- root/root 3 2024-05-01 12:00 {}\n", name))
+            .collect();
+        tar_members(&listing)
+    }
+
+    #[test]
+    fn top_level_entries_follow_strip_components() {
+        let members = files(&[
+            "./",
+            "./app-1.2/",
+            "./app-1.2/bin/app",
+            "./app-1.2/README",
+            "VERSION",
+        ]);
+        assert_eq!(
+            top_level_entries(&members, 0).unwrap(),
+            vec!["app-1.2", "VERSION"]
+        );
+        assert_eq!(
+            top_level_entries(&members, 1).unwrap(),
+            vec!["bin", "README"]
+        );
+    }
+
+    #[test]
+    fn top_level_entries_refuse_path_traversal() {
+        for name in ["app/../../etc/cron.d/x", "/etc/passwd", "../x"] {
+            let err = top_level_entries(&files(&[name]), 0).unwrap_err();
+            assert!(err.contains("outside dest"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn tar_listing_keeps_link_targets() {
+        let listing = "\
+drwxr-xr-x root/root         0 2024-05-01 12:00 app/
+lrwxrwxrwx root/root         0 2024-05-01 12:00 app/config -> ../shared/config
+hrw-r--r-- root/root         0 2024-05-01 12:00 app/bin/app-1.2 link to app/bin/app
+-rw-r--r-- root/root       120 2024-05-01 12:00 app/my notes.txt
+";
+        assert_eq!(
+            tar_members(listing),
+            vec![
+                Member {
+                    path: "app/".to_string(),
+                    link: None,
+                },
+                Member {
+                    path: "app/config".to_string(),
+                    link: Some(Link::Symlink("../shared/config".to_string())),
+                },
+                Member {
+                    path: "app/bin/app-1.2".to_string(),
+                    link: Some(Link::Hardlink("app/bin/app".to_string())),
+                },
+                Member {
+                    path: "app/my notes.txt".to_string(),
+                    link: None,
+                },
+            ]
+        );
+        assert_eq!(
+            top_level_entries(&tar_members(listing), 0).unwrap(),
+            vec!["app"]
+        );
+    }
+
+    #[test]
+    fn top_level_entries_refuse_links_out_of_dest() {
+        let escapes = [
+            "lrwxrwxrwx root/root 0 2024-05-01 12:00 app/etc -> /etc\n",
+            "lrwxrwxrwx root/root 0 2024-05-01 12:00 app/up -> ../../root\n",
+            "hrw-r--r-- root/root 0 2024-05-01 12:00 app/shadow link to ../etc/shadow\n",
+            // Each link stays inside on its own, but `up` steps back out of `here`.
+            "lrwxrwxrwx root/root 0 2024-05-01 12:00 here -> .\n\
+             lrwxrwxrwx root/root 0 2024-05-01 12:00 up -> here/..\n",
+        ];
+        for listing in escapes {
+            let err = top_level_entries(&tar_members(listing), 0).unwrap_err();
+            assert!(err.contains("outside dest"), "{}", err);
+        }
+
+        // Fine as listed, but one level shallower once the top is stripped.
+        let listing = "lrwxrwxrwx root/root 0 2024-05-01 12:00 app/current -> ../shared\n";
+        assert!(top_level_entries(&tar_members(listing), 0).is_ok());
+        assert!(top_level_entries(&tar_members(listing), 1).is_err());
+    }
+
+    #[test]
+    fn zip_symlinks_come_from_the_detailed_listing() {
+        let names = "app/\napp/etc\napp/README\n";
+        let details = "\
+Archive:  app.zip
+Zip file size: 716 bytes, number of entries: 3
+drwxr-xr-x  3.0 unx        0 bx stor 24-May-01 12:00 app/
+lrwxrwxrwx  3.0 unx        4 bx stor 24-May-01 12:00 app/etc
+-rw-r--r--  3.0 unx        3 tx stor 24-May-01 12:00 app/README
+3 files, 7 bytes uncompressed, 7 bytes compressed:  0.0%
+";
+        let mut members = zip_members(names, details);
+        assert_eq!(
+            members.iter().map(|m| m.link.is_some()).collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+        members[1].link = Some(Link::Symlink("/etc".to_string()));
+        let err = top_level_entries(&members, 0).unwrap_err();
+        assert!(err.contains("'app/etc' links to '/etc'"), "{}", err);
+    }
+}
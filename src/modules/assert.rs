@@ -0,0 +1,172 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+
+use crate::common::{AssertSpec, FailSpec, Register};
+use crate::modules::when;
+use crate::utils;
+
+const DEFAULT_FAIL_MSG: &str = "Failed as requested";
+
+// `assert:` evaluates each `that:` expression like `when:` and fails the task
+// on the first one that is false. `fail_msg` replaces the default message,
+// which names the failing expression.
+pub fn process(
+    task_name: &str,
+    spec: &AssertSpec,
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if spec.that.is_empty() {
+        return Err(format!(
+            "Task '{}': assert requires at least one that: condition",
+            task_name
+        )
+        .into());
+    }
+
+    for condition in &spec.that {
+        let passed = when::process(&Some(condition.clone()), vars_map, no_log)
+            .map_err(|error| utils::task_error(task_name, error))?;
+        if passed {
+            continue;
+        }
+        let msg = if no_log {
+            "assertion failed (details hidden by no_log)".to_string()
+        } else {
+            match &spec.fail_msg {
+                Some(fail_msg) => utils::replace_placeholders(fail_msg, vars_map),
+                None => format!("assertion failed: {}", condition),
+            }
+        };
+        register_msg(vars_map, register, &msg, true)?;
+        return Err(format!("Task '{}': {}", task_name, msg).into());
+    }
+
+    let msg = match &spec.success_msg {
+        Some(success_msg) if !no_log => utils::replace_placeholders(success_msg, vars_map),
+        _ => "All assertions passed".to_string(),
+    };
+    println!(
+        "{}",
+        format!(
+            "> [assert] {} condition{} passed",
+            spec.that.len(),
+            if spec.that.len() == 1 { "" } else { "s" }
+        )
+        .magenta()
+    );
+    if spec.success_msg.is_some() && !no_log {
+        println!("{}", msg.green());
+    }
+    register_msg(vars_map, register, &msg, false)
+}
+
+// `fail:` stops the run with `msg`, usually under a `when:`.
+pub fn process_fail(
+    task_name: &str,
+    spec: &FailSpec,
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let msg = if no_log {
+        "failed (details hidden by no_log)".to_string()
+    } else {
+        spec.msg
+            .as_deref()
+            .map(|msg| utils::replace_placeholders(msg, vars_map))
+            .unwrap_or_else(|| DEFAULT_FAIL_MSG.to_string())
+    };
+    register_msg(vars_map, register, &msg, true)?;
+    Err(format!("Task '{}': {}", task_name, msg).into())
+}
+
+// Registers the outcome with the message as `msg`, so a task with
+// `ignore_errors:` can report it later.
+fn register_msg(
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    msg: &str,
+    failed: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(reg) = register else {
+        return Ok(());
+    };
+    let mut value = serde_json::to_value(Register {
+        stdout: String::new(),
+        stderr: String::new(),
+        rc: if failed { 1 } else { 0 },
+        changed: false,
+        failed,
+    })?;
+    if let Value::Object(fields) = &mut value {
+        fields.insert("msg".to_string(), Value::from(msg));
+    }
+    vars_map.insert(reg.clone(), value);
+    println!("{}", format!("Registering output to: {}", reg).yellow());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spec(yaml: &str) -> AssertSpec {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn assert_reports_the_first_false_condition() {
+        let mut vars = IndexMap::new();
+        vars.insert("branch".to_string(), json!("feature"));
+        vars.insert("free_mb".to_string(), json!(900));
+
+        let err = process(
+            "Preflight",
+            &spec("that:\n  - free_mb > 500\n  - branch == 'main'\n"),
+            &mut vars,
+            None,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Task 'Preflight': assertion failed: branch == 'main'"
+        );
+
+        let register = "check".to_string();
+        let err = process(
+            "Preflight",
+            &spec("that: [\"branch == 'main'\"]\nfail_msg: \"on {{ branch }}, expected main\"\n"),
+            &mut vars,
+            Some(&register),
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Task 'Preflight': on feature, expected main"
+        );
+        assert_eq!(vars["check"]["msg"], json!("on feature, expected main"));
+        assert_eq!(vars["check"]["failed"], json!(true));
+    }
+
+    #[test]
+    fn assert_hides_conditions_under_no_log() {
+        let mut vars = IndexMap::new();
+        vars.insert("token".to_string(), json!("secret"));
+        let err = process(
+            "T",
+            &spec("that: [\"token == 'other'\"]\nfail_msg: \"token is {{ token }}\"\n"),
+            &mut vars,
+            None,
+            true,
+        )
+        .unwrap_err();
+        assert!(!err.to_string().contains("secret"), "{}", err);
+        assert!(process("T", &spec("that: []\n"), &mut vars, None, false).is_err());
+    }
+}
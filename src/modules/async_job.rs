@@ -0,0 +1,530 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::{AsyncStatusSpec, Register};
+use crate::modules::command::ExitStatusError;
+use crate::utils;
+
+// Each job gets a private directory from `mktemp -d` on the target, and its
+// path is the job id. The launch writes the job's `limit` there; the job
+// writes its output and `rc` last, so a present `rc` means the job has
+// finished.
+const JOB_DIR_PREFIX: &str = "deploy-helper-async.";
+
+// The line the launch script prints the job directory on, so output from a
+// login shell's profile cannot be mistaken for it.
+const JOB_DIR_LINE: &str = "deploy-helper-async-dir=";
+
+// How long past its `async:` limit a job may still report as running before
+// polling gives up. The target-side watchdog normally ends it well before.
+const POLL_GRACE_SECONDS: u64 = 30;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Job {
+    pub limit: u64,
+    pub poll: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum JobStatus {
+    Running,
+    Missing,
+    Finished {
+        rc: i32,
+        limit: Option<u64>,
+        stdout: String,
+        stderr: String,
+    },
+}
+
+// A job id is the absolute path of a directory made by `launch_script`.
+fn is_job_dir(job_id: &str) -> bool {
+    job_id.starts_with('/')
+        && job_id
+            .rsplit('/')
+            .next()
+            .is_some_and(|name| name.starts_with(JOB_DIR_PREFIX))
+        && !job_id.split('/').any(|segment| segment == "..")
+        && job_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-'))
+}
+
+fn launch_script(script: &str, limit: u64, shell: utils::TimeoutShell) -> String {
+    let job = format!(
+        "{} >\"$1/stdout\" 2>\"$1/stderr\"; echo $? >\"$1/rc.tmp\"; mv \"$1/rc.tmp\" \"$1/rc\"",
+        utils::with_timeout(script, limit, shell)
+    );
+    format!(
+        "umask 077\nd=$(mktemp -d \"${{TMPDIR:-/tmp}}/{prefix}XXXXXXXX\") || exit 1\necho {limit} >\"$d/limit\"\nsetsid sh -c {job} deploy-helper-async \"$d\" </dev/null >/dev/null 2>&1 &\necho \"{line}$d\"",
+        prefix = JOB_DIR_PREFIX,
+        limit = limit,
+        job = utils::shell_escape(&job),
+        line = JOB_DIR_LINE
+    )
+}
+
+fn launched_job_dir(output: &str) -> Option<&str> {
+    output
+        .lines()
+        .rev()
+        .find_map(|line| line.trim().strip_prefix(JOB_DIR_LINE))
+        .filter(|dir| is_job_dir(dir))
+}
+
+// The directory must still be the one `mktemp -d` made for this user: a
+// path someone else created, or replaced with a link, is not read.
+fn status_script(job_dir: &str) -> String {
+    format!(
+        "d={}\nif [ ! -e \"$d\" ] && [ ! -L \"$d\" ]; then echo missing\nelif [ -L \"$d\" ] || [ ! -d \"$d\" ] || [ ! -O \"$d\" ]; then echo foreign\nelif [ -f \"$d/rc\" ]; then cat \"$d/rc\" \"$d/limit\" 2>/dev/null || :\nelse echo running; fi",
+        utils::shell_escape(job_dir)
+    )
+}
+
+fn collect_script(job_dir: &str) -> String {
+    format!(
+        "d={}\ncat \"$d/stdout\"; cat \"$d/stderr\" >&2; rm -rf \"$d\"",
+        utils::shell_escape(job_dir)
+    )
+}
+
+// A finished job reports its exit code and, on the next line, its limit.
+fn parse_status(output: &str) -> Result<Option<(i32, Option<u64>)>, String> {
+    let mut lines = output.lines().map(str::trim);
+    match lines.next().unwrap_or_default() {
+        "running" => Ok(None),
+        value => {
+            let rc = value
+                .parse()
+                .map_err(|_| format!("unexpected async job status: {}", value))?;
+            Ok(Some((
+                rc,
+                lines.next().and_then(|limit| limit.parse().ok()),
+            )))
+        }
+    }
+}
+
+// Reads a job's state. A finished job's output is collected and its directory
+// removed, so each job reports `Finished` once.
+fn status(
+    job_id: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<JobStatus, Box<dyn std::error::Error>> {
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &status_script(job_id),
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    if rc != 0 {
+        return Err(format!("failed to read async job {}: {}", job_id, stderr.trim()).into());
+    }
+    match stdout.trim() {
+        "missing" => return Ok(JobStatus::Missing),
+        "foreign" => {
+            return Err(format!(
+                "async job directory {} is not a directory owned by the current user",
+                job_id
+            )
+            .into())
+        }
+        _ => {}
+    }
+    let Some((job_rc, limit)) = parse_status(&stdout)? else {
+        return Ok(JobStatus::Running);
+    };
+
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &collect_script(job_id),
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    if rc != 0 {
+        return Err(format!("failed to collect async job {}: {}", job_id, stderr.trim()).into());
+    }
+    Ok(JobStatus::Finished {
+        rc: job_rc,
+        limit,
+        stdout,
+        stderr,
+    })
+}
+
+fn register_job(
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    job_id: &str,
+    finished: bool,
+    result: Register,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(register) = register else {
+        return Ok(());
+    };
+    let mut value = serde_json::to_value(result)?;
+    if let Value::Object(fields) = &mut value {
+        fields.insert("job_id".to_string(), Value::from(job_id));
+        fields.insert("finished".to_string(), Value::Bool(finished));
+    }
+    vars_map.insert(register.clone(), value);
+    println!(
+        "{}",
+        format!("Registering output to: {}", register).yellow()
+    );
+    Ok(())
+}
+
+// Registers a finished job like a foreground command and fails the same way:
+// a non-zero exit is an `ExitStatusError` so `failed_when:` still applies. A
+// job the watchdog killed reports its limit instead.
+#[allow(clippy::too_many_arguments)]
+fn finish(
+    job_id: &str,
+    limit: Option<u64>,
+    rc: i32,
+    stdout: String,
+    mut stderr: String,
+    register: Option<&String>,
+    vars_map: &mut IndexMap<String, Value>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let killed = rc == 124 && utils::take_timeout_marker(&mut stderr);
+    if register.is_none() && !no_log {
+        if !stdout.is_empty() {
+            println!("{}", stdout.white());
+        }
+        if !stderr.is_empty() {
+            println!("{}", stderr.red());
+        }
+    }
+    register_job(
+        vars_map,
+        register,
+        job_id,
+        true,
+        Register {
+            stdout,
+            stderr,
+            rc,
+            changed: true,
+            failed: rc != 0,
+        },
+    )?;
+
+    match (rc, limit) {
+        (0, _) => Ok(()),
+        (124, Some(limit)) if killed => Err(format!(
+            "Async job {} timed out after {} second{} and its process group was killed. Stopping further tasks.",
+            job_id,
+            limit,
+            if limit == 1 { "" } else { "s" }
+        )
+        .red()
+        .into()),
+        (rc, _) => Err(Box::new(ExitStatusError(rc))),
+    }
+}
+
+// Starts `script` as a detached job on the target, limited to `job.limit`
+// seconds. With `poll: 0` the job id is registered and the task moves on;
+// otherwise the job is checked every `poll` seconds until it finishes. No
+// connection is held open while the job runs.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    script: &str,
+    job: Job,
+    is_localhost: bool,
+    session: Option<&Session>,
+    chdir: Option<&str>,
+    register: Option<&String>,
+    login_shell: bool,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (stdout, stderr, rc) = utils::run_shell_on_target_with_context(
+        &launch_script(
+            script,
+            job.limit,
+            utils::TimeoutShell::for_task(login_shell, become_enabled, false),
+        ),
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        chdir,
+        login_shell,
+        None,
+    )?;
+    if rc != 0 {
+        return Err(format!(
+            "Failed to start async job (exit status {}): {}",
+            rc,
+            stderr.trim()
+        )
+        .red()
+        .into());
+    }
+    let job_id = launched_job_dir(&stdout)
+        .ok_or_else(|| {
+            format!(
+                "Failed to start async job: no job directory in {:?}",
+                stdout
+            )
+        })?
+        .to_string();
+    println!(
+        "{}",
+        format!("> [async] started job {} (limit {}s)", job_id, job.limit).magenta()
+    );
+
+    if job.poll == 0 {
+        return register_job(
+            vars_map,
+            register,
+            &job_id,
+            false,
+            Register {
+                stdout: String::new(),
+                stderr: String::new(),
+                rc: 0,
+                changed: true,
+                failed: false,
+            },
+        );
+    }
+
+    let started = Instant::now();
+    let give_up = Duration::from_secs(job.limit + job.poll + POLL_GRACE_SECONDS);
+    loop {
+        thread::sleep(Duration::from_secs(job.poll));
+        match status(
+            &job_id,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )? {
+            JobStatus::Running if started.elapsed() > give_up => {
+                return Err(format!(
+                    "Async job {} is still running {}s after its {}s limit",
+                    job_id,
+                    started.elapsed().as_secs() - job.limit,
+                    job.limit
+                )
+                .red()
+                .into());
+            }
+            JobStatus::Running => {
+                println!(
+                    "{}",
+                    format!(
+                        "(async job {} still running after {}s)",
+                        job_id,
+                        started.elapsed().as_secs()
+                    )
+                    .bright_black()
+                );
+            }
+            JobStatus::Missing => {
+                return Err(format!("Async job {} disappeared from the target", job_id)
+                    .red()
+                    .into());
+            }
+            JobStatus::Finished {
+                rc, stdout, stderr, ..
+            } => {
+                return finish(
+                    &job_id,
+                    Some(job.limit),
+                    rc,
+                    stdout,
+                    stderr,
+                    register,
+                    vars_map,
+                    no_log,
+                );
+            }
+        }
+    }
+}
+
+// `async_status:` checks a job started with `poll: 0` once. A running job
+// registers `finished: false`; pair the task with `until:` to wait for it.
+#[allow(clippy::too_many_arguments)]
+pub fn process_status(
+    task_name: &str,
+    spec: &AsyncStatusSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let job_id = utils::replace_placeholders(&spec.job_id, vars_map);
+    if !is_job_dir(&job_id) {
+        return Err(format!(
+            "Task '{}': async_status job_id must be a job id registered by an async task, got '{}'",
+            task_name, job_id
+        )
+        .into());
+    }
+
+    match status(
+        &job_id,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )? {
+        JobStatus::Running => {
+            println!(
+                "{}",
+                format!("> [async_status] job {} is running", job_id).magenta()
+            );
+            register_job(
+                vars_map,
+                register,
+                &job_id,
+                false,
+                Register {
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    rc: 0,
+                    changed: false,
+                    failed: false,
+                },
+            )
+        }
+        JobStatus::Missing => Err(format!(
+            "Task '{}': async job {} not found (it was never started, or its result was already collected)",
+            task_name, job_id
+        )
+        .into()),
+        JobStatus::Finished {
+            rc,
+            limit,
+            stdout,
+            stderr,
+        } => {
+            println!(
+                "{}",
+                format!("> [async_status] job {} finished (rc {})", job_id, rc).magenta()
+            );
+            finish(&job_id, limit, rc, stdout, stderr, register, vars_map, no_log)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn launch(script: &str) -> String {
+        let (stdout, _, rc) = utils::run_shell_on_target(
+            &launch_script(script, 30, utils::TimeoutShell::Sh),
+            true,
+            None,
+            false,
+            "sudo",
+            None,
+        )
+        .unwrap();
+        assert_eq!(rc, 0);
+        launched_job_dir(&stdout).unwrap().to_string()
+    }
+
+    #[test]
+    fn job_ids_must_be_job_directories() {
+        assert!(is_job_dir("/tmp/deploy-helper-async.Ab12cD34"));
+        assert!(!is_job_dir("deploy-helper-async.Ab12cD34"));
+        assert!(!is_job_dir("/tmp/other"));
+        assert!(!is_job_dir("/tmp/../etc/deploy-helper-async.x"));
+        assert!(!is_job_dir("/tmp/deploy-helper-async.x; rm -rf /"));
+    }
+
+    #[test]
+    fn each_job_gets_a_private_directory() {
+        let first = launch("true");
+        let second = launch("true");
+        assert_ne!(first, second);
+        let mode = std::fs::metadata(&first).unwrap().permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o700
+        );
+        thread::sleep(Duration::from_millis(200));
+        for dir in [first, second] {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    #[test]
+    fn status_refuses_a_directory_it_did_not_make() {
+        let link =
+            std::env::temp_dir().join(format!("{}link-{}", JOB_DIR_PREFIX, std::process::id()));
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(std::env::temp_dir(), &link).unwrap();
+        let err = status(link.to_str().unwrap(), true, None, false, "sudo", None).unwrap_err();
+        std::fs::remove_file(&link).unwrap();
+        assert!(err
+            .to_string()
+            .contains("not a directory owned by the current user"));
+    }
+
+    #[test]
+    fn parse_status_reads_exit_code_or_running() {
+        assert_eq!(parse_status("running\n").unwrap(), None);
+        assert_eq!(parse_status("3").unwrap(), Some((3, None)));
+        assert_eq!(parse_status("124\n30\n").unwrap(), Some((124, Some(30))));
+        assert!(parse_status("garbage").is_err());
+    }
+
+    #[test]
+    fn launched_job_records_output_and_exit_code() {
+        let job_id = launch("echo out; echo err >&2; exit 4");
+
+        let started = Instant::now();
+        let result = loop {
+            let result = status(&job_id, true, None, false, "sudo", None).unwrap();
+            if result != JobStatus::Running || started.elapsed() > Duration::from_secs(10) {
+                break result;
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        assert_eq!(
+            result,
+            JobStatus::Finished {
+                rc: 4,
+                limit: Some(30),
+                stdout: "out".to_string(),
+                stderr: "err".to_string(),
+            }
+        );
+        assert_eq!(
+            status(&job_id, true, None, false, "sudo", None).unwrap(),
+            JobStatus::Missing
+        );
+    }
+}
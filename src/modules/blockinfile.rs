@@ -0,0 +1,134 @@
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{BlockInFileSpec, EditState};
+use crate::modules::lineinfile::{register_changed, EditTarget, InsertPosition};
+use crate::utils;
+
+const DEFAULT_MARKER: &str = "# {mark} DEPLOY-HELPER MANAGED BLOCK";
+
+// `blockinfile:` keeps a block of lines between two marker lines in a text
+// file on the target. The markers let a later run find and replace the block,
+// so everything between them is owned by the task.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &BlockInFileSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let marker = spec
+        .marker
+        .as_deref()
+        .map(|m| utils::replace_placeholders(m, vars_map))
+        .unwrap_or_else(|| DEFAULT_MARKER.to_string());
+    if !marker.contains("{mark}") || marker.contains('\n') {
+        return Err(format!(
+            "Task '{}': blockinfile marker must be one line containing {{mark}} (got '{}')",
+            task_name, marker
+        )
+        .into());
+    }
+    let begin = marker.replace("{mark}", "BEGIN");
+    let end = marker.replace("{mark}", "END");
+
+    // An empty block removes the markers too, like `state: absent`.
+    let block = utils::replace_placeholders(&spec.block, vars_map);
+    let block = block.trim_end_matches('\n');
+    let state = if block.is_empty() {
+        EditState::Absent
+    } else {
+        spec.state
+    };
+    let mut managed = Vec::new();
+    if state == EditState::Present {
+        managed.push(begin.clone());
+        managed.extend(block.split('\n').map(str::to_string));
+        managed.push(end.clone());
+    }
+
+    let position = InsertPosition::resolve(
+        task_name,
+        spec.insertafter.as_deref(),
+        spec.insertbefore.as_deref(),
+        vars_map,
+    )?;
+    let target = EditTarget {
+        task_name,
+        action: "blockinfile",
+        path: utils::replace_placeholders(&spec.path, vars_map),
+        create: spec.create,
+        mode: spec
+            .mode
+            .as_deref()
+            .map(|m| utils::replace_placeholders(m, vars_map)),
+    };
+    let changed = target.apply(
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        state,
+        |lines| {
+            match find_block(lines, &begin, &end)? {
+                Some((start, stop)) => {
+                    lines.splice(start..=stop, managed);
+                }
+                None if !managed.is_empty() => {
+                    let index = position.index(lines);
+                    lines.splice(index..index, managed);
+                }
+                None => {}
+            }
+            Ok(())
+        },
+    )?;
+
+    register_changed(vars_map, register, changed)
+}
+
+// Returns the line indexes of the begin and end markers, if the block exists.
+// A dangling marker is an error rather than a reason to add a second block.
+fn find_block(lines: &[String], begin: &str, end: &str) -> Result<Option<(usize, usize)>, String> {
+    let start = lines.iter().position(|l| l == begin);
+    let stop = lines.iter().position(|l| l == end);
+    match (start, stop) {
+        (None, None) => Ok(None),
+        (Some(start), Some(stop)) if start < stop => Ok(Some((start, stop))),
+        _ => Err(format!(
+            "found '{}' or '{}' without its partner; fix the file by hand",
+            begin, end
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn find_block_locates_both_markers() {
+        let file = lines("a\n# BEGIN X\nb\n# END X\nc");
+        assert_eq!(find_block(&file, "# BEGIN X", "# END X"), Ok(Some((1, 3))));
+        assert_eq!(find_block(&lines("a\nc"), "# BEGIN X", "# END X"), Ok(None));
+    }
+
+    #[test]
+    fn find_block_rejects_a_dangling_marker() {
+        let file = lines("a\n# BEGIN X\nb");
+        assert!(find_block(&file, "# BEGIN X", "# END X").is_err());
+        let file = lines("# END X\n# BEGIN X");
+        assert!(find_block(&file, "# BEGIN X", "# END X").is_err());
+    }
+}
@@ -2,10 +2,70 @@ use colored::Colorize;
 use indexmap::IndexMap;
 use serde_json::Value;
 use ssh2::Session;
+use std::fmt;
 
 use crate::common;
+use crate::modules::async_job;
 use crate::utils;
 
+// Per-task limits on how a `shell:`/`command:` runs: `timeout:` kills the
+// command's process group after that many seconds, and `async:` starts it as
+// a detached job on the target instead (see `async_job`).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    pub timeout: Option<u64>,
+    pub r#async: Option<async_job::Job>,
+}
+
+// A command that ran but exited non-zero. Kept as its own type so the task
+// loop can tell it apart from a failure to run the command at all: only the
+// former has registered output for `failed_when:` to look at.
+pub struct ExitStatusError(pub i32);
+
+impl fmt::Display for ExitStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            format!(
+                "Command execution failed with exit status: {}. Stopping further tasks.",
+                self.0
+            )
+            .red()
+        )
+    }
+}
+
+impl fmt::Debug for ExitStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ExitStatusError {}
+
+// A command the `timeout:` watchdog killed. `timed_out` turns it into the
+// message naming the limit.
+struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            "Command timed out and its process group was killed. Stopping further tasks.".red()
+        )
+    }
+}
+
+impl fmt::Debug for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
 fn handle_command_execution(
     is_localhost: bool,
     session: Option<&Session>,
@@ -44,21 +104,17 @@ fn handle_command_execution(
     };
 
     match result {
-        Ok((stdout, stderr, exit_status)) => {
-            if exit_status != 0 {
-                return Err(format!(
-                    "Command execution failed with exit status: {}. Stopping further tasks.",
-                    exit_status
-                )
-                .red()
-                .into());
-            }
-
+        Ok((stdout, mut stderr, exit_status)) => {
+            let killed = exit_status == 124 && utils::take_timeout_marker(&mut stderr);
+            // Register before failing so failed_when/ignore_errors and later
+            // tasks can look at the output of a non-zero exit.
             if let Some(register) = register {
                 let register_value = serde_json::to_value(common::Register {
                     stdout: stdout.clone(),
                     stderr: stderr.clone(),
                     rc: exit_status,
+                    changed: true,
+                    failed: exit_status != 0,
                 })?;
                 vars_map.insert(register.clone(), register_value);
                 println!(
@@ -66,6 +122,13 @@ fn handle_command_execution(
                     format!("Registering output to: {}", register).yellow()
                 );
             }
+
+            if killed {
+                return Err(Box::new(TimedOut));
+            }
+            if exit_status != 0 {
+                return Err(Box::new(ExitStatusError(exit_status)));
+            }
         }
         Err(e) => {
             return Err(format!(
@@ -117,24 +180,28 @@ fn handle_doas_pty_execution(
     };
 
     match result {
-        Ok((stdout, stderr, exit_code)) => {
-            if exit_code != 0 {
-                return Err(format!(
-                    "Command execution failed with exit status: {}. Stopping further tasks.",
-                    exit_code
-                )
-                .red()
-                .into());
-            }
+        Ok((mut stdout, mut stderr, exit_code)) => {
+            // The PTY merges the streams, so the marker may be in either.
+            let killed = exit_code == 124
+                && (utils::take_timeout_marker(&mut stderr)
+                    || utils::take_timeout_marker(&mut stdout));
             if let Some(reg) = register {
                 let val = serde_json::to_value(common::Register {
                     stdout,
                     stderr,
                     rc: exit_code,
+                    changed: true,
+                    failed: exit_code != 0,
                 })?;
                 vars_map.insert(reg.clone(), val);
                 println!("{}", format!("Registering output to: {}", reg).yellow());
             }
+            if killed {
+                return Err(Box::new(TimedOut));
+            }
+            if exit_code != 0 {
+                return Err(Box::new(ExitStatusError(exit_code)));
+            }
         }
         Err(e) => {
             return Err(format!(
@@ -169,6 +236,7 @@ pub fn process_shell_block(
     become_method: &str,
     become_password: Option<&str>,
     no_log: bool,
+    limits: Limits,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // shell_defaults (e.g. "set -euo pipefail") and environment: exports are
     // injected ahead of the block like the built-in set -e: they run but are
@@ -196,6 +264,65 @@ pub fn process_shell_block(
     let substituted_source = utils::replace_placeholders(source, vars_map);
     let exec_source = format!("{}\n{}", prelude.join("\n"), substituted_source);
 
+    run_shell_source(
+        exec_source,
+        is_localhost,
+        session,
+        task_chdir,
+        register,
+        login_shell,
+        vars_map,
+        become_enabled,
+        become_method,
+        become_password,
+        no_log,
+        limits,
+    )
+}
+
+// Runs an already rendered shell script as one invocation, with the task's
+// become, chdir, login shell and limits, and registers its output.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run_shell_source(
+    exec_source: String,
+    is_localhost: bool,
+    session: Option<&Session>,
+    task_chdir: Option<&str>,
+    register: Option<&String>,
+    login_shell: bool,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    no_log: bool,
+    limits: Limits,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(job) = limits.r#async {
+        return async_job::run(
+            &exec_source,
+            job,
+            is_localhost,
+            session,
+            task_chdir,
+            register,
+            login_shell,
+            vars_map,
+            become_enabled,
+            become_method,
+            become_password,
+            no_log,
+        );
+    }
+
+    let exec_source = match limits.timeout {
+        Some(seconds) => utils::with_timeout(
+            &exec_source,
+            seconds,
+            utils::TimeoutShell::for_task(login_shell, become_enabled, false),
+        ),
+        None => exec_source,
+    };
+
     let display_output = register.is_none() && !no_log;
 
     if become_enabled && become_method == "doas" && become_password.is_some() {
@@ -209,7 +336,8 @@ pub fn process_shell_block(
             display_output,
             become_password.unwrap(),
             vars_map,
-        );
+        )
+        .map_err(|error| timed_out(error, limits.timeout));
     }
 
     let exec_cmd = if become_enabled {
@@ -230,6 +358,7 @@ pub fn process_shell_block(
         vars_map,
         None,
     )
+    .map_err(|error| timed_out(error, limits.timeout))
 }
 
 // Runs a `command:` task — each line is a standalone command exec'd directly
@@ -247,12 +376,43 @@ pub fn process_command(
     become_method: &str,
     become_password: Option<&str>,
     no_log: bool,
+    limits: Limits,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let env_rendered = match environment {
         Some(env) if !env.is_empty() => Some(utils::render_env_values(env, vars_map)?),
         _ => None,
     };
 
+    // An async job runs the lines as one script, in order, stopping at the
+    // first failure like the foreground loop below.
+    if let Some(job) = limits.r#async {
+        let mut script = vec!["set -e".to_string()];
+        if let Some(env) = &env_rendered {
+            script.extend(utils::env_export_lines(env));
+        }
+        for cmd in &commands {
+            let substituted_cmd = utils::replace_placeholders(cmd, vars_map);
+            if !no_log {
+                println!("{}", format!("> {}", substituted_cmd).magenta());
+            }
+            script.push(command_line_for_shell(&substituted_cmd, is_localhost)?);
+        }
+        return async_job::run(
+            &script.join("\n"),
+            job,
+            is_localhost,
+            session,
+            task_chdir,
+            register,
+            login_shell,
+            vars_map,
+            become_enabled,
+            become_method,
+            become_password,
+            no_log,
+        );
+    }
+
     for cmd in commands {
         let substituted_cmd = utils::replace_placeholders(&cmd, vars_map);
         if !no_log {
@@ -272,6 +432,22 @@ pub fn process_command(
             _ => substituted_cmd.clone(),
         };
 
+        let become_inner = match limits.timeout {
+            Some(seconds) if become_enabled => {
+                utils::with_timeout(&become_inner, seconds, utils::TimeoutShell::Sh)
+            }
+            _ => become_inner,
+        };
+        let substituted_cmd = match limits.timeout {
+            Some(seconds) if !become_enabled => utils::with_timeout(
+                &command_line_for_shell(&substituted_cmd, is_localhost)?,
+                seconds,
+                // A remote line is run by the user's shell without a timeout.
+                utils::TimeoutShell::for_task(login_shell, false, !is_localhost),
+            ),
+            _ => substituted_cmd,
+        };
+
         if become_enabled && become_method == "doas" && become_password.is_some() {
             handle_doas_pty_execution(
                 &become_inner,
@@ -283,7 +459,8 @@ pub fn process_command(
                 display_output,
                 become_password.unwrap(),
                 vars_map,
-            )?;
+            )
+            .map_err(|error| timed_out(error, limits.timeout))?;
             continue;
         }
 
@@ -308,8 +485,44 @@ pub fn process_command(
             } else {
                 env_rendered.as_ref()
             },
-        )?;
+        )
+        .map_err(|error| timed_out(error, limits.timeout))?;
     }
 
     Ok(())
 }
+
+// Locally a `command:` line is split into words and exec'd without a shell,
+// so it is quoted word by word before a shell script has to carry it. Remote
+// lines already go through the target's shell and are used as written.
+fn command_line_for_shell(
+    command: &str,
+    is_localhost: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if !is_localhost {
+        return Ok(command.to_string());
+    }
+    let words =
+        shell_words::split(command).map_err(|e| format!("Failed to parse command: {}", e))?;
+    Ok(words
+        .iter()
+        .map(|word| utils::shell_escape(word))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+fn timed_out(
+    error: Box<dyn std::error::Error>,
+    timeout: Option<u64>,
+) -> Box<dyn std::error::Error> {
+    match timeout {
+        Some(seconds) if error.is::<TimedOut>() => format!(
+            "Command timed out after {} second{} and its process group was killed. Stopping further tasks.",
+            seconds,
+            if seconds == 1 { "" } else { "s" }
+        )
+        .red()
+        .into(),
+        _ => error,
+    }
+}
@@ -0,0 +1,482 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+use std::thread;
+use std::time::Instant;
+
+use crate::common::{ComposePull, ComposeSpec, ComposeState, Register};
+use crate::modules::verify::retry_wait;
+use crate::utils;
+
+const WAIT_SLEEP_SECONDS: u64 = 1;
+
+// One row of `docker compose ps --format json`.
+#[derive(Debug, PartialEq)]
+struct Container {
+    name: String,
+    service: String,
+    state: String,
+    health: String,
+    exit_code: i64,
+}
+
+impl Container {
+    fn describe(&self) -> String {
+        if self.health.is_empty() {
+            format!("{} ({})", self.name, self.state)
+        } else {
+            format!("{} ({}, {})", self.name, self.state, self.health)
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        serde_json::json!({
+            "name": self.name,
+            "service": self.service,
+            "state": self.state,
+            "health": self.health,
+            "exit_code": self.exit_code,
+        })
+    }
+}
+
+// Where the containers of a project stand after an operation.
+#[derive(Debug, PartialEq)]
+enum Readiness {
+    Ready,
+    Pending(Vec<String>),
+    Failed(String),
+}
+
+// Compose v2.21 and later print one JSON object per line; earlier releases
+// print a single array.
+fn parse_ps(stdout: &str) -> Result<Vec<Container>, String> {
+    let trimmed = stdout.trim();
+    let rows: Vec<Value> = if trimmed.starts_with('[') {
+        serde_json::from_str(trimmed).map_err(|e| e.to_string())?
+    } else {
+        trimmed
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?
+    };
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let field = |key: &str| {
+                row.get(key)
+                    .and_then(Value::as_str)
+                    .unwrap_or("")
+                    .to_string()
+            };
+            Container {
+                name: field("Name"),
+                service: field("Service"),
+                state: field("State"),
+                health: field("Health"),
+                exit_code: row.get("ExitCode").and_then(Value::as_i64).unwrap_or(0),
+            }
+        })
+        .collect())
+}
+
+fn unhealthy(containers: &[Container]) -> Option<String> {
+    let names: Vec<&str> = containers
+        .iter()
+        .filter(|container| container.health == "unhealthy")
+        .map(|container| container.name.as_str())
+        .collect();
+    if names.is_empty() {
+        None
+    } else {
+        Some(format!("unhealthy containers: {}", names.join(", ")))
+    }
+}
+
+// One-shot services such as migrations exit 0 when they are done, so only a
+// non-zero exit or a dead container fails the wait.
+fn completed(container: &Container) -> bool {
+    container.state == "exited" && container.exit_code == 0
+}
+
+fn readiness(containers: &[Container]) -> Readiness {
+    if let Some(message) = unhealthy(containers) {
+        return Readiness::Failed(message);
+    }
+    if let Some(stopped) = containers.iter().find(|container| {
+        (container.state == "exited" && !completed(container)) || container.state == "dead"
+    }) {
+        return Readiness::Failed(format!(
+            "container {} {} with code {}",
+            stopped.name, stopped.state, stopped.exit_code
+        ));
+    }
+    let pending: Vec<String> = containers
+        .iter()
+        .filter(|container| {
+            !completed(container)
+                && (container.state != "running"
+                    || !(container.health.is_empty() || container.health == "healthy"))
+        })
+        .map(Container::describe)
+        .collect();
+    if containers.is_empty() {
+        Readiness::Pending(vec!["no containers yet".to_string()])
+    } else if pending.is_empty() {
+        Readiness::Ready
+    } else {
+        Readiness::Pending(pending)
+    }
+}
+
+// Runs compose from the project directory so relative `files:` and the
+// project's `.env` resolve the way they do for a user in that directory.
+fn compose_command(project_dir: &str, files: &[String], args: &[String]) -> String {
+    let mut command = format!("cd {} && docker compose", utils::shell_escape(project_dir));
+    for file in files {
+        command.push_str(&format!(" -f {}", utils::shell_escape(file)));
+    }
+    for arg in args {
+        command.push_str(&format!(" {}", utils::shell_escape(arg)));
+    }
+    command
+}
+
+fn operation_args(spec: &ComposeSpec, services: &[String]) -> Vec<String> {
+    let mut args: Vec<String> = match spec.state {
+        ComposeState::Up => vec!["up".into(), "-d".into()],
+        ComposeState::Down => vec!["down".into()],
+        ComposeState::Restarted => vec!["restart".into()],
+        ComposeState::Pulled => vec!["pull".into()],
+    };
+    if let Some(pull) = spec.pull {
+        args.push("--pull".into());
+        args.push(
+            match pull {
+                ComposePull::Always => "always",
+                ComposePull::Missing => "missing",
+            }
+            .into(),
+        );
+    }
+    if spec.remove_orphans {
+        args.push("--remove-orphans".into());
+    }
+    args.extend(services.iter().cloned());
+    args
+}
+
+fn state_name(state: ComposeState) -> &'static str {
+    match state {
+        ComposeState::Up => "up",
+        ComposeState::Down => "down",
+        ComposeState::Restarted => "restarted",
+        ComposeState::Pulled => "pulled",
+    }
+}
+
+fn validate(task_name: &str, spec: &ComposeSpec) -> Result<(), Box<dyn std::error::Error>> {
+    let invalid = |message: &str| -> Box<dyn std::error::Error> {
+        format!("Task '{}': compose {}", task_name, message).into()
+    };
+    if spec.pull.is_some() && spec.state != ComposeState::Up {
+        return Err(invalid("pull can only be used with state: up"));
+    }
+    if spec.remove_orphans && !matches!(spec.state, ComposeState::Up | ComposeState::Down) {
+        return Err(invalid(
+            "remove_orphans can only be used with state: up or down",
+        ));
+    }
+    if let Some(wait) = &spec.wait {
+        if !matches!(spec.state, ComposeState::Up | ComposeState::Restarted) {
+            return Err(invalid("wait can only be used with state: up or restarted"));
+        }
+        if wait.timeout_seconds == 0 {
+            return Err(invalid("wait.timeout_seconds must be greater than 0"));
+        }
+    }
+    Ok(())
+}
+
+// `compose:` runs one `docker compose` operation for a project, then reads
+// the project's containers back with `ps`. `wait:` keeps polling until every
+// container is running and, where it has a health check, healthy.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &ComposeSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    validate(task_name, spec)?;
+    let project_dir = utils::replace_placeholders(&spec.project_dir, vars_map);
+    if project_dir.trim().is_empty() {
+        return Err(format!(
+            "Task '{}': compose project_dir must not be empty",
+            task_name
+        )
+        .into());
+    }
+    let render_list = |values: &Option<Vec<String>>, field: &str| {
+        values
+            .iter()
+            .flatten()
+            .map(|value| {
+                let rendered = utils::replace_placeholders(value, vars_map);
+                if rendered.trim().is_empty() {
+                    Err(format!(
+                        "Task '{}': compose {} must not contain empty entries",
+                        task_name, field
+                    ))
+                } else {
+                    Ok(rendered)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let files = render_list(&spec.files, "files")?;
+    let services = render_list(&spec.services, "services")?;
+
+    let target = |command: &str| {
+        utils::run_shell_on_target(
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )
+    };
+
+    println!(
+        "{}",
+        format!(
+            "> [compose] {} {}{}",
+            state_name(spec.state),
+            project_dir,
+            if services.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", services.join(", "))
+            }
+        )
+        .magenta()
+    );
+
+    let (stdout, stderr, rc) = target(&compose_command(
+        &project_dir,
+        &files,
+        &operation_args(spec, &services),
+    ))?;
+    if rc != 0 {
+        let detail = if stderr.trim().is_empty() {
+            stdout.trim()
+        } else {
+            stderr.trim()
+        };
+        return Err(format!(
+            "Task '{}': compose {} failed: {}",
+            task_name,
+            state_name(spec.state),
+            detail
+        )
+        .into());
+    }
+
+    let mut ps_args = vec![
+        "ps".to_string(),
+        "--all".into(),
+        "--format".into(),
+        "json".into(),
+    ];
+    ps_args.extend(services.iter().cloned());
+    let ps = compose_command(&project_dir, &files, &ps_args);
+    let list = |command: &str| -> Result<Vec<Container>, Box<dyn std::error::Error>> {
+        let (out, err, rc) = target(command)?;
+        if rc != 0 {
+            return Err(format!("Task '{}': compose ps failed: {}", task_name, err.trim()).into());
+        }
+        parse_ps(&out).map_err(|e| {
+            format!(
+                "Task '{}': compose ps printed invalid JSON: {}",
+                task_name, e
+            )
+            .into()
+        })
+    };
+
+    let started = Instant::now();
+    let mut containers = list(&ps)?;
+    if let Some(wait) = &spec.wait {
+        loop {
+            match readiness(&containers) {
+                Readiness::Ready => break,
+                Readiness::Failed(message) => {
+                    return Err(format!("Task '{}': compose {}", task_name, message).into());
+                }
+                Readiness::Pending(pending) => {
+                    let Some(delay) = retry_wait(
+                        WAIT_SLEEP_SECONDS,
+                        Some(wait.timeout_seconds),
+                        started.elapsed(),
+                    ) else {
+                        return Err(format!(
+                            "Task '{}': compose containers not ready after {}s: {}",
+                            task_name,
+                            wait.timeout_seconds,
+                            pending.join(", ")
+                        )
+                        .into());
+                    };
+                    thread::sleep(delay);
+                }
+            }
+            containers = list(&ps)?;
+        }
+    } else if matches!(spec.state, ComposeState::Up | ComposeState::Restarted) {
+        if let Some(message) = unhealthy(&containers) {
+            return Err(format!("Task '{}': compose {}", task_name, message).into());
+        }
+    }
+
+    for container in &containers {
+        println!("{}", container.describe().bright_black());
+    }
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout,
+            stderr,
+            rc,
+            changed: true,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert(
+                "containers".to_string(),
+                Value::Array(containers.iter().map(Container::to_value).collect()),
+            );
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(yaml: &str) -> ComposeSpec {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn container(state: &str, health: &str) -> Container {
+        Container {
+            name: "app-web-1".to_string(),
+            service: "web".to_string(),
+            state: state.to_string(),
+            health: health.to_string(),
+            exit_code: 0,
+        }
+    }
+
+    #[test]
+    fn parses_ps_lines_and_arrays() {
+        let line = r#"{"Name":"app-web-1","Service":"web","State":"running","Health":"healthy","ExitCode":0}"#;
+        let expected = vec![container("running", "healthy")];
+        assert_eq!(parse_ps(&format!("{}\n", line)).unwrap(), expected);
+        assert_eq!(parse_ps(&format!("[{}]", line)).unwrap(), expected);
+        assert_eq!(parse_ps("").unwrap(), Vec::new());
+        assert!(parse_ps("not json").is_err());
+    }
+
+    #[test]
+    fn readiness_waits_for_health_and_fails_fast() {
+        assert_eq!(
+            readiness(&[container("running", "healthy"), container("running", "")]),
+            Readiness::Ready
+        );
+        assert_eq!(
+            readiness(&[container("running", "starting")]),
+            Readiness::Pending(vec!["app-web-1 (running, starting)".to_string()])
+        );
+        assert!(matches!(readiness(&[]), Readiness::Pending(_)));
+        assert_eq!(
+            readiness(&[container("running", "unhealthy")]),
+            Readiness::Failed("unhealthy containers: app-web-1".to_string())
+        );
+        let failed_job = Container {
+            exit_code: 3,
+            ..container("exited", "")
+        };
+        assert_eq!(
+            readiness(&[failed_job]),
+            Readiness::Failed("container app-web-1 exited with code 3".to_string())
+        );
+        assert!(matches!(
+            readiness(&[container("dead", "")]),
+            Readiness::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn readiness_counts_one_shot_services_that_exited_cleanly_as_done() {
+        assert_eq!(
+            readiness(&[container("running", "healthy"), container("exited", "")]),
+            Readiness::Ready
+        );
+        assert_eq!(
+            readiness(&[container("exited", ""), container("running", "starting")]),
+            Readiness::Pending(vec!["app-web-1 (running, starting)".to_string()])
+        );
+    }
+
+    #[test]
+    fn builds_compose_commands() {
+        let spec = spec("project_dir: /srv/app\npull: always\nremove_orphans: true\n");
+        let services = vec!["web".to_string()];
+        assert_eq!(
+            compose_command(
+                "/srv/app",
+                &["compose.yml".to_string()],
+                &operation_args(&spec, &services)
+            ),
+            "cd '/srv/app' && docker compose -f 'compose.yml' 'up' '-d' '--pull' 'always' '--remove-orphans' 'web'"
+        );
+    }
+
+    #[test]
+    fn rejects_options_that_do_not_apply_to_the_state() {
+        for (yaml, message) in [
+            (
+                "project_dir: /srv/app\nstate: down\npull: always\n",
+                "pull can only be used with state: up",
+            ),
+            (
+                "project_dir: /srv/app\nstate: restarted\nremove_orphans: true\n",
+                "remove_orphans can only be used with state: up or down",
+            ),
+            (
+                "project_dir: /srv/app\nstate: pulled\nwait:\n  timeout_seconds: 10\n",
+                "wait can only be used with state: up or restarted",
+            ),
+            (
+                "project_dir: /srv/app\nwait:\n  timeout_seconds: 0\n",
+                "wait.timeout_seconds must be greater than 0",
+            ),
+        ] {
+            let err = validate("Example", &spec(yaml)).unwrap_err();
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+}
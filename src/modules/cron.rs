@@ -0,0 +1,439 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{CronSpec, CronSpecialTime, EditState, Register};
+use crate::utils;
+
+const MARKER: &str = "# deploy-helper: ";
+const END_MARKER: &str = "# deploy-helper end: ";
+const CRON_DIR: &str = "/etc/cron.d";
+const CRON_FILE_USER: &str = "root";
+
+// Where the entry lives: a user's crontab, edited with `crontab`, or a file
+// under /etc/cron.d.
+enum Location {
+    Crontab { user: Option<String> },
+    File { path: String },
+}
+
+impl Location {
+    fn describe(&self) -> String {
+        match self {
+            Location::Crontab { user: Some(user) } => format!("crontab of {}", user),
+            Location::Crontab { user: None } => "crontab".to_string(),
+            Location::File { path } => path.clone(),
+        }
+    }
+
+    fn crontab_command(user: &Option<String>, args: &str) -> String {
+        match user {
+            Some(user) => format!("crontab -u {} {}", utils::shell_escape(user), args),
+            None => format!("crontab {}", args),
+        }
+    }
+}
+
+// `cron:` keeps one named entry in a crontab or cron.d file. The entry is its
+// `env:` lines and the job line between `# deploy-helper: <name>` and
+// `# deploy-helper end: <name>` markers, and it is found again by them: a
+// changed schedule or job replaces it in place, `state: absent` removes it,
+// and every other line is left alone.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &CronSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let invalid = |message: String| -> Box<dyn std::error::Error> {
+        format!("Task '{}': cron {}", task_name, message).into()
+    };
+    let rendered = |value: &str| utils::replace_placeholders(value, vars_map);
+    let name = rendered(&spec.name);
+    if name.trim().is_empty() || name.contains('\n') {
+        return Err(invalid("name must be a single non-empty line".to_string()));
+    }
+    let user = spec.user.as_deref().map(rendered);
+    let location = match spec.cron_file.as_deref().map(rendered) {
+        Some(file) => {
+            if file.is_empty()
+                || !file
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(invalid(format!(
+                    "cron_file '{}' must be a file name of letters, digits, '-' and '_'",
+                    file
+                )));
+            }
+            Location::File {
+                path: format!("{}/{}", CRON_DIR, file),
+            }
+        }
+        None => Location::Crontab { user: user.clone() },
+    };
+
+    let entry = match spec.state {
+        EditState::Absent => None,
+        EditState::Present => {
+            let job = spec
+                .job
+                .as_deref()
+                .map(rendered)
+                .ok_or_else(|| invalid("job is required unless state is absent".to_string()))?;
+            let env = spec
+                .env
+                .iter()
+                .flatten()
+                .map(|(key, value)| (key.clone(), rendered(value)))
+                .collect::<Vec<_>>();
+            let schedule = schedule(spec, &rendered).map_err(invalid)?;
+            let run_as = match location {
+                Location::File { .. } => Some(user.as_deref().unwrap_or(CRON_FILE_USER)),
+                Location::Crontab { .. } => None,
+            };
+            Some(entry_lines(&name, &schedule, run_as, &job, &env).map_err(invalid)?)
+        }
+    };
+
+    let target = |command: &str| {
+        utils::run_shell_on_target(
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )
+    };
+
+    let current = match &location {
+        Location::Crontab { user } => {
+            let (stdout, stderr, rc) = target(&Location::crontab_command(user, "-l"))?;
+            match rc {
+                0 => stdout,
+                127 => return Err(invalid("needs crontab on the target".to_string())),
+                _ if is_missing_crontab(&format!("{} {}", stdout, stderr)) => String::new(),
+                rc => {
+                    return Err(invalid(format!(
+                        "failed to read the {}: exit {}: {}",
+                        location.describe(),
+                        rc,
+                        stderr.trim()
+                    )))
+                }
+            }
+        }
+        Location::File { path } => {
+            let (stdout, stderr, rc) = target(&format!(
+                "if [ -e {0} ]; then cat {0}; fi",
+                utils::shell_escape(path)
+            ))?;
+            if rc != 0 {
+                return Err(invalid(format!(
+                    "failed to read {}: {}",
+                    path,
+                    stderr.trim()
+                )));
+            }
+            stdout
+        }
+    };
+
+    let current = normalized(&current);
+    let (updated, found) = edit(&current, &name, entry.as_deref());
+    let changed = updated != current;
+    if changed {
+        let (_, stderr, rc) = match &location {
+            Location::Crontab { user } => target(&format!(
+                "printf '%s' {} | {}",
+                utils::shell_escape(&updated),
+                Location::crontab_command(user, "-")
+            ))?,
+            Location::File { path } if updated.is_empty() => {
+                target(&format!("rm -f {}", utils::shell_escape(path)))?
+            }
+            Location::File { path } => {
+                utils::write_to_target(
+                    updated.as_bytes(),
+                    path,
+                    is_localhost,
+                    session,
+                    become_enabled,
+                    become_method,
+                    become_password,
+                    Some("0644"),
+                )?;
+                (String::new(), String::new(), 0)
+            }
+        };
+        if rc != 0 {
+            return Err(invalid(format!(
+                "failed to update the {}: exit {}: {}",
+                location.describe(),
+                rc,
+                stderr.trim()
+            )));
+        }
+    }
+
+    let summary = match (changed, found, entry.is_some()) {
+        (false, _, _) => "unchanged",
+        (true, false, _) => "added",
+        (true, true, true) => "updated",
+        (true, true, false) => "removed",
+    };
+    println!(
+        "{}",
+        format!("> [cron] {} in {} ({})", name, location.describe(), summary).magenta()
+    );
+
+    if let Some(reg) = register {
+        let value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed,
+            failed: false,
+        })?;
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+// The five time fields, or `@<special_time>`.
+fn schedule(spec: &CronSpec, rendered: &dyn Fn(&str) -> String) -> Result<String, String> {
+    let fields = [
+        ("minute", &spec.minute),
+        ("hour", &spec.hour),
+        ("day", &spec.day),
+        ("month", &spec.month),
+        ("weekday", &spec.weekday),
+    ];
+    if let Some(special_time) = spec.special_time {
+        if let Some((field, _)) = fields.iter().find(|(_, value)| value.is_some()) {
+            return Err(format!("special_time cannot be combined with {}", field));
+        }
+        let name = match special_time {
+            CronSpecialTime::Reboot => "reboot",
+            CronSpecialTime::Yearly => "yearly",
+            CronSpecialTime::Annually => "annually",
+            CronSpecialTime::Monthly => "monthly",
+            CronSpecialTime::Weekly => "weekly",
+            CronSpecialTime::Daily => "daily",
+            CronSpecialTime::Hourly => "hourly",
+        };
+        return Ok(format!("@{}", name));
+    }
+    let mut schedule = Vec::new();
+    for (field, value) in fields {
+        let value = value
+            .as_deref()
+            .map(rendered)
+            .unwrap_or_else(|| "*".to_string());
+        if value.is_empty() || value.chars().any(char::is_whitespace) {
+            return Err(format!("{} '{}' must be a single cron field", field, value));
+        }
+        schedule.push(value);
+    }
+    Ok(schedule.join(" "))
+}
+
+fn entry_lines(
+    name: &str,
+    schedule: &str,
+    run_as: Option<&str>,
+    job: &str,
+    env: &[(String, String)],
+) -> Result<Vec<String>, String> {
+    if job.trim().is_empty() || job.contains('\n') {
+        return Err("job must be a single non-empty line".to_string());
+    }
+    let mut lines = vec![format!("{}{}", MARKER, name)];
+    for (key, value) in env {
+        if !is_env_name(key) {
+            return Err(format!("env name '{}' is not a valid variable name", key));
+        }
+        if value.contains('\n') {
+            return Err(format!("env value for {} must be a single line", key));
+        }
+        lines.push(format!("{}={}", key, value));
+    }
+    lines.push(match run_as {
+        Some(user) => format!("{} {} {}", schedule, user, job),
+        None => format!("{} {}", schedule, job),
+    });
+    lines.push(format!("{}{}", END_MARKER, name));
+    Ok(lines)
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// `crontab -l` fails for a user without a crontab; that reads as empty.
+fn is_missing_crontab(output: &str) -> bool {
+    let output = output.to_ascii_lowercase();
+    output.contains("no crontab") || output.contains("no such file")
+}
+
+fn normalized(content: &str) -> String {
+    let mut lines: Vec<&str> = content.lines().collect();
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+// Replaces the named entry with `entry`, or removes it when `entry` is `None`.
+// A missing entry is appended. Duplicates of the entry are dropped, so a
+// crontab edited by hand converges on one copy. A begin marker whose end
+// marker is gone is dropped on its own, so the lines after it are never taken
+// for the entry. Also returns whether the entry was there before.
+fn edit(content: &str, name: &str, entry: Option<&[String]>) -> (String, bool) {
+    let begin = format!("{}{}", MARKER, name);
+    let end = format!("{}{}", END_MARKER, name);
+    let source: Vec<&str> = content.lines().collect();
+    let mut lines: Vec<&str> = Vec::new();
+    let mut position = None;
+    let mut index = 0;
+    while index < source.len() {
+        let line = source[index];
+        index += 1;
+        if line.trim_end() != begin {
+            lines.push(line);
+            continue;
+        }
+        position.get_or_insert(lines.len());
+        let close = source[index..]
+            .iter()
+            .position(|line| line.starts_with(MARKER) || line.starts_with(END_MARKER))
+            .filter(|&offset| source[index + offset].trim_end() == end);
+        if let Some(offset) = close {
+            index += offset + 1;
+        }
+    }
+
+    let found = position.is_some();
+    if let Some(entry) = entry {
+        let at = position.unwrap_or(lines.len());
+        lines.splice(at..at, entry.iter().map(String::as_str));
+    }
+    (normalized(&lines.join("\n")), found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(job: &str) -> Vec<String> {
+        entry_lines(
+            "rotate logs",
+            "0 3 * * *",
+            None,
+            job,
+            &[("MAILTO".to_string(), String::new())],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn edit_appends_a_missing_entry() {
+        let (content, found) = edit(
+            "# m h dom mon dow command\n*/5 * * * * /usr/bin/ping\n",
+            "rotate logs",
+            Some(&entry("/opt/app/rotate")),
+        );
+        assert!(!found);
+        assert_eq!(
+            content,
+            "# m h dom mon dow command\n*/5 * * * * /usr/bin/ping\n# deploy-helper: rotate logs\nMAILTO=\n0 3 * * * /opt/app/rotate\n# deploy-helper end: rotate logs\n"
+        );
+    }
+
+    #[test]
+    fn edit_replaces_the_entry_in_place_and_drops_duplicates() {
+        let current = "# deploy-helper: rotate logs\nMAILTO=\n0 3 * * * /opt/app/old\n# deploy-helper end: rotate logs\n@reboot /opt/app/start\n# deploy-helper: rotate logs\n0 4 * * * /opt/app/old\n# deploy-helper end: rotate logs\n";
+        let (content, found) = edit(current, "rotate logs", Some(&entry("/opt/app/rotate")));
+        assert!(found);
+        assert_eq!(
+            content,
+            "# deploy-helper: rotate logs\nMAILTO=\n0 3 * * * /opt/app/rotate\n# deploy-helper end: rotate logs\n@reboot /opt/app/start\n"
+        );
+        let (again, _) = edit(&content, "rotate logs", Some(&entry("/opt/app/rotate")));
+        assert_eq!(again, content);
+    }
+
+    #[test]
+    fn edit_removes_only_the_named_entry() {
+        let current = "# deploy-helper: rotate logs\n0 3 * * * /opt/app/rotate\n# deploy-helper end: rotate logs\n# deploy-helper: backup\n0 1 * * * /opt/app/backup\n# deploy-helper end: backup\n";
+        let (content, found) = edit(current, "rotate logs", None);
+        assert!(found);
+        assert_eq!(
+            content,
+            "# deploy-helper: backup\n0 1 * * * /opt/app/backup\n# deploy-helper end: backup\n"
+        );
+        assert_eq!(edit("", "rotate logs", None), (String::new(), false));
+    }
+
+    #[test]
+    fn edit_keeps_the_lines_after_a_marker_whose_job_was_deleted() {
+        // The job line and end marker were removed by hand; the next line is
+        // an unrelated job.
+        let current = "# deploy-helper: rotate logs\nMAILTO=\n*/5 * * * * /usr/bin/ping\n# deploy-helper: backup\n0 1 * * * /opt/app/backup\n# deploy-helper end: backup\n";
+        let (content, found) = edit(current, "rotate logs", None);
+        assert!(found);
+        assert_eq!(
+            content,
+            "MAILTO=\n*/5 * * * * /usr/bin/ping\n# deploy-helper: backup\n0 1 * * * /opt/app/backup\n# deploy-helper end: backup\n"
+        );
+        let (content, _) = edit(current, "rotate logs", Some(&entry("/opt/app/rotate")));
+        assert!(content.starts_with(
+            "# deploy-helper: rotate logs\nMAILTO=\n0 3 * * * /opt/app/rotate\n# deploy-helper end: rotate logs\nMAILTO=\n*/5 * * * * /usr/bin/ping\n"
+        ));
+    }
+
+    #[test]
+    fn entry_lines_for_cron_files_name_the_user() {
+        let lines = entry_lines("sync", "@hourly", Some("app"), "/opt/app/sync", &[]).unwrap();
+        assert_eq!(
+            lines,
+            [
+                "# deploy-helper: sync",
+                "@hourly app /opt/app/sync",
+                "# deploy-helper end: sync"
+            ]
+        );
+        assert!(entry_lines("sync", "@hourly", None, "a\nb", &[]).is_err());
+        assert!(entry_lines(
+            "sync",
+            "@hourly",
+            None,
+            "/opt/app/sync",
+            &[("1BAD".to_string(), "x".to_string())]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn missing_crontab_messages_read_as_empty() {
+        assert!(is_missing_crontab("no crontab for app"));
+        assert!(is_missing_crontab(
+            "crontab: can't open 'app': No such file or directory"
+        ));
+        assert!(!is_missing_crontab("must be privileged to use -u"));
+    }
+}
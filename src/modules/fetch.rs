@@ -0,0 +1,252 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::common::{FetchSpec, Register};
+use crate::utils;
+
+// `fetch:` copies one file from the target to the control machine. By default
+// it lands at `dest/<host>/<src path>` so the same task run against several
+// hosts never overwrites itself; `flat: true` uses `dest` as given. The
+// transfer is checked against a SHA-256 computed on the target.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &FetchSpec,
+    host: &str,
+    deploy_file_dir: &Path,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let src = utils::replace_placeholders(&spec.src, vars_map);
+    let dest = utils::replace_placeholders(&spec.dest, vars_map);
+    let local = local_dest(
+        &utils::resolve_src_path(deploy_file_dir, &dest),
+        dest.ends_with('/'),
+        host,
+        &src,
+        spec.flat,
+    )
+    .map_err(|e| format!("Task '{}': {}", task_name, e))?;
+
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &checksum_command(&src),
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    let checksum = match (rc, stdout.trim()) {
+        (0, "missing") => {
+            return Err(format!(
+                "Task '{}': fetch source not found on target: {}",
+                task_name, src
+            )
+            .into());
+        }
+        (0, "not-a-file") => {
+            return Err(format!(
+                "Task '{}': fetch source is not a regular file: {}",
+                task_name, src
+            )
+            .into());
+        }
+        (0, output) => parse_checksum(output).ok_or_else(|| {
+            format!(
+                "Task '{}': unexpected checksum output for {}: {}",
+                task_name, src, output
+            )
+        })?,
+        (rc, _) => {
+            return Err(format!(
+                "Task '{}': failed to checksum {}: exit {}: {}",
+                task_name,
+                src,
+                rc,
+                stderr.trim()
+            )
+            .into());
+        }
+    };
+
+    let local_display = local.to_string_lossy().replace('\\', "/");
+    let unchanged = fs::read(&local).is_ok_and(|bytes| sha256_hex(&bytes) == checksum);
+    if unchanged {
+        println!(
+            "{}",
+            format!("> [fetch] {} -> {} (unchanged)", src, local_display).magenta()
+        );
+    } else {
+        let bytes = utils::read_file_from_target(
+            &src,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?;
+        let received = sha256_hex(&bytes);
+        if received != checksum {
+            return Err(format!(
+                "Task '{}': fetch checksum mismatch for {} (target {}, received {}); was the file changing during the transfer?",
+                task_name, src, checksum, received
+            )
+            .into());
+        }
+        if let Some(parent) = local.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        fs::write(&local, &bytes)
+            .map_err(|e| format!("Failed to write {}: {}", local_display, e))?;
+        println!(
+            "{}",
+            format!(
+                "> [fetch] {} -> {} ({} bytes)",
+                src,
+                local_display,
+                bytes.len()
+            )
+            .magenta()
+        );
+    }
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed: !unchanged,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("dest".to_string(), Value::from(local_display));
+            fields.insert("checksum".to_string(), Value::from(checksum));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+// Where the fetched file goes locally. Only the normal components of `src`
+// are kept under `dest/<host>/`, so `..` in a target path can't escape it.
+fn local_dest(
+    dest: &Path,
+    dest_is_dir: bool,
+    host: &str,
+    src: &str,
+    flat: bool,
+) -> Result<PathBuf, String> {
+    let src_parts: Vec<_> = Path::new(src)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect();
+    let Some(file_name) = src_parts.last() else {
+        return Err(format!("fetch src '{}' does not name a file", src));
+    };
+
+    if flat {
+        return Ok(if dest_is_dir {
+            dest.join(file_name)
+        } else {
+            dest.to_path_buf()
+        });
+    }
+    let mut path = dest.join(host);
+    path.extend(src_parts);
+    Ok(path)
+}
+
+// Prints the SHA-256 of the file in `$p`, with `sha256sum` (GNU) or
+// `shasum` (macOS/BSD), for `parse_checksum`.
+pub(crate) const SHA256_COMMAND: &str = "if command -v sha256sum >/dev/null 2>&1; then sha256sum -- \"$p\"; else shasum -a 256 -- \"$p\"; fi";
+
+pub(crate) fn checksum_command(src: &str) -> String {
+    format!(
+        "p={}\nif [ ! -e \"$p\" ]; then echo missing\nelif [ ! -f \"$p\" ]; then echo not-a-file\nelse {}\nfi",
+        utils::shell_escape(src),
+        SHA256_COMMAND
+    )
+}
+
+pub(crate) fn parse_checksum(output: &str) -> Option<String> {
+    let hex = output.split_whitespace().next()?.to_ascii_lowercase();
+    (hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())).then_some(hex)
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    openssl::sha::sha256(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_dest_is_laid_out_per_host() {
+        let dest = Path::new("/backups");
+        assert_eq!(
+            local_dest(dest, false, "web1", "/var/log/app.log", false).unwrap(),
+            PathBuf::from("/backups/web1/var/log/app.log")
+        );
+        assert_eq!(
+            local_dest(dest, false, "web1", "../../etc/passwd", false).unwrap(),
+            PathBuf::from("/backups/web1/etc/passwd")
+        );
+        assert!(local_dest(dest, false, "web1", "/", false).is_err());
+    }
+
+    #[test]
+    fn flat_dest_is_a_file_or_a_directory() {
+        assert_eq!(
+            local_dest(
+                Path::new("/backups/app.log"),
+                false,
+                "web1",
+                "/var/log/app.log",
+                true
+            )
+            .unwrap(),
+            PathBuf::from("/backups/app.log")
+        );
+        assert_eq!(
+            local_dest(
+                Path::new("/backups/"),
+                true,
+                "web1",
+                "/var/log/app.log",
+                true
+            )
+            .unwrap(),
+            PathBuf::from("/backups/app.log")
+        );
+    }
+
+    #[test]
+    fn parses_sha256sum_and_shasum_output() {
+        let hex = sha256_hex(b"abc");
+        assert_eq!(
+            hex,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(parse_checksum(&format!("{}  /tmp/a", hex)), Some(hex));
+        assert_eq!(parse_checksum("sha256sum: not found"), None);
+    }
+}
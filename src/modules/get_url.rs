@@ -0,0 +1,343 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+use std::fs;
+
+use crate::common::{GetUrlSpec, Register, RunFrom};
+use crate::modules::fetch::{checksum_command, parse_checksum, sha256_hex, SHA256_COMMAND};
+use crate::modules::uri;
+use crate::utils;
+
+// `get_url:` downloads `url` to `dest` on the target. The download lands in a
+// temp file next to `dest` and only replaces it once the `checksum:` matches,
+// and a `dest` that already has the content is left alone. With a checksum,
+// a matching `dest` skips the download entirely. `from: control` downloads on
+// the control machine and uploads the file instead, for targets that can't
+// reach the URL.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &GetUrlSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = utils::replace_placeholders(&spec.url, vars_map);
+    let dest = utils::replace_placeholders(&spec.dest, vars_map);
+    let mode = spec
+        .mode
+        .as_deref()
+        .map(|m| utils::replace_placeholders(m, vars_map));
+    if let Some(m) = &mode {
+        utils::validate_mode(m).map_err(|e| format!("Task '{}': {}", task_name, e))?;
+    }
+    let want = spec
+        .checksum
+        .as_deref()
+        .map(|c| parse_expected_checksum(&utils::replace_placeholders(c, vars_map)))
+        .transpose()
+        .map_err(|e| format!("Task '{}': get_url {}", task_name, e))?;
+    let download = Download {
+        url: &url,
+        headers: uri::render_headers(spec.headers.as_ref(), vars_map),
+        timeout: spec.timeout.unwrap_or(uri::DEFAULT_TIMEOUT_SECONDS),
+        no_log,
+    };
+
+    let outcome = match spec.from {
+        RunFrom::Target => download_on_target(
+            &download,
+            &dest,
+            want.as_deref(),
+            mode.as_deref(),
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        ),
+        RunFrom::Control => download_on_control(
+            &download,
+            &dest,
+            want.as_deref(),
+            mode.as_deref(),
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        ),
+    }
+    .map_err(|e| format!("Task '{}': get_url {}", task_name, e))?;
+
+    let status = if outcome.changed {
+        "downloaded"
+    } else {
+        "unchanged"
+    };
+    // The URL may carry a token, so no_log keeps it off the progress line.
+    let line = if no_log {
+        format!("> [get_url] {} ({}, URL hidden by no_log)", dest, status)
+    } else {
+        format!("> [get_url] {} -> {} ({})", url, dest, status)
+    };
+    println!("{}", line.magenta());
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed: outcome.changed,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("dest".to_string(), Value::from(dest));
+            fields.insert("checksum".to_string(), Value::from(outcome.checksum));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+struct Download<'a> {
+    url: &'a str,
+    headers: Vec<(String, String)>,
+    timeout: u64,
+    no_log: bool,
+}
+
+impl Download<'_> {
+    // curl writing the body to `output`, a shell word. `-f` turns an HTTP
+    // error status into a failed download. Headers go in on stdin, see
+    // `uri::curl_config`.
+    fn command(&self, output: &str) -> String {
+        let mut command = String::new();
+        let config = uri::curl_config(&self.headers, None);
+        if let Some(config) = &config {
+            command.push_str(&format!("printf '%s' {} | ", utils::shell_escape(config)));
+        }
+        command.push_str(&format!("curl -fsSL --max-time {}", self.timeout));
+        if config.is_some() {
+            command.push_str(" --config -");
+        }
+        command.push_str(&format!(" -o {} {}", output, utils::shell_escape(self.url)));
+        command
+    }
+}
+
+struct Outcome {
+    changed: bool,
+    checksum: String,
+}
+
+// `sha256:<hex>` is the only accepted form.
+fn parse_expected_checksum(checksum: &str) -> Result<String, String> {
+    let Some((algorithm, hex)) = checksum.split_once(':') else {
+        return Err(format!(
+            "checksum must look like sha256:<hex> (got '{}')",
+            checksum
+        ));
+    };
+    if !algorithm.trim().eq_ignore_ascii_case("sha256") {
+        return Err(format!(
+            "checksum algorithm '{}' is not supported; use sha256",
+            algorithm
+        ));
+    }
+    parse_checksum(hex).ok_or_else(|| format!("checksum '{}' is not a SHA-256 hex digest", hex))
+}
+
+fn curl_failure(rc: i32, stderr: &str, download: &Download) -> String {
+    if rc == 127 {
+        return "needs curl, which is not installed".to_string();
+    }
+    if download.no_log {
+        return format!("download failed: exit {} (details hidden by no_log)", rc);
+    }
+    format!(
+        "failed to download {}: exit {}: {}",
+        download.url,
+        rc,
+        stderr.trim()
+    )
+}
+
+fn checksum_mismatch(want: &str, got: &str) -> String {
+    format!(
+        "checksum mismatch: expected sha256:{}, downloaded sha256:{}",
+        want, got
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn download_on_target(
+    download: &Download,
+    dest: &str,
+    want: Option<&str>,
+    mode: Option<&str>,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<Outcome, String> {
+    let chmod = mode
+        .map(|m| format!("chmod {} \"$d\"\n", m))
+        .unwrap_or_default();
+    let command = format!(
+        r#"d={}; want={}; tmp="$d.tmp.$$"
+sha() {{ p="$1"; {} | cut -d' ' -f1; }}
+if [ -n "$want" ] && [ -f "$d" ] && [ "$(sha "$d")" = "$want" ]; then
+  {}echo status=unchanged; echo "sha256=$want"; exit 0
+fi
+{} || {{ rc=$?; rm -f "$tmp"; exit $rc; }}
+got=$(sha "$tmp")
+if [ -n "$want" ] && [ "$got" != "$want" ]; then rm -f "$tmp"; echo status=mismatch; echo "sha256=$got"; exit 0; fi
+if [ -f "$d" ] && [ "$(sha "$d")" = "$got" ]; then rm -f "$tmp"; echo status=unchanged
+else mv -f "$tmp" "$d"; echo status=changed; fi
+{}echo "sha256=$got""#,
+        utils::shell_escape(dest),
+        utils::shell_escape(want.unwrap_or_default()),
+        SHA256_COMMAND,
+        chmod,
+        download.command("\"$tmp\""),
+        chmod
+    );
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &command,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )
+    .map_err(|e| e.to_string())?;
+    if rc != 0 {
+        return Err(curl_failure(rc, &stderr, download));
+    }
+
+    let fields: IndexMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.trim_end_matches('\r').split_once('='))
+        .collect();
+    let checksum = fields
+        .get("sha256")
+        .and_then(|sha| parse_checksum(sha))
+        .ok_or_else(|| format!("unexpected output: {}", stdout.trim()))?;
+    match fields.get("status") {
+        Some(&"mismatch") => Err(checksum_mismatch(want.unwrap_or_default(), &checksum)),
+        Some(&"changed") => Ok(Outcome {
+            changed: true,
+            checksum,
+        }),
+        _ => Ok(Outcome {
+            changed: false,
+            checksum,
+        }),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn download_on_control(
+    download: &Download,
+    dest: &str,
+    want: Option<&str>,
+    mode: Option<&str>,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<Outcome, String> {
+    let temp_path = utils::make_temp_file_on_target("deploy-helper-get-url", true, None)
+        .map_err(|e| e.to_string())?;
+    let result = utils::run_shell_on_target(
+        &download.command(&utils::shell_escape(&temp_path)),
+        true,
+        None,
+        false,
+        "",
+        None,
+    )
+    .map_err(|e| e.to_string())
+    .and_then(|(_, stderr, rc)| match rc {
+        0 => fs::read(&temp_path).map_err(|e| format!("failed to read {}: {}", temp_path, e)),
+        rc => Err(curl_failure(rc, &stderr, download)),
+    });
+    utils::remove_temp_file_on_target(&temp_path, true, None);
+    let bytes = result?;
+
+    let checksum = sha256_hex(&bytes);
+    if let Some(want) = want {
+        if want != checksum {
+            return Err(checksum_mismatch(want, &checksum));
+        }
+    }
+
+    let target = |command: &str| {
+        utils::run_shell_on_target(
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )
+        .map_err(|e| e.to_string())
+    };
+    let (stdout, _, rc) = target(&checksum_command(dest))?;
+    if rc == 0 && parse_checksum(&stdout).as_deref() == Some(checksum.as_str()) {
+        if let Some(m) = mode {
+            let (_, stderr, rc) = target(&format!("chmod {} {}", m, utils::shell_escape(dest)))?;
+            if rc != 0 {
+                return Err(format!("failed to chmod {}: {}", dest, stderr.trim()));
+            }
+        }
+        return Ok(Outcome {
+            changed: false,
+            checksum,
+        });
+    }
+
+    utils::write_to_target(
+        &bytes,
+        dest,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        mode,
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(Outcome {
+        changed: true,
+        checksum,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_checksum_must_be_sha256() {
+        let hex = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        assert_eq!(
+            parse_expected_checksum(&format!("sha256:{}", hex)).unwrap(),
+            hex.to_ascii_lowercase()
+        );
+        assert!(parse_expected_checksum(hex).is_err());
+        assert!(parse_expected_checksum("md5:900150983cd24fb0d6963f7d28e17f72").is_err());
+        assert!(parse_expected_checksum("sha256:abc").is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{GitSpec, Register};
+use crate::utils;
+
+const DEFAULT_VERSION: &str = "HEAD";
+
+// `git:` makes `dest` on the target a checkout of `repo` at `version`, a
+// branch, tag or commit. A missing or empty `dest` is initialized first, so a
+// fresh clone and an update take the same path: fetch the one ref, then check
+// out the commit it names on a detached HEAD. Nothing is checked out when HEAD
+// is already there, which keeps the task idempotent.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &GitSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rendered = |value: &str| utils::replace_placeholders(value, vars_map);
+    let repo = rendered(&spec.repo);
+    let dest = rendered(&spec.dest);
+    let version = spec
+        .version
+        .as_deref()
+        .map(rendered)
+        .unwrap_or_else(|| DEFAULT_VERSION.to_string());
+    let key_file = spec.key_file.as_deref().map(rendered);
+    if spec.depth == Some(0) {
+        return Err(format!("Task '{}': git depth must be at least 1", task_name).into());
+    }
+
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &checkout_command(
+            &repo,
+            &dest,
+            &version,
+            spec.depth,
+            spec.force,
+            ssh_command(key_file.as_deref(), spec.accept_hostkey).as_deref(),
+        ),
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    if rc != 0 {
+        return Err(format!(
+            "Task '{}': git checkout of {} at {} failed: exit {}: {}",
+            task_name,
+            repo,
+            version,
+            rc,
+            format!("{} {}", stdout.trim(), stderr.trim()).trim()
+        )
+        .into());
+    }
+
+    let fields: IndexMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.trim_end_matches('\r').split_once('='))
+        .collect();
+    match fields.get("status") {
+        Some(&"not-a-repo") => {
+            return Err(format!(
+                "Task '{}': git dest {} exists and is not a git checkout",
+                task_name, dest
+            )
+            .into());
+        }
+        Some(&"dirty") => {
+            return Err(format!(
+                "Task '{}': git dest {} has local modifications; set force: true to discard them",
+                task_name, dest
+            )
+            .into());
+        }
+        _ => {}
+    }
+    let before = fields
+        .get("before")
+        .copied()
+        .filter(|sha| !sha.is_empty())
+        .map(str::to_string);
+    let after = fields
+        .get("after")
+        .map(|sha| sha.to_string())
+        .ok_or_else(|| {
+            format!(
+                "Task '{}': unexpected git output: {}",
+                task_name,
+                stdout.trim()
+            )
+        })?;
+    let changed = fields.get("changed") == Some(&"true");
+
+    let summary = match (&before, changed) {
+        (None, _) => format!("cloned at {}", short(&after)),
+        (Some(before), true) if *before != after => {
+            format!("{} -> {}", short(before), short(&after))
+        }
+        (Some(_), true) => format!("reset local changes at {}", short(&after)),
+        (Some(_), false) => format!("unchanged at {}", short(&after)),
+    };
+    println!(
+        "{}",
+        format!("> [git] {}@{} -> {} ({})", repo, version, dest, summary).magenta()
+    );
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert(
+                "before".to_string(),
+                before.map_or(Value::Null, Value::from),
+            );
+            fields.insert("after".to_string(), Value::from(after));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+fn short(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+// `GIT_SSH_COMMAND` for a deploy key and/or accepting an unknown host key.
+// BatchMode makes a missing key fail instead of waiting for a passphrase.
+fn ssh_command(key_file: Option<&str>, accept_hostkey: bool) -> Option<String> {
+    if key_file.is_none() && !accept_hostkey {
+        return None;
+    }
+    let mut command = vec!["ssh".to_string(), "-o BatchMode=yes".to_string()];
+    if let Some(key_file) = key_file {
+        command.push(format!(
+            "-i {} -o IdentitiesOnly=yes",
+            utils::shell_escape(key_file)
+        ));
+    }
+    if accept_hostkey {
+        command.push("-o StrictHostKeyChecking=accept-new".to_string());
+    }
+    Some(command.join(" "))
+}
+
+fn is_full_sha(version: &str) -> bool {
+    version.len() == 40 && version.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Prints `status=` for a dest it refuses to touch, otherwise `before=`,
+// `after=` and `changed=`. A full commit SHA that is already present is not
+// fetched again, so re-running a pinned checkout needs no network.
+fn checkout_command(
+    repo: &str,
+    dest: &str,
+    version: &str,
+    depth: Option<u32>,
+    force: bool,
+    ssh_command: Option<&str>,
+) -> String {
+    let mut script = format!(
+        "set -e\nrepo={}; dest={}; version={}\nexport GIT_TERMINAL_PROMPT=0\n",
+        utils::shell_escape(repo),
+        utils::shell_escape(dest),
+        utils::shell_escape(version)
+    );
+    if let Some(ssh_command) = ssh_command {
+        script.push_str(&format!(
+            "export GIT_SSH_COMMAND={}\n",
+            utils::shell_escape(ssh_command)
+        ));
+    }
+    script.push_str(
+        r#"before=
+if [ -e "$dest/.git" ]; then
+  before=$(git -C "$dest" rev-parse -q --verify 'HEAD^{commit}' || true)
+  git -C "$dest" remote set-url origin "$repo" 2>/dev/null || git -C "$dest" remote add origin "$repo"
+elif [ -e "$dest" ] && { [ ! -d "$dest" ] || [ -n "$(ls -A "$dest")" ]; }; then
+  echo status=not-a-repo; exit 0
+else
+  git init -q "$dest"
+  git -C "$dest" remote add origin "$repo"
+fi
+"#,
+    );
+    let fetch = format!(
+        "git -C \"$dest\" fetch -q{} origin \"$version\"\ntarget=$(git -C \"$dest\" rev-parse 'FETCH_HEAD^{{commit}}')\n",
+        depth.map(|d| format!(" --depth {}", d)).unwrap_or_default()
+    );
+    if is_full_sha(version) {
+        script.push_str(&format!(
+            "if git -C \"$dest\" cat-file -e \"$version^{{commit}}\" 2>/dev/null; then\ntarget=$version\nelse\n{}fi\n",
+            fetch
+        ));
+    } else {
+        script.push_str(&fetch);
+    }
+    script.push_str(&format!(
+        r#"dirty=
+if [ -n "$before" ] && [ -n "$(git -C "$dest" status --porcelain --untracked-files=no)" ]; then
+  if [ {} = true ]; then dirty=yes; else echo status=dirty; exit 0; fi
+fi
+changed=false
+if [ "$before" != "$target" ] || [ -n "$dirty" ]; then
+  git -C "$dest" checkout -q --force --detach "$target"
+  changed=true
+fi
+echo "before=$before"
+echo "after=$(git -C "$dest" rev-parse HEAD)"
+echo "changed=$changed""#,
+        force
+    ));
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_command_only_when_needed() {
+        assert_eq!(ssh_command(None, false), None);
+        assert_eq!(
+            ssh_command(Some("/etc/deploy/key"), true).unwrap(),
+            "ssh -o BatchMode=yes -i '/etc/deploy/key' -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new"
+        );
+    }
+
+    #[test]
+    fn pinned_commits_skip_the_fetch_when_present() {
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        assert!(is_full_sha(sha));
+        assert!(!is_full_sha("main"));
+        assert!(!is_full_sha(&sha[..12]));
+        let script = checkout_command("/srv/repo.git", "/srv/app", sha, None, false, None);
+        assert!(
+            script.contains("cat-file -e \"$version^{commit}\""),
+            "{}",
+            script
+        );
+        let script = checkout_command("/srv/repo.git", "/srv/app", "main", Some(1), false, None);
+        assert!(!script.contains("cat-file"), "{}", script);
+        assert!(
+            script.contains("fetch -q --depth 1 origin \"$version\""),
+            "{}",
+            script
+        );
+    }
+}
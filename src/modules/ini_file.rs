@@ -0,0 +1,212 @@
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{EditState, IniFileSpec};
+use crate::modules::lineinfile::{register_changed, EditTarget};
+use crate::utils;
+
+// `ini_file:` sets or removes one `option = value` line in a section of an
+// INI-style file. Only the option's own lines are touched, so comments,
+// blank lines and the formatting of other options survive.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &IniFileSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let section = spec
+        .section
+        .as_deref()
+        .map(|s| utils::replace_placeholders(s, vars_map));
+    let option = utils::replace_placeholders(&spec.option, vars_map);
+    let value = spec
+        .value
+        .as_deref()
+        .map(|v| utils::replace_placeholders(v, vars_map));
+
+    let invalid = |message: &str| -> Box<dyn std::error::Error> {
+        format!("Task '{}': ini_file {}", task_name, message).into()
+    };
+    if option.trim().is_empty() || option.contains(['=', '\n']) || option.starts_with('[') {
+        return Err(invalid("option must be a name without '=' or line breaks"));
+    }
+    if section
+        .as_deref()
+        .is_some_and(|s| s.trim().is_empty() || s.contains([']', '\n']))
+    {
+        return Err(invalid("section must be a name without ']' or line breaks"));
+    }
+    let value = match (spec.state, value) {
+        (EditState::Present, None) => return Err(invalid("state: present requires value")),
+        (EditState::Present, Some(v)) if v.contains('\n') => {
+            return Err(invalid("value must be a single line"));
+        }
+        (EditState::Present, value) => value,
+        (EditState::Absent, _) => None,
+    };
+
+    let target = EditTarget {
+        task_name,
+        action: "ini_file",
+        path: utils::replace_placeholders(&spec.path, vars_map),
+        create: spec.create,
+        mode: spec
+            .mode
+            .as_deref()
+            .map(|m| utils::replace_placeholders(m, vars_map)),
+    };
+    let changed = target.apply(
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        spec.state,
+        |lines| {
+            set_option(lines, section.as_deref(), option.trim(), value.as_deref());
+            Ok(())
+        },
+    )?;
+
+    register_changed(vars_map, register, changed)
+}
+
+// The option name on an `option = value` line, if it is one. Comments and
+// section headers have none.
+fn option_name(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with([';', '#', '[']) {
+        return None;
+    }
+    trimmed.split_once('=').map(|(name, _)| name.trim())
+}
+
+fn section_name(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .map(str::trim)
+}
+
+// Sets `option` in `section` (or before the first section when `None`) to
+// `value`, or removes it when `value` is `None`. A line already holding the
+// value is left as written; later duplicates of the option are dropped. A
+// missing option goes after the last non-blank line of its section, and a
+// missing section is appended to the file.
+pub(crate) fn set_option(
+    lines: &mut Vec<String>,
+    section: Option<&str>,
+    option: &str,
+    value: Option<&str>,
+) {
+    let start = match section {
+        None => 0,
+        Some(section) => match lines.iter().position(|l| section_name(l) == Some(section)) {
+            Some(header) => header + 1,
+            None => {
+                if let Some(value) = value {
+                    if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                        lines.push(String::new());
+                    }
+                    lines.push(format!("[{}]", section));
+                    lines.push(format!("{} = {}", option, value));
+                }
+                return;
+            }
+        },
+    };
+    let end = lines[start..]
+        .iter()
+        .position(|l| section_name(l).is_some())
+        .map_or(lines.len(), |offset| start + offset);
+
+    let matches: Vec<usize> = (start..end)
+        .filter(|&index| option_name(&lines[index]) == Some(option))
+        .collect();
+    let Some(value) = value else {
+        for index in matches.into_iter().rev() {
+            lines.remove(index);
+        }
+        return;
+    };
+    match matches.split_first() {
+        Some((&first, duplicates)) => {
+            for &index in duplicates.iter().rev() {
+                lines.remove(index);
+            }
+            let current = lines[first]
+                .split_once('=')
+                .map(|(_, current)| current.trim());
+            if current != Some(value) {
+                lines[first] = format!("{} = {}", option, value);
+            }
+        }
+        None => {
+            let index = (start..end)
+                .rev()
+                .find(|&index| !lines[index].trim().is_empty())
+                .map_or(start, |index| index + 1);
+            lines.insert(index, format!("{} = {}", option, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edited(text: &str, section: Option<&str>, option: &str, value: Option<&str>) -> String {
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        set_option(&mut lines, section, option, value);
+        lines.join("\n")
+    }
+
+    #[test]
+    fn set_option_updates_in_place_and_keeps_comments() {
+        let text =
+            "; global\n[server]\n# port to bind\nport=8080\nhost = 0.0.0.0\n\n[client]\nport = 1";
+        assert_eq!(
+            edited(text, Some("server"), "port", Some("9090")),
+            "; global\n[server]\n# port to bind\nport = 9090\nhost = 0.0.0.0\n\n[client]\nport = 1"
+        );
+        assert_eq!(edited(text, Some("server"), "port", Some("8080")), text);
+    }
+
+    #[test]
+    fn set_option_inserts_at_end_of_section_or_adds_section() {
+        let text = "[server]\nport = 8080\n\n[client]\nport = 1";
+        assert_eq!(
+            edited(text, Some("server"), "host", Some("::")),
+            "[server]\nport = 8080\nhost = ::\n\n[client]\nport = 1"
+        );
+        assert_eq!(
+            edited(text, Some("log"), "level", Some("info")),
+            "[server]\nport = 8080\n\n[client]\nport = 1\n\n[log]\nlevel = info"
+        );
+        assert_eq!(
+            edited(text, None, "user", Some("app")),
+            "user = app\n[server]\nport = 8080\n\n[client]\nport = 1"
+        );
+    }
+
+    #[test]
+    fn set_option_removes_option_and_duplicates() {
+        let text = "[server]\nport = 1\n;port = 2\nport = 3\n[client]\nport = 4";
+        assert_eq!(
+            edited(text, Some("server"), "port", None),
+            "[server]\n;port = 2\n[client]\nport = 4"
+        );
+        assert_eq!(
+            edited(text, Some("server"), "port", Some("1")),
+            "[server]\nport = 1\n;port = 2\n[client]\nport = 4"
+        );
+        assert_eq!(edited(text, Some("missing"), "port", None), text);
+    }
+}
@@ -0,0 +1,403 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use regex::Regex;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{EditState, LineInFileSpec, Register};
+use crate::utils;
+
+// `lineinfile:` makes sure one line of a text file on the target is present
+// (replacing the last line that matches `regexp:`) or absent. The file is only
+// rewritten when its lines change, so `register:` reports whether it did.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &LineInFileSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let line = spec
+        .line
+        .as_deref()
+        .map(|l| utils::replace_placeholders(l, vars_map));
+    if line.as_deref().is_some_and(|l| l.contains('\n')) {
+        return Err(format!(
+            "Task '{}': lineinfile line must be a single line (use blockinfile for several)",
+            task_name
+        )
+        .into());
+    }
+    let regexp = spec
+        .regexp
+        .as_deref()
+        .map(|r| {
+            compile(
+                task_name,
+                "regexp",
+                &utils::replace_placeholders(r, vars_map),
+            )
+        })
+        .transpose()?;
+    let position = InsertPosition::resolve(
+        task_name,
+        spec.insertafter.as_deref(),
+        spec.insertbefore.as_deref(),
+        vars_map,
+    )?;
+
+    let edit = match (spec.state, line) {
+        (EditState::Present, None) => {
+            return Err(format!(
+                "Task '{}': lineinfile state: present requires line",
+                task_name
+            )
+            .into());
+        }
+        (EditState::Present, Some(line)) => LineEdit::Present {
+            line,
+            regexp,
+            position,
+        },
+        (EditState::Absent, line) => {
+            let matcher = match (regexp, line) {
+                (Some(regexp), _) => LineMatcher::Regex(regexp),
+                (None, Some(line)) => LineMatcher::Exact(line),
+                (None, None) => {
+                    return Err(format!(
+                        "Task '{}': lineinfile state: absent requires regexp or line",
+                        task_name
+                    )
+                    .into());
+                }
+            };
+            LineEdit::Absent(matcher)
+        }
+    };
+
+    let target = EditTarget {
+        task_name,
+        action: "lineinfile",
+        path: utils::replace_placeholders(&spec.path, vars_map),
+        create: spec.create,
+        mode: spec
+            .mode
+            .as_deref()
+            .map(|m| utils::replace_placeholders(m, vars_map)),
+    };
+    let changed = target.apply(
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        spec.state,
+        |lines| {
+            edit.apply(lines);
+            Ok(())
+        },
+    )?;
+
+    register_changed(vars_map, register, changed)
+}
+
+enum LineMatcher {
+    Regex(Regex),
+    Exact(String),
+}
+
+enum LineEdit {
+    Present {
+        line: String,
+        regexp: Option<Regex>,
+        position: InsertPosition,
+    },
+    Absent(LineMatcher),
+}
+
+impl LineEdit {
+    fn apply(&self, lines: &mut Vec<String>) {
+        match self {
+            LineEdit::Present {
+                line,
+                regexp,
+                position,
+            } => {
+                if let Some(index) = regexp
+                    .as_ref()
+                    .and_then(|regexp| lines.iter().rposition(|l| regexp.is_match(l)))
+                {
+                    lines[index] = line.clone();
+                } else if !lines.iter().any(|l| l == line) {
+                    let index = position.index(lines);
+                    lines.insert(index, line.clone());
+                }
+            }
+            LineEdit::Absent(LineMatcher::Regex(regexp)) => lines.retain(|l| !regexp.is_match(l)),
+            LineEdit::Absent(LineMatcher::Exact(line)) => lines.retain(|l| l != line),
+        }
+    }
+}
+
+// Where a new line (or block) goes when nothing in the file is replaced:
+// after/before the last line matching a regex, or at the start/end. A regex
+// that matches nothing falls back to the end of the file.
+pub(crate) enum InsertPosition {
+    End,
+    Start,
+    After(Regex),
+    Before(Regex),
+}
+
+impl InsertPosition {
+    pub(crate) fn resolve(
+        task_name: &str,
+        insertafter: Option<&str>,
+        insertbefore: Option<&str>,
+        vars_map: &IndexMap<String, Value>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rendered = |value: &str| utils::replace_placeholders(value, vars_map);
+        match (insertafter, insertbefore) {
+            (Some(_), Some(_)) => Err(format!(
+                "Task '{}': insertafter and insertbefore cannot be combined",
+                task_name
+            )
+            .into()),
+            (None, None) => Ok(Self::End),
+            (Some(after), None) => match rendered(after).as_str() {
+                "EOF" => Ok(Self::End),
+                pattern => Ok(Self::After(compile(task_name, "insertafter", pattern)?)),
+            },
+            (None, Some(before)) => match rendered(before).as_str() {
+                "BOF" => Ok(Self::Start),
+                pattern => Ok(Self::Before(compile(task_name, "insertbefore", pattern)?)),
+            },
+        }
+    }
+
+    pub(crate) fn index(&self, lines: &[String]) -> usize {
+        let last_match = |regexp: &Regex| lines.iter().rposition(|l| regexp.is_match(l));
+        match self {
+            Self::End => lines.len(),
+            Self::Start => 0,
+            Self::After(regexp) => last_match(regexp).map_or(lines.len(), |index| index + 1),
+            Self::Before(regexp) => last_match(regexp).unwrap_or(lines.len()),
+        }
+    }
+}
+
+fn compile(task_name: &str, key: &str, pattern: &str) -> Result<Regex, Box<dyn std::error::Error>> {
+    Regex::new(pattern).map_err(|e| {
+        format!(
+            "Task '{}': invalid {} regex '{}': {}",
+            task_name, key, pattern, e
+        )
+        .into()
+    })
+}
+
+// The file a `lineinfile:`/`blockinfile:` task edits. Lines are compared
+// without their trailing newline; a rewritten file always ends with one.
+pub(crate) struct EditTarget<'a> {
+    pub task_name: &'a str,
+    pub action: &'a str,
+    pub path: String,
+    pub create: bool,
+    pub mode: Option<String>,
+}
+
+impl EditTarget<'_> {
+    // Reads the file, applies `edit` to its lines, and writes it back through
+    // `write_to_target` only if they changed. Returns whether it did. `edit`
+    // is skipped when the file is missing and nothing should be there.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn apply(
+        &self,
+        is_localhost: bool,
+        session: Option<&Session>,
+        become_enabled: bool,
+        become_method: &str,
+        become_password: Option<&str>,
+        state: EditState,
+        edit: impl FnOnce(&mut Vec<String>) -> Result<(), String>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if let Some(m) = &self.mode {
+            utils::validate_mode(m).map_err(|e| format!("Task '{}': {}", self.task_name, e))?;
+        }
+
+        let existing = utils::read_from_target(
+            &self.path,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?;
+        let original = match existing {
+            Some(bytes) => Some(String::from_utf8(bytes).map_err(|_| {
+                format!(
+                    "Task '{}': {} can only edit text files ({} is not valid UTF-8)",
+                    self.task_name, self.action, self.path
+                )
+            })?),
+            None if state == EditState::Absent => None,
+            None if self.create => None,
+            None => {
+                return Err(format!(
+                    "Task '{}': {} {} does not exist (set create: true to create it)",
+                    self.task_name, self.action, self.path
+                )
+                .into());
+            }
+        };
+
+        let before = original.as_deref().map(split_lines).unwrap_or_default();
+        let mut after = before.clone();
+        if original.is_some() || state == EditState::Present {
+            edit(&mut after).map_err(|e| {
+                format!(
+                    "Task '{}': {} {}: {}",
+                    self.task_name, self.action, self.path, e
+                )
+            })?;
+        }
+        let changed = after != before || (original.is_none() && !after.is_empty());
+
+        println!(
+            "{}",
+            format!(
+                "> [{}] {} ({})",
+                self.action,
+                self.path,
+                if changed { "changed" } else { "unchanged" }
+            )
+            .magenta()
+        );
+        if changed {
+            utils::rewrite_file_on_target(
+                join_lines(&after).as_bytes(),
+                &self.path,
+                is_localhost,
+                session,
+                become_enabled,
+                become_method,
+                become_password,
+                self.mode.as_deref(),
+            )?;
+        }
+        Ok(changed)
+    }
+}
+
+fn split_lines(text: &str) -> Vec<String> {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    if text.is_empty() {
+        return Vec::new();
+    }
+    text.split('\n').map(str::to_string).collect()
+}
+
+fn join_lines(lines: &[String]) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+    format!("{}\n", lines.join("\n"))
+}
+
+pub(crate) fn register_changed(
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    changed: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(reg) = register {
+        let value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed,
+            failed: false,
+        })?;
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        split_lines(text)
+    }
+
+    fn present(line: &str, regexp: Option<&str>, position: InsertPosition) -> LineEdit {
+        LineEdit::Present {
+            line: line.to_string(),
+            regexp: regexp.map(|r| Regex::new(r).unwrap()),
+            position,
+        }
+    }
+
+    #[test]
+    fn present_replaces_last_match_or_inserts() {
+        let mut file = lines("PermitRootLogin yes\n#PermitRootLogin no\nPort 22\n");
+        present(
+            "PermitRootLogin no",
+            Some("^#?PermitRootLogin"),
+            InsertPosition::End,
+        )
+        .apply(&mut file);
+        assert_eq!(
+            file,
+            lines("PermitRootLogin yes\nPermitRootLogin no\nPort 22\n")
+        );
+
+        let mut file = lines("Port 22\n");
+        present(
+            "PasswordAuthentication no",
+            Some("^PasswordAuthentication"),
+            InsertPosition::Start,
+        )
+        .apply(&mut file);
+        assert_eq!(file, lines("PasswordAuthentication no\nPort 22\n"));
+    }
+
+    #[test]
+    fn present_leaves_an_existing_line_alone() {
+        let original = lines("127.0.0.1 localhost\n10.0.0.5 db\n");
+        let mut file = original.clone();
+        present("10.0.0.5 db", Some("^10\\.0\\.0\\.9"), InsertPosition::End).apply(&mut file);
+        assert_eq!(file, original);
+    }
+
+    #[test]
+    fn insert_position_uses_last_match_and_falls_back_to_end() {
+        let file = lines("[a]\nx=1\n[b]\ny=2\n");
+        let after = InsertPosition::After(Regex::new("^\\[").unwrap());
+        assert_eq!(after.index(&file), 3);
+        let before = InsertPosition::Before(Regex::new("^\\[b\\]").unwrap());
+        assert_eq!(before.index(&file), 2);
+        let missing = InsertPosition::Before(Regex::new("^\\[c\\]").unwrap());
+        assert_eq!(missing.index(&file), 4);
+    }
+
+    #[test]
+    fn absent_removes_every_match() {
+        let mut file = lines("a\nold 1\nb\nold 2\n");
+        LineEdit::Absent(LineMatcher::Regex(Regex::new("^old").unwrap())).apply(&mut file);
+        assert_eq!(file, lines("a\nb\n"));
+    }
+
+    #[test]
+    fn lines_round_trip_with_a_final_newline() {
+        assert_eq!(join_lines(&lines("a\nb")), "a\nb\n");
+        assert_eq!(join_lines(&lines("a\n\nb\n")), "a\n\nb\n");
+        assert!(lines("").is_empty());
+    }
+}
@@ -109,7 +109,7 @@ fn collect_visible(
         }
         out.push((depth, task_name.clone(), effective.clone()));
 
-        if let Some(include_file) = &task.include_tasks {
+        if task.include_tasks.is_some() || task.block.is_some() {
             if let Some(task_vars) = &task.vars {
                 for (key, value) in task_vars {
                     let value_evaluated = utils::replace_placeholders_value_result(
@@ -121,6 +121,9 @@ fn collect_visible(
                     vars_map.insert(key.clone(), value_evaluated);
                 }
             }
+        }
+
+        if let Some(include_file) = &task.include_tasks {
             let include_path = deploy_file_dir.join(include_file);
             let children = include_tasks::process(include_path.to_str().unwrap());
             let mut nested = collect_visible(
@@ -134,6 +137,40 @@ fn collect_visible(
             )?;
             out.append(&mut nested);
         }
+
+        if let Some(block) = &task.block {
+            let mut nested = collect_visible(
+                block,
+                &effective,
+                config,
+                state,
+                depth + 1,
+                deploy_file_dir,
+                vars_map,
+            )?;
+            out.append(&mut nested);
+
+            let recovery = filter::merge_tags(&effective, Some(&["always".to_string()]));
+            for (prefix, tasks) in [("rescue", &task.rescue), ("always", &task.always)] {
+                let Some(tasks) = tasks else {
+                    continue;
+                };
+                let nested = collect_visible(
+                    tasks,
+                    &recovery,
+                    config,
+                    state,
+                    depth + 1,
+                    deploy_file_dir,
+                    vars_map,
+                )?;
+                out.extend(
+                    nested
+                        .into_iter()
+                        .map(|(depth, name, tags)| (depth, format!("[{}] {}", prefix, name), tags)),
+                );
+            }
+        }
     }
     Ok(out)
 }
@@ -1,14 +1,34 @@
+pub mod archive;
+pub mod assert;
+pub mod async_job;
+pub mod blockinfile;
 pub mod command;
+pub mod compose;
 pub mod copy;
+pub mod cron;
 pub mod debug;
 pub mod env_file;
+pub mod fetch;
 pub mod file;
 pub mod filter;
+pub mod get_url;
+pub mod git;
 pub mod include_tasks;
+pub mod ini_file;
+pub mod lineinfile;
 pub mod list_tasks;
+pub mod patch;
+pub mod pause;
+pub mod run_state;
+pub mod script;
+pub mod stat;
 pub mod step;
+pub mod sysctl;
 pub mod systemd;
 pub mod template;
+pub mod uri;
+pub mod user;
 pub mod vars_file;
 pub mod verify;
+pub mod wait_for;
 pub mod when;
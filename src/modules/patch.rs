@@ -0,0 +1,461 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use serde_yaml::{Mapping, Value as Node};
+use ssh2::Session;
+
+use crate::common::{PatchOperation, PatchSpec, Register};
+use crate::utils;
+
+// The document format a `json_patch:` or `yaml_patch:` task edits. Both are
+// held as YAML nodes, whose mappings keep their keys in file order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    fn action(self) -> &'static str {
+        match self {
+            Format::Json => "json_patch",
+            Format::Yaml => "yaml_patch",
+        }
+    }
+
+    fn parse(self, text: &str) -> Result<Node, String> {
+        if text.trim().is_empty() {
+            return Ok(Node::Mapping(Mapping::new()));
+        }
+        match self {
+            Format::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+        }
+    }
+
+    // JSON keeps the indent of the original file's first indented line.
+    fn render(self, document: &Node, original: &str) -> Result<String, String> {
+        match self {
+            Format::Json => {
+                let indent = original
+                    .lines()
+                    .map(|line| &line[..line.len() - line.trim_start().len()])
+                    .find(|indent| !indent.is_empty())
+                    .unwrap_or("  ");
+                let mut out = Vec::new();
+                let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+                let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+                serde::Serialize::serialize(document, &mut serializer)
+                    .map_err(|e| e.to_string())?;
+                out.push(b'\n');
+                String::from_utf8(out).map_err(|e| e.to_string())
+            }
+            Format::Yaml => serde_yaml::to_string(document).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+// A path as written in the task, split into segments. Numeric segments index
+// lists; `-` appends to one.
+#[derive(Debug, PartialEq)]
+struct DocPath {
+    text: String,
+    segments: Vec<String>,
+}
+
+impl DocPath {
+    fn parse(text: &str) -> Result<Self, String> {
+        let segments: Vec<String> = if let Some(pointer) = text.strip_prefix('/') {
+            pointer
+                .split('/')
+                .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+                .collect()
+        } else {
+            if text.is_empty() || text.split('.').any(str::is_empty) {
+                return Err(format!(
+                    "path '{}' must be a JSON pointer like /a/b or dotted like a.b",
+                    text
+                ));
+            }
+            text.split('.').map(str::to_string).collect()
+        };
+        Ok(DocPath {
+            text: text.to_string(),
+            segments,
+        })
+    }
+}
+
+fn list_index(segment: &str, len: usize, allow_end: bool) -> Option<usize> {
+    if allow_end && segment == "-" {
+        return Some(len);
+    }
+    segment
+        .parse::<usize>()
+        .ok()
+        .filter(|&index| index < len || (allow_end && index == len))
+}
+
+// Sets the node at `path`, creating missing mappings on the way. Returns
+// the node it replaced.
+fn set(document: &mut Node, path: &DocPath, value: Node) -> Result<Option<Node>, String> {
+    let (last, parents) = path
+        .segments
+        .split_last()
+        .expect("paths have at least one segment");
+    let not_container = |segment: &str| {
+        format!(
+            "cannot set {}: '{}' is not an object or a list",
+            path.text, segment
+        )
+    };
+    let mut node = document;
+    for segment in parents {
+        if node.is_null() {
+            *node = Node::Mapping(Mapping::new());
+        }
+        node = match node {
+            Node::Mapping(map) => map
+                .entry(Node::String(segment.clone()))
+                .or_insert_with(|| Node::Mapping(Mapping::new())),
+            Node::Sequence(list) => {
+                let index = list_index(segment, list.len(), false).ok_or_else(|| {
+                    format!(
+                        "cannot set {}: '{}' is not an index of the list",
+                        path.text, segment
+                    )
+                })?;
+                &mut list[index]
+            }
+            _ => return Err(not_container(segment)),
+        };
+    }
+    if node.is_null() {
+        *node = Node::Mapping(Mapping::new());
+    }
+    match node {
+        Node::Mapping(map) => Ok(map.insert(Node::String(last.clone()), value)),
+        Node::Sequence(list) => {
+            let index = list_index(last, list.len(), true).ok_or_else(|| {
+                format!(
+                    "cannot set {}: '{}' is not an index of the list (use '-' to append)",
+                    path.text, last
+                )
+            })?;
+            if index == list.len() {
+                list.push(value);
+                Ok(None)
+            } else {
+                Ok(Some(std::mem::replace(&mut list[index], value)))
+            }
+        }
+        _ => Err(not_container(last)),
+    }
+}
+
+// Removes the node at `path`, keeping the order of its siblings. Returns the
+// removed node; a path that does not exist is not an error.
+fn delete(document: &mut Node, path: &DocPath) -> Option<Node> {
+    let (last, parents) = path.segments.split_last()?;
+    let mut node = document;
+    for segment in parents {
+        node = match node {
+            Node::Mapping(map) => map.get_mut(segment.as_str())?,
+            Node::Sequence(list) => {
+                let index = list_index(segment, list.len(), false)?;
+                &mut list[index]
+            }
+            _ => return None,
+        };
+    }
+    match node {
+        Node::Mapping(map) => map.shift_remove(last.as_str()),
+        Node::Sequence(list) => list_index(last, list.len(), false).map(|index| list.remove(index)),
+        _ => None,
+    }
+}
+
+// Renders the strings in a YAML value the way `vars:` values are rendered,
+// so `"{{ port }}"` stays a number. Mapping order is kept.
+fn render_value(
+    value: &Node,
+    vars_map: &IndexMap<String, Value>,
+    no_log: bool,
+) -> Result<Node, Box<dyn std::error::Error>> {
+    Ok(match value {
+        Node::String(text) => serde_yaml::to_value(utils::replace_placeholders_value_result(
+            &Value::String(text.clone()),
+            vars_map,
+            no_log,
+        )?)?,
+        Node::Sequence(items) => Node::Sequence(
+            items
+                .iter()
+                .map(|item| render_value(item, vars_map, no_log))
+                .collect::<Result<_, _>>()?,
+        ),
+        Node::Mapping(map) => {
+            let mut rendered = Mapping::new();
+            for (key, item) in map {
+                rendered.insert(key.clone(), render_value(item, vars_map, no_log)?);
+            }
+            Node::Mapping(rendered)
+        }
+        other => other.clone(),
+    })
+}
+
+enum Operation {
+    Set(DocPath, Node),
+    Delete(DocPath),
+}
+
+fn resolve(
+    operation: &PatchOperation,
+    vars_map: &IndexMap<String, Value>,
+    no_log: bool,
+) -> Result<Operation, Box<dyn std::error::Error>> {
+    match (&operation.set, &operation.delete, &operation.value) {
+        (Some(path), None, Some(value)) => Ok(Operation::Set(
+            DocPath::parse(&utils::replace_placeholders(path, vars_map))?,
+            render_value(value, vars_map, no_log)?,
+        )),
+        (Some(_), None, None) => Err("set requires value".into()),
+        (None, Some(path), None) => Ok(Operation::Delete(DocPath::parse(
+            &utils::replace_placeholders(path, vars_map),
+        )?)),
+        (None, Some(_), Some(_)) => Err("delete does not take a value".into()),
+        _ => Err("each operation needs exactly one of set or delete".into()),
+    }
+}
+
+fn inline(node: Option<&Node>) -> String {
+    match node {
+        Some(node) => serde_json::to_string(node).unwrap_or_else(|_| format!("{:?}", node)),
+        None => "(absent)".to_string(),
+    }
+}
+
+// `json_patch:` and `yaml_patch:` read a structured file from the target,
+// apply the operations in order, and write it back only if the document
+// changed. Each path that changed is printed and registered as `diff`.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &PatchSpec,
+    format: Format,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let action = format.action();
+    let path = utils::replace_placeholders(&spec.path, vars_map);
+    let fail = |message: String| -> Box<dyn std::error::Error> {
+        format!("Task '{}': {} {}: {}", task_name, action, path, message).into()
+    };
+    if spec.operations.is_empty() {
+        return Err(fail("operations must not be empty".to_string()));
+    }
+    let mode = spec
+        .mode
+        .as_deref()
+        .map(|m| utils::replace_placeholders(m, vars_map));
+    if let Some(m) = &mode {
+        utils::validate_mode(m).map_err(|e| format!("Task '{}': {}", task_name, e))?;
+    }
+    let operations = spec
+        .operations
+        .iter()
+        .map(|operation| resolve(operation, vars_map, no_log))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| fail(e.to_string()))?;
+
+    let existing = utils::read_from_target(
+        &path,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    let missing = existing.is_none();
+    let original = match existing {
+        Some(bytes) => String::from_utf8(bytes).map_err(|_| fail("is not valid UTF-8".into()))?,
+        None if spec.create => String::new(),
+        None => {
+            return Err(fail(
+                "does not exist (set create: true to create it)".to_string(),
+            ));
+        }
+    };
+    let mut document = format.parse(&original).map_err(fail)?;
+    let before = document.clone();
+
+    let mut diff = Vec::new();
+    for operation in operations {
+        let (doc_path, old, new) = match operation {
+            Operation::Set(doc_path, value) => {
+                let old = set(&mut document, &doc_path, value.clone()).map_err(&fail)?;
+                (doc_path, old, Some(value))
+            }
+            Operation::Delete(doc_path) => {
+                let old = delete(&mut document, &doc_path);
+                (doc_path, old, None)
+            }
+        };
+        if old != new {
+            diff.push((doc_path.text, old, new));
+        }
+    }
+    let changed = document != before || missing;
+
+    println!(
+        "{}",
+        format!(
+            "> [{}] {} ({})",
+            action,
+            path,
+            if changed { "changed" } else { "unchanged" }
+        )
+        .magenta()
+    );
+    for (doc_path, old, new) in &diff {
+        let line = if no_log {
+            format!("  {}: changed", doc_path)
+        } else {
+            format!(
+                "  {}: {} -> {}",
+                doc_path,
+                inline(old.as_ref()),
+                inline(new.as_ref())
+            )
+        };
+        println!("{}", line.bright_black());
+    }
+    if changed {
+        let rendered = format.render(&document, &original).map_err(fail)?;
+        utils::rewrite_file_on_target(
+            rendered.as_bytes(),
+            &path,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+            mode.as_deref(),
+        )?;
+    }
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            let entries = diff
+                .iter()
+                .map(|(doc_path, old, new)| {
+                    Ok(serde_json::json!({
+                        "path": doc_path,
+                        "before": serde_json::to_value(old)?,
+                        "after": serde_json::to_value(new)?,
+                    }))
+                })
+                .collect::<Result<Vec<_>, serde_json::Error>>()?;
+            fields.insert("diff".to_string(), Value::Array(entries));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(text: &str) -> Node {
+        Format::Yaml.parse(text).unwrap()
+    }
+
+    fn path(text: &str) -> DocPath {
+        DocPath::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parses_pointer_and_dotted_paths() {
+        assert_eq!(path("/a~1b/c~0d/0").segments, vec!["a/b", "c~d", "0"]);
+        assert_eq!(
+            path("services.web.image").segments,
+            vec!["services", "web", "image"]
+        );
+        assert!(DocPath::parse("a..b").is_err());
+        assert!(DocPath::parse("").is_err());
+    }
+
+    #[test]
+    fn set_keeps_key_order_and_creates_parents() {
+        let mut document = doc("b: 1\na: 2\n");
+        assert_eq!(
+            set(&mut document, &path("b"), Node::from(3)).unwrap(),
+            Some(Node::from(1))
+        );
+        set(&mut document, &path("c.d"), Node::from("x")).unwrap();
+        assert_eq!(
+            serde_yaml::to_string(&document).unwrap(),
+            "b: 3\na: 2\nc:\n  d: x\n"
+        );
+    }
+
+    #[test]
+    fn set_indexes_and_appends_to_lists() {
+        let mut document = doc("ports: [80, 443]\n");
+        set(&mut document, &path("/ports/1"), Node::from(8443)).unwrap();
+        set(&mut document, &path("/ports/-"), Node::from(9000)).unwrap();
+        assert_eq!(document, doc("ports: [80, 8443, 9000]\n"));
+        let err = set(&mut document, &path("ports.7"), Node::from(1)).unwrap_err();
+        assert!(err.contains("'7' is not an index of the list"), "{}", err);
+        let err = set(&mut document, &path("ports.0.name"), Node::from(1)).unwrap_err();
+        assert!(err.contains("'name' is not an object or a list"), "{}", err);
+    }
+
+    #[test]
+    fn delete_removes_in_place_and_ignores_missing_paths() {
+        let mut document = doc("a: 1\nb: 2\nc: [x, y]\n");
+        assert_eq!(delete(&mut document, &path("a")), Some(Node::from(1)));
+        assert_eq!(delete(&mut document, &path("c.0")), Some(Node::from("x")));
+        assert_eq!(delete(&mut document, &path("missing.key")), None);
+        assert_eq!(serde_yaml::to_string(&document).unwrap(), "b: 2\nc:\n- y\n");
+    }
+
+    #[test]
+    fn json_render_keeps_order_and_indent() {
+        let original = "{\n    \"name\": \"app\",\n    \"port\": 80\n}\n";
+        let mut document = Format::Json.parse(original).unwrap();
+        set(&mut document, &path("port"), Node::from(8080)).unwrap();
+        assert_eq!(
+            Format::Json.render(&document, original).unwrap(),
+            "{\n    \"name\": \"app\",\n    \"port\": 8080\n}\n"
+        );
+    }
+
+    #[test]
+    fn render_value_keeps_types_from_vars() {
+        let mut vars = IndexMap::new();
+        vars.insert("port".to_string(), Value::from(8080));
+        let value = doc("listen: \"{{ port }}\"\nhost: \"h-{{ port }}\"\n");
+        assert_eq!(
+            render_value(&value, &vars, false).unwrap(),
+            doc("listen: 8080\nhost: h-8080\n")
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use std::io::{self, IsTerminal};
+use std::thread;
+use std::time::Duration;
+
+use crate::common::{NonInteractive, PauseSpec, Register};
+use crate::modules::step;
+use crate::utils;
+
+const DEFAULT_PROMPT: &str = "Press Enter to continue";
+
+// `pause:` either sleeps for `seconds` (which Enter cuts short on a
+// terminal), or shows `prompt` and waits for a line of input, which is
+// registered as `user_input`. Without a terminal on stdin
+// there is nobody to answer, so the task fails unless `non_interactive:
+// default` says to use `default:` instead.
+pub fn process(
+    task_name: &str,
+    spec: &PauseSpec,
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_spec(spec).map_err(|e| format!("Task '{}': pause {}", task_name, e))?;
+
+    if let Some(seconds) = spec.seconds {
+        let duration = format!("{} second{}", seconds, if seconds == 1 { "" } else { "s" });
+        // On a terminal, Enter ends the pause early.
+        if io::stdin().is_terminal() {
+            println!(
+                "{}",
+                format!("> [pause] {} (press Enter to continue)", duration).magenta()
+            );
+            step::wait_for_line(Duration::from_secs(seconds))?;
+        } else {
+            println!("{}", format!("> [pause] {}", duration).magenta());
+            thread::sleep(Duration::from_secs(seconds));
+        }
+        return register_input(vars_map, register, None);
+    }
+
+    let prompt = spec
+        .prompt
+        .as_deref()
+        .map(|p| utils::replace_placeholders(p, vars_map))
+        .unwrap_or_else(|| DEFAULT_PROMPT.to_string());
+    let default = spec
+        .default
+        .as_deref()
+        .map(|d| utils::replace_placeholders(d, vars_map));
+
+    let input = if io::stdin().is_terminal() {
+        let message = match &default {
+            Some(default) if !no_log && spec.echo != Some(false) => {
+                format!("{} [{}]: ", prompt, default)
+            }
+            _ => format!("{}: ", prompt),
+        };
+        let line = if spec.echo == Some(false) {
+            rpassword::prompt_password(message)?
+        } else {
+            step::read_line(&message)?.ok_or_else(|| {
+                format!(
+                    "Task '{}': pause got no input (stdin was closed)",
+                    task_name
+                )
+            })?
+        };
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        match default {
+            Some(default) if line.is_empty() => default,
+            _ => line,
+        }
+    } else {
+        match (spec.non_interactive, default) {
+            (NonInteractive::Default, Some(default)) => {
+                println!(
+                    "{}",
+                    format!("> [pause] {} (no terminal, using the default)", prompt).magenta()
+                );
+                default
+            }
+            _ => {
+                return Err(format!(
+                    "Task '{}': pause needs a terminal to answer '{}' (set non_interactive: default to use default: instead)",
+                    task_name, prompt
+                )
+                .into());
+            }
+        }
+    };
+
+    register_input(vars_map, register, Some(input))
+}
+
+// An answer typed at a hidden prompt is a secret, so the task runs as
+// `no_log` and the answer stays out of the output and the run state file.
+pub fn is_secret(spec: &PauseSpec) -> bool {
+    spec.echo == Some(false)
+}
+
+fn check_spec(spec: &PauseSpec) -> Result<(), &'static str> {
+    if spec.seconds.is_some()
+        && (spec.prompt.is_some() || spec.echo.is_some() || spec.default.is_some())
+    {
+        return Err("seconds cannot be combined with prompt, echo or default");
+    }
+    if spec.non_interactive == NonInteractive::Default && spec.default.is_none() {
+        return Err("non_interactive: default requires default");
+    }
+    Ok(())
+}
+
+fn register_input(
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    input: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(reg) = register else {
+        return Ok(());
+    };
+    let mut value = serde_json::to_value(Register {
+        stdout: String::new(),
+        stderr: String::new(),
+        rc: 0,
+        changed: false,
+        failed: false,
+    })?;
+    if let (Value::Object(fields), Some(input)) = (&mut value, input) {
+        fields.insert("user_input".to_string(), Value::from(input));
+    }
+    vars_map.insert(reg.clone(), value);
+    println!("{}", format!("Registering output to: {}", reg).yellow());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(yaml: &str) -> PauseSpec {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn check_spec_rejects_conflicting_keys() {
+        assert!(check_spec(&spec("seconds: 5\n")).is_ok());
+        assert!(check_spec(&spec(
+            "prompt: Continue?\ndefault: yes\nnon_interactive: default\n"
+        ))
+        .is_ok());
+        assert!(check_spec(&spec("seconds: 5\nprompt: Continue?\n")).is_err());
+        assert!(check_spec(&spec("prompt: Continue?\nnon_interactive: default\n")).is_err());
+    }
+}
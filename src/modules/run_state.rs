@@ -0,0 +1,346 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// The run state file records how far the main `tasks:` section got, so
+// `--resume` can pick up after the last completed task with the same variable
+// context. A task position is its index path: the top-level index followed by
+// the index inside each `include_tasks` file that led to it. A looped include
+// adds the iteration index before the index inside the file.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Snapshot {
+    pub deployment: usize,
+    pub host: String,
+    pub task_path: Option<Vec<usize>>,
+    pub task_name: Option<String>,
+    pub vars: IndexMap<String, Value>,
+    // no_log values never reach the file; only their names are kept so a
+    // resumed run can say which vars have to be set again.
+    pub omitted_vars: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct RunState {
+    file: PathBuf,
+    snapshot: Snapshot,
+    recording: bool,
+    resume_target: Option<Snapshot>,
+    skip_until: Option<Vec<usize>>,
+}
+
+pub fn state_file_path(deploy_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.run-state.json", deploy_file))
+}
+
+impl RunState {
+    pub fn new(file: PathBuf) -> Self {
+        Self {
+            file,
+            snapshot: Snapshot::default(),
+            recording: false,
+            resume_target: None,
+            skip_until: None,
+        }
+    }
+
+    pub fn resume(file: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(&file).map_err(|error| {
+            if error.kind() == io::ErrorKind::NotFound {
+                format!(
+                    "--resume: no run state at {} (it is written by a failed run and removed after a successful one)",
+                    file.display()
+                )
+            } else {
+                format!("--resume: failed to read {}: {}", file.display(), error)
+            }
+        })?;
+        let snapshot: Snapshot = serde_json::from_str(&contents).map_err(|error| {
+            format!("--resume: invalid run state {}: {}", file.display(), error)
+        })?;
+        let mut state = Self::new(file);
+        state.resume_target = Some(snapshot);
+        Ok(state)
+    }
+
+    pub fn skips_deployment(&self, deployment: usize) -> bool {
+        self.resume_target
+            .as_ref()
+            .is_some_and(|target| deployment < target.deployment)
+    }
+
+    pub fn skips_host(&self, deployment: usize, host: &str) -> bool {
+        self.resume_target
+            .as_ref()
+            .is_some_and(|target| deployment == target.deployment && host != target.host)
+    }
+
+    // Called once the hosts of the first `deployments_done` deployments have
+    // been processed, so a resume target that never matched a host is
+    // reported instead of silently running everything after it.
+    pub fn check_resume_reached(
+        &self,
+        deployments_done: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.resume_target {
+            Some(target) if target.deployment < deployments_done => Err(format!(
+                "--resume: host '{}' of deployment {} recorded in {} was not found in the deploy file",
+                target.host,
+                target.deployment + 1,
+                self.file.display()
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn start_host(
+        &mut self,
+        deployment: usize,
+        host: &str,
+        vars_map: &mut IndexMap<String, Value>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.snapshot.deployment = deployment;
+        self.snapshot.host = host.to_string();
+        self.snapshot.task_path = None;
+        self.snapshot.task_name = None;
+        self.skip_until = None;
+
+        if let Some(target) = self
+            .resume_target
+            .take_if(|target| target.deployment == deployment && target.host == host)
+        {
+            match &target.task_name {
+                Some(name) => println!(
+                    "{}",
+                    format!("Resuming on host {} after task: {}\n", host, name).blue()
+                ),
+                None => println!("{}", format!("Resuming on host {}\n", host).blue()),
+            }
+            if !target.omitted_vars.is_empty() {
+                println!(
+                    "{}",
+                    format!(
+                        "Run state omits no_log vars: {}\n",
+                        target.omitted_vars.join(", ")
+                    )
+                    .yellow()
+                );
+            }
+            vars_map.extend(target.vars.clone());
+            // Until the resumed position is passed, the last completed task
+            // is still the one from the previous run.
+            self.skip_until = target.task_path.clone();
+            self.snapshot.task_path = target.task_path;
+            self.snapshot.task_name = target.task_name;
+            self.snapshot.vars = target.vars;
+            self.snapshot.omitted_vars = target.omitted_vars;
+        }
+
+        self.save()
+    }
+
+    // Only the main `tasks:` section is recorded; recovery sections run after
+    // a failure and must not move the resume point. Returns the previous
+    // setting so nested recovery (block `rescue:`/`always:`) can restore it.
+    pub fn set_recording(&mut self, recording: bool) -> bool {
+        std::mem::replace(&mut self.recording, recording)
+    }
+
+    pub fn skips_task(&mut self, task_path: &[usize]) -> bool {
+        if !self.recording {
+            return false;
+        }
+        let Some(completed) = &self.skip_until else {
+            return false;
+        };
+        // An ancestor of the completed task (an include) runs so its
+        // children can be skipped one by one.
+        if task_path.len() < completed.len() && completed.starts_with(task_path) {
+            return false;
+        }
+        if task_path <= completed.as_slice() {
+            return true;
+        }
+        self.skip_until = None;
+        false
+    }
+
+    pub fn complete_task(
+        &mut self,
+        task_path: &[usize],
+        task_name: &str,
+        keys: &[&str],
+        vars_map: &IndexMap<String, Value>,
+        no_log: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.recording {
+            return Ok(());
+        }
+        for key in keys {
+            let Some(value) = vars_map.get(*key) else {
+                continue;
+            };
+            if no_log {
+                self.snapshot.vars.shift_remove(*key);
+                if !self.snapshot.omitted_vars.iter().any(|name| name == key) {
+                    self.snapshot.omitted_vars.push(key.to_string());
+                }
+            } else {
+                self.snapshot.omitted_vars.retain(|name| name != key);
+                self.snapshot.vars.insert(key.to_string(), value.clone());
+            }
+        }
+        self.snapshot.task_path = Some(task_path.to_vec());
+        self.snapshot.task_name = Some(task_name.to_string());
+        self.save()
+    }
+
+    pub fn finish(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match fs::remove_file(&self.file) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => {
+                Err(format!("Failed to remove {}: {}", self.file.display(), error).into())
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        write_snapshot(&self.file, &self.snapshot)
+    }
+}
+
+fn write_snapshot(file: &Path, snapshot: &Snapshot) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = serde_json::to_string_pretty(snapshot)?;
+    fs::write(file, contents)
+        .map_err(|error| format!("Failed to write run state {}: {}", file.display(), error).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn scratch_file(tag: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dh-run-state-{}-{}.json", tag, std::process::id()))
+    }
+
+    fn resuming_after(task_path: Vec<usize>) -> RunState {
+        let mut state = RunState::new(scratch_file("unused"));
+        state.recording = true;
+        state.skip_until = Some(task_path);
+        state
+    }
+
+    #[test]
+    fn skips_tasks_up_to_and_including_the_completed_one() {
+        let mut state = resuming_after(vec![2]);
+        assert!(state.skips_task(&[0]));
+        assert!(state.skips_task(&[2]));
+        assert!(!state.skips_task(&[3]));
+        // Once past the resume point, nothing else is skipped.
+        assert!(!state.skips_task(&[1]));
+    }
+
+    #[test]
+    fn descends_into_the_include_holding_the_completed_task() {
+        let mut state = resuming_after(vec![1, 2]);
+        assert!(state.skips_task(&[0]));
+        assert!(!state.skips_task(&[1]));
+        assert!(state.skips_task(&[1, 0]));
+        assert!(state.skips_task(&[1, 2]));
+        assert!(!state.skips_task(&[1, 3]));
+    }
+
+    #[test]
+    fn later_iterations_of_a_looped_include_still_run() {
+        // Task 1 is an include looping over two items; the run stopped after
+        // the first child of the second iteration.
+        let mut state = resuming_after(vec![1, 1, 0]);
+        assert!(!state.skips_task(&[1]));
+        assert!(state.skips_task(&[1, 0, 0]));
+        assert!(state.skips_task(&[1, 0, 1]));
+        assert!(state.skips_task(&[1, 1, 0]));
+        assert!(!state.skips_task(&[1, 1, 1]));
+    }
+
+    #[test]
+    fn recovery_sections_are_never_skipped_or_recorded() {
+        let mut state = resuming_after(vec![4]);
+        state.recording = false;
+        assert!(!state.skips_task(&[0]));
+        state
+            .complete_task(&[0], "Cleanup", &[], &IndexMap::new(), false)
+            .unwrap();
+        assert_eq!(state.snapshot.task_path, None);
+    }
+
+    #[test]
+    fn no_log_vars_are_omitted_by_name() {
+        let file = scratch_file("no-log");
+        let mut state = RunState::new(file.clone());
+        state.recording = true;
+        let vars = IndexMap::from([
+            ("build".to_string(), json!({"stdout": "42"})),
+            ("token".to_string(), json!("secret-token-value")),
+        ]);
+        state
+            .complete_task(&[0], "Build", &["build"], &vars, false)
+            .unwrap();
+        state
+            .complete_task(&[1], "Token", &["token"], &vars, true)
+            .unwrap();
+
+        let written = fs::read_to_string(&file).unwrap();
+        assert!(!written.contains("secret-token-value"));
+        let snapshot: Snapshot = serde_json::from_str(&written).unwrap();
+        assert_eq!(snapshot.vars["build"], json!({"stdout": "42"}));
+        assert_eq!(snapshot.omitted_vars, vec!["token".to_string()]);
+        assert_eq!(snapshot.task_path, Some(vec![1]));
+        assert_eq!(snapshot.task_name.as_deref(), Some("Token"));
+
+        state.finish().unwrap();
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn resume_restores_vars_at_the_recorded_host() {
+        let file = scratch_file("resume");
+        let snapshot = Snapshot {
+            deployment: 1,
+            host: "web2".to_string(),
+            task_path: Some(vec![0, 1]),
+            task_name: Some("Build".to_string()),
+            vars: IndexMap::from([("build".to_string(), json!("42"))]),
+            omitted_vars: Vec::new(),
+        };
+        write_snapshot(&file, &snapshot).unwrap();
+
+        let mut state = RunState::resume(file.clone()).unwrap();
+        assert!(state.skips_deployment(0));
+        assert!(!state.skips_deployment(1));
+        assert!(state.skips_host(1, "web1"));
+        assert!(!state.skips_host(1, "web2"));
+
+        let mut vars_map = IndexMap::new();
+        state.start_host(1, "web2", &mut vars_map).unwrap();
+        assert_eq!(vars_map["build"], json!("42"));
+        assert!(!state.skips_host(1, "web3"));
+        state.set_recording(true);
+        assert!(state.skips_task(&[0, 1]));
+        assert!(!state.skips_task(&[0, 2]));
+
+        state.finish().unwrap();
+    }
+
+    #[test]
+    fn resume_without_a_state_file_is_an_error() {
+        let err = RunState::resume(scratch_file("missing")).unwrap_err();
+        assert!(err.to_string().contains("no run state"));
+    }
+}
@@ -0,0 +1,148 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+use std::fs;
+use std::path::Path;
+
+use crate::common::ScriptSpec;
+use crate::modules::command;
+use crate::utils;
+
+// `script:` uploads a local script to a temp file on the target, runs it with
+// the task's environment, chdir, become and login shell, and removes it
+// again. The script is run through `executable:` or its own `#!` line, so a
+// target with a noexec /tmp can still run it.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &ScriptSpec,
+    deploy_file_dir: &Path,
+    environment: Option<&IndexMap<String, String>>,
+    is_localhost: bool,
+    session: Option<&Session>,
+    chdir: Option<&str>,
+    register: Option<&String>,
+    login_shell: bool,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let src = utils::replace_placeholders(&spec.src, vars_map);
+    let resolved_src = utils::resolve_src_path(deploy_file_dir, &src);
+    let raw = fs::read(&resolved_src).map_err(|_| {
+        format!(
+            "Task '{}': script source not found: {}",
+            task_name,
+            resolved_src.to_string_lossy().replace('\\', "/")
+        )
+    })?;
+    let bytes = if spec.template {
+        let text = String::from_utf8(raw).map_err(|_| {
+            format!(
+                "Task '{}': script source is not valid UTF-8: {}",
+                task_name,
+                resolved_src.display()
+            )
+        })?;
+        utils::replace_placeholders(&text, vars_map).into_bytes()
+    } else {
+        raw
+    };
+
+    let executable = match &spec.executable {
+        Some(executable) => utils::replace_placeholders(executable, vars_map),
+        None => interpreter(&bytes).unwrap_or_else(|| "sh".to_string()),
+    };
+    let args: Vec<String> = spec
+        .args
+        .iter()
+        .map(|arg| utils::replace_placeholders(arg, vars_map))
+        .collect();
+
+    if !no_log {
+        let shown = std::iter::once(&src).chain(&args);
+        println!(
+            "{}",
+            format!(
+                "> [script] {} (via {})",
+                shell_words::join(shown),
+                executable
+            )
+            .magenta()
+        );
+    }
+
+    // The temp file belongs to the login user, so uploading and removing it
+    // needs no become; root can still run it.
+    let temp_path = utils::make_temp_file_on_target("deploy-helper-script", is_localhost, session)
+        .map_err(|e| format!("Task '{}': {}", task_name, e))?;
+
+    let result = utils::write_to_target(
+        &bytes,
+        &temp_path,
+        is_localhost,
+        session,
+        false,
+        "",
+        None,
+        Some("0700"),
+    )
+    .and_then(|()| {
+        let mut lines = Vec::new();
+        if let Some(env) = environment {
+            let rendered = utils::render_env_values(env, vars_map)?;
+            lines.extend(utils::env_export_lines(&rendered));
+        }
+        let mut invocation = vec![executable, utils::shell_escape(&temp_path)];
+        invocation.extend(args.iter().map(|arg| utils::shell_escape(arg)));
+        lines.push(invocation.join(" "));
+
+        command::run_shell_source(
+            lines.join("\n"),
+            is_localhost,
+            session,
+            chdir,
+            register,
+            login_shell,
+            vars_map,
+            become_enabled,
+            become_method,
+            become_password,
+            no_log,
+            command::Limits::default(),
+        )
+    });
+
+    utils::remove_temp_file_on_target(&temp_path, is_localhost, session);
+    result
+}
+
+// The interpreter named by a `#!` line, with its arguments, as shell words.
+fn interpreter(script: &[u8]) -> Option<String> {
+    let first_line = script.split(|byte| *byte == b'\n').next()?;
+    let line = std::str::from_utf8(first_line.strip_prefix(b"#!")?).ok()?;
+    let line = line.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpreter_comes_from_the_shebang_line() {
+        assert_eq!(
+            interpreter(b"#!/usr/bin/env python3\nprint(1)\n"),
+            Some("/usr/bin/env python3".to_string())
+        );
+        assert_eq!(
+            interpreter(b"#! /bin/bash -eu\r\necho hi\n"),
+            Some("/bin/bash -eu".to_string())
+        );
+        assert_eq!(interpreter(b"echo hi\n"), None);
+        assert_eq!(interpreter(b"#!\n"), None);
+    }
+}
@@ -0,0 +1,167 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::{json, Map, Value};
+use ssh2::Session;
+
+use crate::common::{Register, StatSpec};
+use crate::modules::fetch::{parse_checksum, SHA256_COMMAND};
+use crate::utils;
+
+// `stat:` registers what the target knows about a path under `<register>.stat`
+// so `when:` and `vars:` can use it. Symlinks are reported as links, not
+// followed. `age_seconds` is measured with the target's clock.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &StatSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = utils::replace_placeholders(&spec.path, vars_map);
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        &stat_command(&path, spec.checksum),
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    if rc != 0 {
+        return Err(format!(
+            "Task '{}': failed to stat {}: exit {}: {}",
+            task_name,
+            path,
+            rc,
+            format!("{} {}", stdout.trim(), stderr.trim()).trim()
+        )
+        .into());
+    }
+    let stat =
+        parse_stat(&stdout).map_err(|e| format!("Task '{}': stat {}: {}", task_name, path, e))?;
+
+    let summary = match (&stat["type"], &stat["size"]) {
+        (Value::String(kind), Value::Number(size)) => format!("{}, {} bytes", kind, size),
+        _ => "missing".to_string(),
+    };
+    println!("{}", format!("> [stat] {} ({})", path, summary).magenta());
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed: false,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("stat".to_string(), stat);
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+// Prints `key=value` lines. GNU `stat -c` is tried first, then BSD `stat -f`.
+fn stat_command(path: &str, checksum: bool) -> String {
+    let mut script = format!(
+        r#"p={}
+if [ ! -e "$p" ] && [ ! -L "$p" ]; then echo exists=false; exit 0; fi
+if [ -L "$p" ]; then t=link; elif [ -d "$p" ]; then t=dir; elif [ -f "$p" ]; then t=file; else t=other; fi
+echo "type=$t"
+s=$(stat -c '%s %a %u %g %Y %U %G' -- "$p" 2>/dev/null) || s=$(stat -f '%z %Lp %u %g %m %Su %Sg' "$p") || exit 1
+echo "stat=$s"
+echo "now=$(date +%s)"
+if [ "$t" = link ]; then echo "link=$(readlink -- "$p")"; fi"#,
+        utils::shell_escape(path)
+    );
+    if checksum {
+        script.push_str(&format!(
+            "\nif [ \"$t\" = file ]; then echo \"sha256=$({})\"; fi",
+            SHA256_COMMAND
+        ));
+    }
+    script
+}
+
+fn parse_stat(output: &str) -> Result<Value, String> {
+    let fields: IndexMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| line.trim_end_matches('\r').split_once('='))
+        .collect();
+    if fields.get("exists") == Some(&"false") {
+        return Ok(json!({ "exists": false }));
+    }
+
+    let kind = fields.get("type").ok_or("missing type in stat output")?;
+    let raw = fields.get("stat").ok_or("missing stat output")?;
+    let parts: Vec<&str> = raw.splitn(7, ' ').collect();
+    let [size, mode, uid, gid, mtime, owner, group] = parts[..] else {
+        return Err(format!("unexpected stat output '{}'", raw));
+    };
+    let number = |value: &str, name: &str| {
+        value
+            .parse::<i64>()
+            .map_err(|_| format!("unexpected {} '{}' in stat output", name, value))
+    };
+    let mtime = number(mtime, "mtime")?;
+    let now = number(fields.get("now").copied().unwrap_or_default(), "time")?;
+
+    let mut stat = Map::new();
+    stat.insert("exists".to_string(), Value::Bool(true));
+    stat.insert("type".to_string(), Value::from(*kind));
+    stat.insert("size".to_string(), Value::from(number(size, "size")?));
+    stat.insert("mode".to_string(), Value::from(format!("{:0>4}", mode)));
+    stat.insert("owner".to_string(), Value::from(owner));
+    stat.insert("group".to_string(), Value::from(group));
+    stat.insert("uid".to_string(), Value::from(number(uid, "uid")?));
+    stat.insert("gid".to_string(), Value::from(number(gid, "gid")?));
+    stat.insert("mtime".to_string(), Value::from(mtime));
+    stat.insert("age_seconds".to_string(), Value::from((now - mtime).max(0)));
+    if let Some(target) = fields.get("link") {
+        stat.insert("link_target".to_string(), Value::from(*target));
+    }
+    if let Some(sha256) = fields.get("sha256") {
+        let sha256 = parse_checksum(sha256).ok_or("unexpected sha256 output")?;
+        stat.insert("sha256".to_string(), Value::from(sha256));
+    }
+    Ok(Value::Object(stat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_an_existing_file() {
+        let output = "type=file\nstat=120 640 0 33 1700000000 root www data\nnow=1700000600\n";
+        let stat = parse_stat(output).unwrap();
+        assert_eq!(stat["exists"], json!(true));
+        assert_eq!(stat["type"], json!("file"));
+        assert_eq!(stat["size"], json!(120));
+        assert_eq!(stat["mode"], json!("0640"));
+        assert_eq!(stat["owner"], json!("root"));
+        // Only the group may contain spaces; it is the last field.
+        assert_eq!(stat["group"], json!("www data"));
+        assert_eq!(stat["age_seconds"], json!(600));
+        assert!(stat.get("link_target").is_none());
+    }
+
+    #[test]
+    fn parses_a_link_and_a_missing_path() {
+        let output = "type=link\nstat=11 777 0 0 1700000000 root root\nnow=1700000000\nlink=/srv/app/releases/42\n";
+        let stat = parse_stat(output).unwrap();
+        assert_eq!(stat["link_target"], json!("/srv/app/releases/42"));
+        assert_eq!(
+            parse_stat("exists=false\n").unwrap(),
+            json!({ "exists": false })
+        );
+        assert!(parse_stat("type=file\nstat=oops\n").is_err());
+    }
+}
@@ -1,4 +1,8 @@
 use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StepChoice {
@@ -39,19 +43,98 @@ pub fn parse_choice(input: &str) -> Option<StepChoice> {
     }
 }
 
+// Every line read goes through one reader thread. A read that timed out
+// stays pending, and the line it gets is handed to the next read instead of
+// being lost.
+struct StdinReader {
+    wake: Sender<()>,
+    lines: Receiver<io::Result<Option<String>>>,
+    pending: bool,
+}
+
+fn stdin_reader() -> MutexGuard<'static, StdinReader> {
+    static READER: OnceLock<Mutex<StdinReader>> = OnceLock::new();
+    READER
+        .get_or_init(|| {
+            let (wake, wake_rx) = mpsc::channel::<()>();
+            let (line_tx, lines) = mpsc::channel();
+            thread::spawn(move || {
+                for () in wake_rx {
+                    let mut line = String::new();
+                    let read = io::stdin()
+                        .lock()
+                        .read_line(&mut line)
+                        .map(|bytes| (bytes > 0).then_some(line));
+                    if line_tx.send(read).is_err() {
+                        break;
+                    }
+                }
+            });
+            Mutex::new(StdinReader {
+                wake,
+                lines,
+                pending: false,
+            })
+        })
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+// Waits up to `timeout` (forever when `None`) for the next line of stdin.
+// Returns `None` if the time ran out first.
+fn next_line(timeout: Option<Duration>) -> Option<io::Result<Option<String>>> {
+    let mut reader = stdin_reader();
+    if !reader.pending {
+        if reader.wake.send(()).is_err() {
+            return Some(Err(io::Error::other("stdin reader stopped")));
+        }
+        reader.pending = true;
+    }
+    let read = match timeout {
+        Some(timeout) => match reader.lines.recv_timeout(timeout) {
+            Ok(read) => read,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::other("stdin reader stopped")),
+        },
+        None => reader
+            .lines
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("stdin reader stopped"))),
+    };
+    reader.pending = false;
+    Some(read)
+}
+
+// Prints `message` and reads one line from stdin. Returns `None` at EOF.
+pub fn read_line(message: &str) -> io::Result<Option<String>> {
+    print!("{}", message);
+    io::stdout().lock().flush()?;
+
+    next_line(None).unwrap_or(Ok(None))
+}
+
+// Waits up to `timeout` for a line of stdin and reports whether one came.
+// EOF counts as no line, so a closed stdin just waits out the time.
+pub fn wait_for_line(timeout: Duration) -> io::Result<bool> {
+    let started = Instant::now();
+    match next_line(Some(timeout)) {
+        Some(Ok(Some(_))) => Ok(true),
+        Some(Ok(None)) => {
+            thread::sleep(timeout.saturating_sub(started.elapsed()));
+            Ok(false)
+        }
+        Some(Err(e)) => Err(e),
+        None => Ok(false),
+    }
+}
+
 pub fn prompt(task_name: &str) -> io::Result<StepChoice> {
-    let stdin = io::stdin();
-    let stdout = io::stdout();
     loop {
-        print!("Perform task: {} (N)o/(y)es/(c)ontinue: ", task_name);
-        stdout.lock().flush()?;
-
-        let mut line = String::new();
-        let bytes = stdin.lock().read_line(&mut line)?;
-        if bytes == 0 {
+        let message = format!("Perform task: {} (N)o/(y)es/(c)ontinue: ", task_name);
+        let Some(line) = read_line(&message)? else {
             // EOF: treat as Skip
             return Ok(StepChoice::Skip);
-        }
+        };
         if let Some(choice) = parse_choice(&line) {
             return Ok(choice);
         }
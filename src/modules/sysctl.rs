@@ -0,0 +1,138 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{EditState, SysctlSpec};
+use crate::modules::ini_file::set_option;
+use crate::modules::lineinfile::{register_changed, EditTarget};
+use crate::utils;
+
+const DEFAULT_SYSCTL_FILE: &str = "/etc/sysctl.d/99-deploy-helper.conf";
+
+// Kernel values with several fields are printed tab-separated by `sysctl -n`
+// but usually written with spaces, so compare them field by field.
+fn same_value(current: &str, wanted: &str) -> bool {
+    current.split_whitespace().eq(wanted.split_whitespace())
+}
+
+// `sysctl:` persists one kernel parameter to a file under /etc/sysctl.d and,
+// unless `reload: false`, sets the running value with `sysctl -w` when it
+// differs.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &SysctlSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // sysctl accepts `/` as the separator too; keep one spelling in the file.
+    let name = utils::replace_placeholders(&spec.name, vars_map)
+        .trim()
+        .replace('/', ".");
+    let value = utils::replace_placeholders(&spec.value, vars_map)
+        .trim()
+        .to_string();
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '=') {
+        return Err(format!(
+            "Task '{}': sysctl name must be a parameter such as net.ipv4.ip_forward (got '{}')",
+            task_name, name
+        )
+        .into());
+    }
+    if value.is_empty() || value.contains('\n') {
+        return Err(format!(
+            "Task '{}': sysctl value for {} must be a single non-empty line",
+            task_name, name
+        )
+        .into());
+    }
+
+    let target = EditTarget {
+        task_name,
+        action: "sysctl",
+        path: spec
+            .sysctl_file
+            .as_deref()
+            .map(|f| utils::replace_placeholders(f, vars_map))
+            .unwrap_or_else(|| DEFAULT_SYSCTL_FILE.to_string()),
+        create: true,
+        mode: Some("0644".to_string()),
+    };
+    let mut changed = target.apply(
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        EditState::Present,
+        |lines| {
+            set_option(lines, None, &name, Some(&value));
+            Ok(())
+        },
+    )?;
+
+    if spec.reload.unwrap_or(true) {
+        let target_shell = |command: &str| {
+            utils::run_shell_on_target(
+                command,
+                is_localhost,
+                session,
+                become_enabled,
+                become_method,
+                become_password,
+            )
+        };
+        let (current, stderr, rc) =
+            target_shell(&format!("sysctl -n {}", utils::shell_escape(&name)))?;
+        if rc != 0 {
+            return Err(format!(
+                "Task '{}': sysctl could not read {}: {}",
+                task_name,
+                name,
+                stderr.trim()
+            )
+            .into());
+        }
+        if !same_value(&current, &value) {
+            let (_, stderr, rc) = target_shell(&format!(
+                "sysctl -w {}",
+                utils::shell_escape(&format!("{}={}", name, value))
+            ))?;
+            if rc != 0 {
+                return Err(format!(
+                    "Task '{}': sysctl could not set {} to {}: {}",
+                    task_name,
+                    name,
+                    value,
+                    stderr.trim()
+                )
+                .into());
+            }
+            println!(
+                "{}",
+                format!("> [sysctl] {} = {} (applied)", name, value).magenta()
+            );
+            changed = true;
+        }
+    }
+
+    register_changed(vars_map, register, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_value_ignores_field_spacing() {
+        assert!(same_value("4096\t87380\t6291456", "4096 87380 6291456"));
+        assert!(same_value("1", "1"));
+        assert!(!same_value("0", "1"));
+    }
+}
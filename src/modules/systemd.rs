@@ -3,10 +3,64 @@ use indexmap::IndexMap;
 use serde_json::Value;
 use ssh2::Session;
 use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
 
-use crate::common::{Register, SystemdSpec, SystemdUnitSpec, SystemdUnitState};
+use crate::common::{Register, SystemdScope, SystemdSpec, SystemdUnitSpec, SystemdUnitState};
+use crate::modules::fetch::{checksum_command, parse_checksum, sha256_hex};
 use crate::utils;
 
+const SYSTEM_UNIT_DIR: &str = "/etc/systemd/system";
+const USER_UNIT_DIR: &str = ".config/systemd/user";
+const DEFAULT_JOURNAL_LINES: usize = 20;
+
+// How a task reaches systemd: the system manager or the connecting user's
+// manager, and how much of a unit's journal to attach to its failures.
+struct Systemctl {
+    user: bool,
+    journal_lines: usize,
+}
+
+impl Systemctl {
+    fn program(&self) -> &'static str {
+        if self.user {
+            "systemctl --user"
+        } else {
+            "systemctl"
+        }
+    }
+
+    // Non-interactive SSH sessions often lack the variables the user manager
+    // is found through, so default them to the standard runtime directory.
+    fn script(&self) -> String {
+        let mut script = String::from("set -eu\n");
+        if self.user {
+            script.push_str(
+                "export XDG_RUNTIME_DIR=\"${XDG_RUNTIME_DIR:-/run/user/$(id -u)}\"\n\
+                 export DBUS_SESSION_BUS_ADDRESS=\"${DBUS_SESSION_BUS_ADDRESS:-unix:path=$XDG_RUNTIME_DIR/bus}\"\n",
+            );
+        }
+        script
+    }
+
+    // Shell lines printing the unit's last journal lines to stderr, for use
+    // just before a failing check exits.
+    fn journal(&self, escaped_name: &str) -> String {
+        if self.journal_lines == 0 {
+            return String::new();
+        }
+        let journalctl = if self.user {
+            "journalctl --user"
+        } else {
+            "journalctl"
+        };
+        let lines = self.journal_lines;
+        format!(
+            "printf 'last %s journal lines for %s:\\n' {lines} {escaped_name} >&2\n{journalctl} -u {escaped_name} -n {lines} --no-pager 2>&1 | sed 's/^/  /' >&2 || true\n"
+        )
+    }
+}
+
 fn state_command(state: &SystemdUnitState) -> &'static str {
     match state {
         SystemdUnitState::Started => "start",
@@ -16,28 +70,60 @@ fn state_command(state: &SystemdUnitState) -> &'static str {
     }
 }
 
-fn append_checked_command(command: &mut String, shell_command: &str, error: &str) {
+// `journal` is shell run after the error message, before the script exits.
+fn append_checked_command(command: &mut String, shell_command: &str, error: &str, journal: &str) {
     command.push_str(&format!(
-        "if ! {shell_command}; then\n  echo {} >&2\n  exit 1\nfi\n",
+        "if ! {shell_command}; then\n  echo {} >&2\n{journal}  exit 1\nfi\n",
         utils::shell_escape(error)
     ));
 }
 
-fn append_enabled_state_check(command: &mut String, escaped_name: &str, expected_enabled: bool) {
+fn append_enabled_state_check(
+    command: &mut String,
+    systemctl: &Systemctl,
+    escaped_name: &str,
+    expected_enabled: bool,
+) {
+    let program = systemctl.program();
+    let journal = systemctl.journal(escaped_name);
     command.push_str(&format!(
-        "unit_enabled_state_rc=0\nunit_enabled_state=$(systemctl is-enabled {escaped_name} 2>&1) || unit_enabled_state_rc=$?\n"
+        "unit_enabled_state_rc=0\nunit_enabled_state=$({program} is-enabled {escaped_name} 2>&1) || unit_enabled_state_rc=$?\n"
     ));
     if expected_enabled {
         command.push_str(&format!(
-            "case \"${{unit_enabled_state_rc}}:${{unit_enabled_state}}\" in\n  0:enabled|0:enabled-runtime) ;;\n  *) printf 'systemd unit %s is not enabled (state: %s)\\n' {escaped_name} \"$unit_enabled_state\" >&2; exit 1 ;;\nesac\n"
+            "case \"${{unit_enabled_state_rc}}:${{unit_enabled_state}}\" in\n  0:enabled|0:enabled-runtime) ;;\n  *) printf 'systemd unit %s is not enabled (state: %s)\\n' {escaped_name} \"$unit_enabled_state\" >&2\n{journal}exit 1 ;;\nesac\n"
         ));
     } else {
         command.push_str(&format!(
-            "case \"$unit_enabled_state\" in\n  disabled|static|indirect|generated|transient|masked|masked-runtime|linked|linked-runtime) ;;\n  enabled|enabled-runtime|alias) printf 'systemd unit %s is still enabled (state: %s)\\n' {escaped_name} \"$unit_enabled_state\" >&2; exit 1 ;;\n  *) printf 'systemd failed to determine enabled state for unit %s (exit %s): %s\\n' {escaped_name} \"$unit_enabled_state_rc\" \"$unit_enabled_state\" >&2; exit 1 ;;\nesac\n"
+            "case \"$unit_enabled_state\" in\n  disabled|static|indirect|generated|transient|masked|masked-runtime|linked|linked-runtime) ;;\n  enabled|enabled-runtime|alias) printf 'systemd unit %s is still enabled (state: %s)\\n' {escaped_name} \"$unit_enabled_state\" >&2\n{journal}exit 1 ;;\n  *) printf 'systemd failed to determine enabled state for unit %s (exit %s): %s\\n' {escaped_name} \"$unit_enabled_state_rc\" \"$unit_enabled_state\" >&2; exit 1 ;;\nesac\n"
         ));
     }
 }
 
+// Polls `is-active` once a second until the unit is active. A unit that
+// reaches `failed` fails the check without waiting out the timeout.
+fn append_wait_active(
+    command: &mut String,
+    systemctl: &Systemctl,
+    escaped_name: &str,
+    timeout_seconds: u64,
+) {
+    let program = systemctl.program();
+    let journal = systemctl.journal(escaped_name);
+    command.push_str(&format!(
+        "wait_deadline=$(( $(date +%s) + {timeout_seconds} ))\nwhile :; do\n  unit_active_state=$({program} is-active {escaped_name} 2>/dev/null) || true\n  case \"$unit_active_state\" in\n    active) break ;;\n    failed) printf 'systemd unit %s failed while waiting for it to become active\\n' {escaped_name} >&2\n{journal}      exit 1 ;;\n  esac\n  if [ \"$(date +%s)\" -ge \"$wait_deadline\" ]; then\n    printf 'systemd unit %s is not active after %ss (state: %s)\\n' {escaped_name} {timeout_seconds} \"$unit_active_state\" >&2\n{journal}    exit 1\n  fi\n  sleep 1\ndone\n"
+    ));
+}
+
+// Starts the service a timer activates, once, as if the timer had elapsed.
+fn append_run_now(command: &mut String, systemctl: &Systemctl, escaped_name: &str) {
+    let program = systemctl.program();
+    command.push_str(&format!(
+        "timer_service=$({program} show -p Unit --value {escaped_name}) || timer_service=\nif [ -z \"$timer_service\" ]; then\n  printf 'systemd failed to find the service triggered by timer %s\\n' {escaped_name} >&2\n  exit 1\nfi\nif ! {program} start \"$timer_service\"; then\n  printf 'systemd failed to run %s for timer %s\\n' \"$timer_service\" {escaped_name} >&2\n{}  exit 1\nfi\n",
+        systemctl.journal("\"$timer_service\""),
+    ));
+}
+
 #[derive(Debug)]
 struct SystemdUnitResolved {
     name: String,
@@ -74,6 +160,13 @@ fn validate_units(
             && !unit.assert_enabled
             && !unit.assert_active
             && unit.assert_result.is_none()
+            && unit.wait_active.is_none()
+            && !unit.assert_scheduled
+            && !unit.run_now
+            && unit.content.is_none()
+            && unit.template.is_none()
+            && unit.dropins.is_none()
+            && unit.masked.is_none()
         {
             return Err(format!(
                 "Task '{}': systemd unit '{}' has no requested operation or assertion",
@@ -95,6 +188,86 @@ fn validate_units(
             )
             .into());
         }
+        if matches!(unit.state.as_ref(), Some(SystemdUnitState::Stopped))
+            && unit.wait_active.is_some()
+        {
+            return Err(format!(
+                "Task '{}': systemd unit '{}' cannot set state: stopped and wait_active",
+                task_name, name
+            )
+            .into());
+        }
+        if unit
+            .wait_active
+            .as_ref()
+            .is_some_and(|wait| wait.timeout_seconds == 0)
+        {
+            return Err(format!(
+                "Task '{}': systemd unit '{}' wait_active.timeout_seconds must be greater than 0",
+                task_name, name
+            )
+            .into());
+        }
+        if (unit.assert_scheduled || unit.run_now) && !name.ends_with(".timer") {
+            return Err(format!(
+                "Task '{}': systemd unit '{}' can only use assert_scheduled and run_now on a .timer unit",
+                task_name, name
+            )
+            .into());
+        }
+        if unit.content.is_some() && unit.template.is_some() {
+            return Err(format!(
+                "Task '{}': systemd unit '{}' takes content or template, not both",
+                task_name, name
+            )
+            .into());
+        }
+        if (unit.content.is_some() || unit.template.is_some() || unit.dropins.is_some())
+            && (name.contains('/') || !name.contains('.'))
+        {
+            return Err(format!(
+                "Task '{}': systemd unit '{}' needs a full unit name such as app.service to install unit files",
+                task_name, name
+            )
+            .into());
+        }
+        if unit.masked == Some(true) {
+            let conflict = if unit.content.is_some() || unit.template.is_some() {
+                Some("content or template")
+            } else if unit.dropins.is_some() {
+                Some("dropins")
+            } else if unit.enabled == Some(true) || unit.assert_enabled {
+                Some("enabled: true or assert_enabled")
+            } else if unit.assert_active
+                || unit.wait_active.is_some()
+                || unit.assert_scheduled
+                || unit.run_now
+                || unit
+                    .state
+                    .as_ref()
+                    .is_some_and(|state| *state != SystemdUnitState::Stopped)
+            {
+                Some("a state other than stopped, wait_active, assert_active, assert_scheduled or run_now")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(format!(
+                    "Task '{}': systemd unit '{}' cannot set masked: true with {}",
+                    task_name, name, conflict
+                )
+                .into());
+            }
+        }
+        for dropin in unit.dropins.iter().flatten().map(|(dropin, _)| dropin) {
+            if dropin.is_empty() || dropin.contains('/') || dropin.starts_with('.') {
+                return Err(format!(
+                    "Task '{}': systemd unit '{}' drop-in name '{}' must be a plain file name",
+                    task_name, name, dropin
+                )
+                .into());
+            }
+        }
         let assert_result = unit
             .assert_result
             .as_ref()
@@ -117,9 +290,107 @@ fn validate_units(
     Ok(units_resolved)
 }
 
+// The unit file and drop-ins a unit asks for, as target paths and rendered
+// contents. Drop-in names get a `.conf` suffix when they don't have one.
+fn unit_files(
+    task_name: &str,
+    unit: &SystemdUnitSpec,
+    name: &str,
+    unit_dir: &str,
+    deploy_file_dir: &Path,
+    vars_map: &IndexMap<String, Value>,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let content = match (&unit.content, &unit.template) {
+        (Some(content), _) => Some(utils::replace_placeholders(content, vars_map)),
+        (None, Some(template)) => {
+            let src = utils::replace_placeholders(template, vars_map);
+            let resolved_src = utils::resolve_src_path(deploy_file_dir, &src);
+            let raw = fs::read_to_string(&resolved_src).map_err(|_| {
+                format!(
+                    "Task '{}': systemd unit template not found or not UTF-8: {}",
+                    task_name,
+                    resolved_src.to_string_lossy().replace('\\', "/")
+                )
+            })?;
+            Some(utils::replace_placeholders(&raw, vars_map))
+        }
+        (None, None) => None,
+    };
+    if let Some(content) = content {
+        files.push((format!("{}/{}", unit_dir, name), content));
+    }
+    for (dropin, content) in unit.dropins.iter().flatten() {
+        let file = if dropin.ends_with(".conf") {
+            dropin.clone()
+        } else {
+            format!("{}.conf", dropin)
+        };
+        files.push((
+            format!("{}/{}.d/{}", unit_dir, name, file),
+            utils::replace_placeholders(content, vars_map),
+        ));
+    }
+    Ok(files)
+}
+
+// Writes `content` to `path` unless it is already there. Returns whether it
+// wrote.
+#[allow(clippy::too_many_arguments)]
+fn install_file(
+    task_name: &str,
+    path: &str,
+    content: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let target = |command: &str| {
+        utils::run_shell_on_target(
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )
+    };
+    let (stdout, _, rc) = target(&checksum_command(path))?;
+    if rc == 0
+        && parse_checksum(&stdout).as_deref() == Some(sha256_hex(content.as_bytes()).as_str())
+    {
+        return Ok(false);
+    }
+    let parent = path.rsplit_once('/').map_or("/", |(parent, _)| parent);
+    let (_, stderr, rc) = target(&format!("mkdir -p {}", utils::shell_escape(parent)))?;
+    if rc != 0 {
+        return Err(format!(
+            "Task '{}': systemd failed to create {}: {}",
+            task_name,
+            parent,
+            stderr.trim()
+        )
+        .into());
+    }
+    utils::write_to_target(
+        content.as_bytes(),
+        path,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        Some("0644"),
+    )?;
+    Ok(true)
+}
+
 pub fn process(
     task_name: &str,
     spec: &SystemdSpec,
+    deploy_file_dir: &Path,
     is_localhost: bool,
     session: Option<&Session>,
     vars_map: &mut IndexMap<String, Value>,
@@ -129,75 +400,206 @@ pub fn process(
     register: Option<&String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let units_resolved = validate_units(task_name, &spec.units, vars_map)?;
-    let mut command = String::from("set -eu\n");
+    let systemctl = Systemctl {
+        user: spec.scope == SystemdScope::User,
+        journal_lines: spec.journal_lines.unwrap_or(DEFAULT_JOURNAL_LINES),
+    };
+    let program = systemctl.program();
+
+    let installs_files = spec
+        .units
+        .iter()
+        .any(|unit| unit.content.is_some() || unit.template.is_some() || unit.dropins.is_some());
+    let unit_dir = if systemctl.user && installs_files {
+        let (home, stderr, rc) = utils::run_shell_on_target(
+            "printf '%s' \"$HOME\"",
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?;
+        if rc != 0 || !home.starts_with('/') {
+            return Err(format!(
+                "Task '{}': systemd could not find the home directory for user units: {}",
+                task_name,
+                stderr.trim()
+            )
+            .into());
+        }
+        format!("{}/{}", home.trim_end_matches('/'), USER_UNIT_DIR)
+    } else {
+        SYSTEM_UNIT_DIR.to_string()
+    };
 
-    if spec.daemon_reload {
+    let mut files = Vec::new();
+    for (unit, unit_resolved) in spec.units.iter().zip(&units_resolved) {
+        files.extend(unit_files(
+            task_name,
+            unit,
+            &unit_resolved.name,
+            &unit_dir,
+            deploy_file_dir,
+            vars_map,
+        )?);
+    }
+
+    // A masked unit is a symlink to /dev/null in the unit directory, so
+    // unmasking has to happen before its unit file is written.
+    let mut unmask = String::new();
+    for (unit, unit_resolved) in spec.units.iter().zip(&units_resolved) {
+        if unit.masked == Some(false) {
+            append_checked_command(
+                &mut unmask,
+                &format!(
+                    "{} unmask {}",
+                    program,
+                    utils::shell_escape(&unit_resolved.name)
+                ),
+                &format!("systemd failed to unmask unit {}", unit_resolved.name),
+                "",
+            );
+        }
+    }
+    if !unmask.is_empty() {
+        let unmask = systemctl.script() + &unmask;
+        run_systemctl(
+            task_name,
+            &unmask,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?;
+    }
+
+    let mut changed_files = Vec::new();
+    for (path, content) in &files {
+        if install_file(
+            task_name,
+            path,
+            content,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )? {
+            changed_files.push(path.clone());
+        }
+    }
+    let daemon_reload = spec.daemon_reload || !changed_files.is_empty();
+
+    let mut command = systemctl.script();
+    if daemon_reload {
         append_checked_command(
             &mut command,
-            "systemctl daemon-reload",
+            &format!("{} daemon-reload", program),
             "systemd daemon-reload failed",
+            "",
         );
     }
 
     for (unit, unit_resolved) in spec.units.iter().zip(&units_resolved) {
         let name = &unit_resolved.name;
         let escaped_name = utils::shell_escape(name);
+        let journal = systemctl.journal(&escaped_name);
+        if unit.masked == Some(true) {
+            append_checked_command(
+                &mut command,
+                &format!("{} mask {}", program, escaped_name),
+                &format!("systemd failed to mask unit {}", name),
+                "",
+            );
+        }
         if let Some(enabled) = unit.enabled {
             let operation = if enabled { "enable" } else { "disable" };
             append_checked_command(
                 &mut command,
-                &format!("systemctl {} {}", operation, escaped_name),
+                &format!("{} {} {}", program, operation, escaped_name),
                 &format!("systemd failed to {} unit {}", operation, name),
+                "",
             );
-            if enabled {
-                append_enabled_state_check(&mut command, &escaped_name, true);
-            } else {
-                append_enabled_state_check(&mut command, &escaped_name, false);
-            }
+            append_enabled_state_check(&mut command, &systemctl, &escaped_name, enabled);
         }
         if unit.assert_enabled && unit.enabled != Some(true) {
-            append_enabled_state_check(&mut command, &escaped_name, true);
+            append_enabled_state_check(&mut command, &systemctl, &escaped_name, true);
         }
         if let Some(state) = &unit.state {
             let operation = state_command(state);
             append_checked_command(
                 &mut command,
-                &format!("systemctl {} {}", operation, escaped_name),
+                &format!("{} {} {}", program, operation, escaped_name),
                 &format!("systemd failed to {} unit {}", operation, name),
+                &journal,
+            );
+        }
+        if let Some(wait) = &unit.wait_active {
+            append_wait_active(
+                &mut command,
+                &systemctl,
+                &escaped_name,
+                wait.timeout_seconds,
             );
         }
+        if unit.run_now {
+            append_run_now(&mut command, &systemctl, &escaped_name);
+        }
         if unit.assert_active {
             append_checked_command(
                 &mut command,
-                &format!("systemctl is-active --quiet {}", escaped_name),
+                &format!("{} is-active --quiet {}", program, escaped_name),
                 &format!("systemd unit {} is not active", name),
+                &journal,
             );
         }
+        if unit.assert_scheduled {
+            command.push_str(&format!(
+                "if ! next_elapse=$({program} show -p NextElapseUSecRealtime --value {escaped_name}); then\n  echo {} >&2\n  exit 1\nfi\ncase \"$next_elapse\" in\n  ''|0|n/a) printf 'systemd timer %s has no next elapse scheduled\\n' {escaped_name} >&2\n{journal}  exit 1 ;;\nesac\n",
+                utils::shell_escape(&format!("systemd failed to read the schedule for timer {}", name)),
+            ));
+        }
         if let Some(result) = &unit_resolved.assert_result {
             let escaped_result = utils::shell_escape(result);
             command.push_str(&format!(
-                "if ! actual_result=$(systemctl show -p Result --value {escaped_name}); then\n  echo {} >&2\n  exit 1\nfi\nif [ \"$actual_result\" != {escaped_result} ]; then\n  printf 'systemd unit %s result: expected %s, got %s\\n' {escaped_name} {escaped_result} \"$actual_result\" >&2\n  exit 1\nfi\n",
+                "if ! actual_result=$({program} show -p Result --value {escaped_name}); then\n  echo {} >&2\n  exit 1\nfi\nif [ \"$actual_result\" != {escaped_result} ]; then\n  printf 'systemd unit %s result: expected %s, got %s\\n' {escaped_name} {escaped_result} \"$actual_result\" >&2\n{journal}  exit 1\nfi\n",
                 utils::shell_escape(&format!("systemd failed to read result for unit {}", name)),
             ));
         }
     }
 
+    let mut notes = Vec::new();
+    if !changed_files.is_empty() {
+        notes.push(format!(
+            "{} unit file{} changed",
+            changed_files.len(),
+            if changed_files.len() == 1 { "" } else { "s" }
+        ));
+    }
+    if daemon_reload {
+        notes.push("daemon reload".to_string());
+    }
+    if systemctl.user {
+        notes.push("user scope".to_string());
+    }
     println!(
         "{}",
         format!(
             "> [systemd] {} unit{}{}",
             units_resolved.len(),
             if units_resolved.len() == 1 { "" } else { "s" },
-            if spec.daemon_reload {
-                " (daemon reload)"
+            if notes.is_empty() {
+                String::new()
             } else {
-                ""
+                format!(" ({})", notes.join(", "))
             }
         )
         .magenta()
     );
 
-    let (out, stderr, code) = utils::run_shell_on_target(
+    run_systemctl(
+        task_name,
         &command,
         is_localhost,
         session,
@@ -205,21 +607,29 @@ pub fn process(
         become_method,
         become_password,
     )?;
-    if code != 0 {
-        let detail = if stderr.trim().is_empty() {
-            out.trim()
-        } else {
-            stderr.trim()
-        };
-        return Err(format!("Task '{}': systemd failed: {}", task_name, detail).into());
-    }
+
+    // systemctl does not say whether an operation changed anything, so any
+    // operation that can is counted; assertions and waits alone are not.
+    let changed = daemon_reload
+        || !unmask.is_empty()
+        || spec.units.iter().any(|unit| {
+            unit.masked == Some(true)
+                || unit.enabled.is_some()
+                || unit.state.is_some()
+                || unit.run_now
+        });
 
     if let Some(reg) = register {
-        let value = serde_json::to_value(Register {
+        let mut value = serde_json::to_value(Register {
             stdout: String::new(),
             stderr: String::new(),
             rc: 0,
+            changed,
+            failed: false,
         })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("unit_files_changed".to_string(), Value::from(changed_files));
+        }
         vars_map.insert(reg.clone(), value);
         println!("{}", format!("Registering output to: {}", reg).yellow());
     }
@@ -227,11 +637,40 @@ pub fn process(
     Ok(())
 }
 
+fn run_systemctl(
+    task_name: &str,
+    command: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (out, stderr, code) = utils::run_shell_on_target(
+        command,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    if code != 0 {
+        let detail = if stderr.trim().is_empty() {
+            out.trim()
+        } else {
+            stderr.trim()
+        };
+        return Err(format!("Task '{}': systemd failed: {}", task_name, detail).into());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{state_command, validate_units};
-    use crate::common::{SystemdUnitSpec, SystemdUnitState};
+    use super::{state_command, unit_files, validate_units};
+    use crate::common::{SystemdUnitSpec, SystemdUnitState, SystemdWaitActive};
     use indexmap::IndexMap;
+    use std::path::Path;
 
     #[test]
     fn systemd_state_commands_match_systemctl() {
@@ -248,17 +687,13 @@ mod tests {
                 name: "app.service".to_string(),
                 enabled: Some(true),
                 state: None,
-                assert_enabled: false,
-                assert_active: false,
-                assert_result: None,
+                ..Default::default()
             },
             SystemdUnitSpec {
                 name: "app.service".to_string(),
                 enabled: None,
                 state: Some(SystemdUnitState::Started),
-                assert_enabled: false,
-                assert_active: false,
-                assert_result: None,
+                ..Default::default()
             },
         ];
         let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
@@ -271,9 +706,7 @@ mod tests {
             name: "app.service".to_string(),
             enabled: None,
             state: None,
-            assert_enabled: false,
-            assert_active: false,
-            assert_result: None,
+            ..Default::default()
         }];
         let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
         assert!(err.to_string().contains("no requested operation"));
@@ -286,8 +719,7 @@ mod tests {
             enabled: Some(false),
             state: None,
             assert_enabled: true,
-            assert_active: false,
-            assert_result: None,
+            ..Default::default()
         }];
         let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
         assert!(err.to_string().contains("cannot set enabled: false"));
@@ -299,9 +731,8 @@ mod tests {
             name: "app.service".to_string(),
             enabled: None,
             state: Some(SystemdUnitState::Stopped),
-            assert_enabled: false,
             assert_active: true,
-            assert_result: None,
+            ..Default::default()
         }];
         let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
         assert!(err.to_string().contains("cannot set state: stopped"));
@@ -313,13 +744,122 @@ mod tests {
             name: "app.service".to_string(),
             enabled: None,
             state: None,
-            assert_enabled: false,
-            assert_active: false,
             assert_result: Some("{{ expected_result }}".to_string()),
+            ..Default::default()
         }];
         let mut vars_map = IndexMap::new();
         vars_map.insert("expected_result".to_string(), "".into());
         let err = validate_units("Example", &units, &vars_map).unwrap_err();
         assert!(err.to_string().contains("assert_result must not be empty"));
     }
+
+    #[test]
+    fn systemd_rejects_content_with_template() {
+        let units = vec![SystemdUnitSpec {
+            name: "app.service".to_string(),
+            content: Some("[Service]\n".to_string()),
+            template: Some("app.service.j2".to_string()),
+            ..Default::default()
+        }];
+        let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("takes content or template, not both"));
+    }
+
+    #[test]
+    fn systemd_rejects_unit_files_without_unit_type() {
+        let units = vec![SystemdUnitSpec {
+            name: "app".to_string(),
+            content: Some("[Service]\n".to_string()),
+            ..Default::default()
+        }];
+        let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
+        assert!(err.to_string().contains("needs a full unit name"));
+    }
+
+    #[test]
+    fn systemd_rejects_masking_a_started_unit() {
+        let units = vec![SystemdUnitSpec {
+            name: "app.service".to_string(),
+            state: Some(SystemdUnitState::Started),
+            masked: Some(true),
+            ..Default::default()
+        }];
+        let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
+        assert!(err.to_string().contains("cannot set masked: true"));
+    }
+
+    #[test]
+    fn systemd_unit_files_place_dropins_under_unit_directory() {
+        let mut dropins = IndexMap::new();
+        dropins.insert(
+            "limits".to_string(),
+            "LimitNOFILE={{ files }}\n".to_string(),
+        );
+        dropins.insert("env.conf".to_string(), "[Service]\n".to_string());
+        let unit = SystemdUnitSpec {
+            name: "app.service".to_string(),
+            content: Some("[Unit]\n".to_string()),
+            dropins: Some(dropins),
+            ..Default::default()
+        };
+        let mut vars_map = IndexMap::new();
+        vars_map.insert("files".to_string(), 1024.into());
+        let files = unit_files(
+            "Example",
+            &unit,
+            "app.service",
+            "/etc/systemd/system",
+            Path::new("."),
+            &vars_map,
+        )
+        .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "/etc/systemd/system/app.service".to_string(),
+                    "[Unit]".to_string()
+                ),
+                (
+                    "/etc/systemd/system/app.service.d/limits.conf".to_string(),
+                    "LimitNOFILE=1024".to_string()
+                ),
+                (
+                    "/etc/systemd/system/app.service.d/env.conf".to_string(),
+                    "[Service]".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn systemd_rejects_timer_options_on_services() {
+        let units = vec![SystemdUnitSpec {
+            name: "app.service".to_string(),
+            run_now: true,
+            ..Default::default()
+        }];
+        let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("only use assert_scheduled and run_now on a .timer"));
+    }
+
+    #[test]
+    fn systemd_rejects_waiting_for_stopped_unit() {
+        let units = vec![SystemdUnitSpec {
+            name: "app.service".to_string(),
+            state: Some(SystemdUnitState::Stopped),
+            wait_active: Some(SystemdWaitActive {
+                timeout_seconds: 10,
+            }),
+            ..Default::default()
+        }];
+        let err = validate_units("Example", &units, &IndexMap::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cannot set state: stopped and wait_active"));
+    }
 }
@@ -74,6 +74,8 @@ pub fn process(
             stdout: String::new(),
             stderr: String::new(),
             rc: 0,
+            changed: true,
+            failed: false,
         })?;
         vars_map.insert(reg.clone(), value);
         println!("{}", format!("Registering output to: {}", reg).yellow());
@@ -0,0 +1,320 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{Register, RunFrom, UriSpec};
+use crate::utils;
+
+pub(crate) const DEFAULT_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_STATUS_CODE: u16 = 200;
+
+// One HTTP request, made with curl on the target or the control machine.
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    pub timeout: u64,
+}
+
+pub(crate) struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Request {
+    // Prints the response body followed by a last line with the status code.
+    // The headers and body reach curl as a config on stdin, see
+    // `curl_config`.
+    fn command(&self) -> String {
+        let mut command = String::new();
+        let config = curl_config(&self.headers, self.body.as_deref());
+        if let Some(config) = &config {
+            command.push_str(&format!("printf '%s' {} | ", utils::shell_escape(config)));
+        }
+        command.push_str(&format!(
+            "curl -sS -X {} --max-time {}",
+            utils::shell_escape(&self.method),
+            self.timeout
+        ));
+        if config.is_some() {
+            command.push_str(" --config -");
+        }
+        command.push_str(&format!(
+            " -w '\\n%{{http_code}}' {}",
+            utils::shell_escape(&self.url)
+        ));
+        command
+    }
+
+    // Sends the request. `Err` means no response arrived (DNS, connection,
+    // timeout); any HTTP status is an `Ok` response for the caller to judge.
+    pub(crate) fn send(
+        &self,
+        from: RunFrom,
+        is_localhost: bool,
+        session: Option<&Session>,
+    ) -> Result<Response, String> {
+        let result = match from {
+            RunFrom::Control => {
+                utils::run_shell_on_target(&self.command(), true, None, false, "", None)
+            }
+            RunFrom::Target => {
+                utils::run_shell_on_target(&self.command(), is_localhost, session, false, "", None)
+            }
+        };
+        let (stdout, stderr, rc) = result.map_err(|e| e.to_string())?;
+        if rc != 0 {
+            let reason = if rc == 127 {
+                "curl is not installed".to_string()
+            } else {
+                format!("exit {}: {}", rc, stderr.trim())
+            };
+            return Err(format!("{} {} failed: {}", self.method, self.url, reason));
+        }
+        parse_response(&stdout).ok_or_else(|| {
+            format!(
+                "{} {} returned unexpected curl output",
+                self.method, self.url
+            )
+        })
+    }
+}
+
+fn parse_response(stdout: &str) -> Option<Response> {
+    let (body, status) = stdout.rsplit_once('\n').unwrap_or(("", stdout));
+    Some(Response {
+        status: status.trim().parse().ok()?,
+        body: body.to_string(),
+    })
+}
+
+// A curl config carrying the headers and the body, for `curl --config -`.
+// Headers often hold credentials, and curl's arguments can be read by any
+// user on the machine, so they never go on its command line. The body uses
+// `data-raw` so a leading `@` is never read as a file. `None` when there is
+// nothing to send.
+pub(crate) fn curl_config(headers: &[(String, String)], body: Option<&str>) -> Option<String> {
+    let mut config = String::new();
+    for (name, value) in headers {
+        config.push_str(&format!(
+            "header = {}\n",
+            config_quote(&format!("{}: {}", name, value))
+        ));
+    }
+    if let Some(body) = body {
+        config.push_str(&format!("data-raw = {}\n", config_quote(body)));
+    }
+    (!config.is_empty()).then_some(config)
+}
+
+// A double-quoted curl config value.
+fn config_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Renders `headers:` values, keeping the order they were written in.
+pub(crate) fn render_headers(
+    headers: Option<&IndexMap<String, String>>,
+    vars_map: &IndexMap<String, Value>,
+) -> Vec<(String, String)> {
+    headers
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| (name.clone(), utils::replace_placeholders(value, vars_map)))
+        .collect()
+}
+
+// `uri:` makes an HTTP request, by default from the target so it sees the
+// same network as the services on it, and fails unless the status is one of
+// `status_code`. The body is registered as `content`, and as `json` too when
+// it parses as JSON.
+pub fn process(
+    task_name: &str,
+    spec: &UriSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    register: Option<&String>,
+    no_log: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut headers = render_headers(spec.headers.as_ref(), vars_map);
+    let body = match (&spec.body, &spec.json) {
+        (Some(_), Some(_)) => {
+            return Err(format!("Task '{}': uri takes body or json, not both", task_name).into());
+        }
+        (Some(body), None) => Some(utils::replace_placeholders(body, vars_map)),
+        (None, Some(json)) => {
+            let json = utils::replace_placeholders_value_result(json, vars_map, no_log)
+                .map_err(|e| utils::task_error(task_name, e))?;
+            if !headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            {
+                headers.push(("Content-Type".to_string(), "application/json".to_string()));
+            }
+            Some(serde_json::to_string(&json)?)
+        }
+        (None, None) => None,
+    };
+    let method = match &spec.method {
+        Some(method) => utils::replace_placeholders(method, vars_map).to_ascii_uppercase(),
+        None if body.is_some() => "POST".to_string(),
+        None => "GET".to_string(),
+    };
+    let request = Request {
+        method,
+        url: utils::replace_placeholders(&spec.url, vars_map),
+        headers,
+        body,
+        timeout: spec.timeout.unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+    };
+    let expected = spec
+        .status_code
+        .clone()
+        .unwrap_or_else(|| vec![DEFAULT_STATUS_CODE]);
+
+    if !no_log {
+        println!(
+            "{}",
+            format!("> [uri] {} {}", request.method, request.url).magenta()
+        );
+    }
+    let response = request
+        .send(spec.from, is_localhost, session)
+        .map_err(|e| {
+            if no_log {
+                format!(
+                    "Task '{}': uri request failed (details hidden by no_log)",
+                    task_name
+                )
+            } else {
+                format!("Task '{}': uri {}", task_name, e)
+            }
+        })?;
+    let ok = expected.contains(&response.status);
+    if !no_log {
+        println!("{}", format!("Status {}", response.status).bright_black());
+    }
+
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed: false,
+            failed: !ok,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("status".to_string(), Value::from(response.status));
+            if let Ok(json) = serde_json::from_str::<Value>(&response.body) {
+                fields.insert("json".to_string(), json);
+            }
+            fields.insert("content".to_string(), Value::from(response.body.clone()));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    if !ok {
+        let expected: Vec<String> = expected.iter().map(u16::to_string).collect();
+        return Err(if no_log {
+            format!(
+                "Task '{}': uri returned status {} (details hidden by no_log)",
+                task_name, response.status
+            )
+        } else {
+            format!(
+                "Task '{}': uri {} {} returned status {}, expected {}: {}",
+                task_name,
+                request.method,
+                request.url,
+                response.status,
+                expected.join(" or "),
+                excerpt(&response.body)
+            )
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+// The start of a response body for an error message.
+pub(crate) fn excerpt(body: &str) -> String {
+    const LIMIT: usize = 200;
+    let body = body.trim();
+    match body.char_indices().nth(LIMIT) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_sends_headers_and_body_on_stdin() {
+        let request = Request {
+            method: "POST".to_string(),
+            url: "http://127.0.0.1:8080/deploys".to_string(),
+            headers: vec![("Authorization".to_string(), "Bearer t0k'en".to_string())],
+            body: Some("@/etc/passwd".to_string()),
+            timeout: 5,
+        };
+        assert_eq!(
+            request.command(),
+            "printf '%s' 'header = \"Authorization: Bearer t0k'\\''en\"\ndata-raw = \"@/etc/passwd\"\n' | curl -sS -X 'POST' --max-time 5 --config - -w '\\n%{http_code}' 'http://127.0.0.1:8080/deploys'"
+        );
+
+        let request = Request {
+            method: "GET".to_string(),
+            url: "http://127.0.0.1:8080/health".to_string(),
+            headers: Vec::new(),
+            body: None,
+            timeout: 5,
+        };
+        assert_eq!(
+            request.command(),
+            "curl -sS -X 'GET' --max-time 5 -w '\\n%{http_code}' 'http://127.0.0.1:8080/health'"
+        );
+    }
+
+    #[test]
+    fn curl_config_quotes_values() {
+        assert_eq!(curl_config(&[], None), None);
+        assert_eq!(
+            curl_config(
+                &[("X-Note".to_string(), "say \"hi\" \\o/".to_string())],
+                Some("line one\nline two\t@x")
+            )
+            .unwrap(),
+            "header = \"X-Note: say \\\"hi\\\" \\\\o/\"\ndata-raw = \"line one\\nline two\\t@x\"\n"
+        );
+    }
+
+    #[test]
+    fn parse_response_splits_off_the_status_line() {
+        let response = parse_response("{\"ok\":true}\nline two\n201").unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, "{\"ok\":true}\nline two");
+        let response = parse_response("204").unwrap();
+        assert_eq!((response.status, response.body.as_str()), (204, ""));
+        assert!(parse_response("curl noise").is_none());
+    }
+}
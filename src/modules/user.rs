@@ -0,0 +1,565 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+
+use crate::common::{EditState, GroupSpec, Register, UserSpec};
+use crate::utils;
+
+const MISSING_TOOLS: &str =
+    "needs getent, useradd, usermod and groupadd on the target (the shadow package on Alpine)";
+
+// A user account as `getent passwd` and `id` report it.
+#[derive(Debug, PartialEq)]
+struct Account {
+    uid: u32,
+    gid: u32,
+    home: String,
+    shell: String,
+    group: String,
+    groups: Vec<String>,
+}
+
+// The account fields a `user:` task asks for, rendered.
+#[derive(Debug, Default)]
+struct DesiredUser {
+    uid: Option<u32>,
+    group: Option<String>,
+    groups: Option<Vec<String>>,
+    shell: Option<String>,
+    home: Option<String>,
+    system: bool,
+    create_home: bool,
+}
+
+// What has to change and the command that changes it.
+#[derive(Debug, PartialEq)]
+struct Plan {
+    summary: String,
+    command: Option<Vec<String>>,
+}
+
+// `user:` makes a system account match the task: created with `useradd`,
+// brought in line with `usermod`, or removed with `userdel`. The current
+// account is read with `getent` first and only the fields that differ are
+// touched, so a second run reports no change. `authorized_keys:` replaces the
+// account's `~/.ssh/authorized_keys` with exactly the listed keys.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &UserSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rendered = |value: &str| utils::replace_placeholders(value, vars_map);
+    let name = rendered(&spec.name);
+    validate_name(&name).map_err(|e| format!("Task '{}': user {}", task_name, e))?;
+    if spec.state == EditState::Absent && spec.authorized_keys.is_some() {
+        return Err(format!(
+            "Task '{}': user authorized_keys cannot be combined with state absent",
+            task_name
+        )
+        .into());
+    }
+    let desired = DesiredUser {
+        uid: spec.uid,
+        group: spec.group.as_deref().map(rendered),
+        groups: spec
+            .groups
+            .as_ref()
+            .map(|groups| groups.iter().map(|group| rendered(group)).collect()),
+        shell: spec.shell.as_deref().map(rendered),
+        home: spec.home.as_deref().map(rendered),
+        system: spec.system,
+        create_home: spec.create_home.unwrap_or(true),
+    };
+    let authorized_keys: Option<Vec<String>> = spec.authorized_keys.as_ref().map(|keys| {
+        keys.iter()
+            .map(|key| rendered(key).trim().to_string())
+            .collect()
+    });
+
+    let target = |command: &str| {
+        run(
+            task_name,
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )
+    };
+
+    let stdout = target(&account_query(&name))?;
+    let current =
+        parse_account(&stdout).map_err(|e| format!("Task '{}': user {}", task_name, e))?;
+    let plan = match spec.state {
+        EditState::Present => plan_user(&name, &desired, current.as_ref()),
+        EditState::Absent => Plan {
+            summary: if current.is_some() {
+                "removed".to_string()
+            } else {
+                "absent".to_string()
+            },
+            command: current
+                .is_some()
+                .then(|| vec!["userdel".to_string(), name.clone()]),
+        },
+    };
+    let mut changed = false;
+    let mut summary = vec![plan.summary];
+    if let Some(command) = &plan.command {
+        target(&shell_words::join(command))?;
+        changed = true;
+    }
+    if let Some(keys) = &authorized_keys {
+        if target(&authorized_keys_command(&name, keys))?.trim() == "changed=true" {
+            changed = true;
+            summary.push("authorized_keys updated".to_string());
+        }
+    }
+
+    println!(
+        "{}",
+        format!("> [user] {} ({})", name, summary.join(", ")).magenta()
+    );
+    register_result(register, changed, &name, vars_map)
+}
+
+// `group:` creates, updates the gid of, or removes a group, after checking it
+// with `getent group`.
+#[allow(clippy::too_many_arguments)]
+pub fn process_group(
+    task_name: &str,
+    spec: &GroupSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let name = utils::replace_placeholders(&spec.name, vars_map);
+    validate_name(&name).map_err(|e| format!("Task '{}': group {}", task_name, e))?;
+    let target = |command: &str| {
+        run(
+            task_name,
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )
+    };
+
+    let stdout = target(&format!(
+        "command -v getent >/dev/null 2>&1 || exit 127\ngetent group {} || true",
+        utils::shell_escape(&name)
+    ))?;
+    let current_gid =
+        parse_group(&stdout).map_err(|e| format!("Task '{}': group {}", task_name, e))?;
+    let plan = plan_group(&name, spec.gid, spec.system, spec.state, current_gid);
+    if let Some(command) = &plan.command {
+        target(&shell_words::join(command))?;
+    }
+
+    println!(
+        "{}",
+        format!("> [group] {} ({})", name, plan.summary).magenta()
+    );
+    register_result(register, plan.command.is_some(), &name, vars_map)
+}
+
+fn run(
+    task_name: &str,
+    command: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (stdout, stderr, rc) = utils::run_shell_on_target(
+        command,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    match rc {
+        0 => Ok(stdout),
+        127 => Err(format!("Task '{}': {}", task_name, MISSING_TOOLS).into()),
+        rc => Err(format!(
+            "Task '{}': {} failed: exit {}: {}",
+            task_name,
+            command.lines().last().unwrap_or(command),
+            rc,
+            format!("{} {}", stdout.trim(), stderr.trim()).trim()
+        )
+        .into()),
+    }
+}
+
+fn register_result(
+    register: Option<&String>,
+    changed: bool,
+    name: &str,
+    vars_map: &mut IndexMap<String, Value>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("name".to_string(), Value::from(name));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+    Ok(())
+}
+
+// Account and group names go to the tools as single words, so anything that
+// could be read as an option or a `getent` key list is refused.
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name.starts_with('-')
+        || name
+            .chars()
+            .any(|c| c.is_whitespace() || c == ':' || c == ',' || c == '/')
+    {
+        return Err(format!("name '{}' is not a valid account name", name));
+    }
+    Ok(())
+}
+
+// Prints `passwd=`, `group=` and `groups=` for an existing account and
+// nothing for a missing one.
+fn account_query(name: &str) -> String {
+    format!(
+        r#"n={}
+command -v getent >/dev/null 2>&1 || exit 127
+entry=$(getent passwd "$n") || exit 0
+echo "passwd=$entry"
+echo "group=$(id -gn "$n")"
+echo "groups=$(id -Gn "$n")""#,
+        utils::shell_escape(name)
+    )
+}
+
+fn parse_account(stdout: &str) -> Result<Option<Account>, String> {
+    let fields: IndexMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.trim_end_matches('\r').split_once('='))
+        .collect();
+    let Some(passwd) = fields.get("passwd") else {
+        return Ok(None);
+    };
+    let unexpected = || format!("unexpected getent output: {}", stdout.trim());
+    let entry: Vec<&str> = passwd.split(':').collect();
+    if entry.len() != 7 {
+        return Err(unexpected());
+    }
+    Ok(Some(Account {
+        uid: entry[2].parse().map_err(|_| unexpected())?,
+        gid: entry[3].parse().map_err(|_| unexpected())?,
+        home: entry[5].to_string(),
+        shell: entry[6].to_string(),
+        group: fields.get("group").ok_or_else(unexpected)?.to_string(),
+        groups: fields
+            .get("groups")
+            .ok_or_else(unexpected)?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+    }))
+}
+
+// The gid of a `getent group` line, or `None` when the group is missing.
+fn parse_group(stdout: &str) -> Result<Option<u32>, String> {
+    let Some(line) = stdout.lines().find(|line| !line.trim().is_empty()) else {
+        return Ok(None);
+    };
+    line.split(':')
+        .nth(2)
+        .and_then(|gid| gid.trim().parse().ok())
+        .map(Some)
+        .ok_or_else(|| format!("unexpected getent output: {}", stdout.trim()))
+}
+
+fn plan_user(name: &str, desired: &DesiredUser, current: Option<&Account>) -> Plan {
+    let Some(current) = current else {
+        let mut command = vec!["useradd".to_string()];
+        if desired.system {
+            command.push("-r".to_string());
+        }
+        command.push(if desired.create_home { "-m" } else { "-M" }.to_string());
+        command.extend(user_options(desired));
+        command.push(name.to_string());
+        return Plan {
+            summary: "created".to_string(),
+            command: Some(command),
+        };
+    };
+
+    let mut changes = Vec::new();
+    let mut options = Vec::new();
+    if let Some(uid) = desired.uid.filter(|uid| *uid != current.uid) {
+        changes.push("uid");
+        options.extend(["-u".to_string(), uid.to_string()]);
+    }
+    if let Some(group) = &desired.group {
+        let same = match group.parse::<u32>() {
+            Ok(gid) => gid == current.gid,
+            Err(_) => *group == current.group,
+        };
+        if !same {
+            changes.push("group");
+            options.extend(["-g".to_string(), group.clone()]);
+        }
+    }
+    if let Some(groups) = &desired.groups {
+        let mut want: Vec<&str> = groups.iter().map(String::as_str).collect();
+        want.sort_unstable();
+        want.dedup();
+        let mut have: Vec<&str> = current
+            .groups
+            .iter()
+            .map(String::as_str)
+            .filter(|group| *group != current.group)
+            .collect();
+        have.sort_unstable();
+        have.dedup();
+        if want != have {
+            changes.push("groups");
+            options.extend(["-G".to_string(), groups.join(",")]);
+        }
+    }
+    if let Some(shell) = desired
+        .shell
+        .as_ref()
+        .filter(|shell| **shell != current.shell)
+    {
+        changes.push("shell");
+        options.extend(["-s".to_string(), shell.clone()]);
+    }
+    if let Some(home) = desired.home.as_ref().filter(|home| **home != current.home) {
+        changes.push("home");
+        options.extend(["-d".to_string(), home.clone()]);
+    }
+
+    if changes.is_empty() {
+        return Plan {
+            summary: "unchanged".to_string(),
+            command: None,
+        };
+    }
+    let mut command = vec!["usermod".to_string()];
+    command.extend(options);
+    command.push(name.to_string());
+    Plan {
+        summary: format!("updated {}", changes.join(", ")),
+        command: Some(command),
+    }
+}
+
+// The `useradd` options for the fields that were set.
+fn user_options(desired: &DesiredUser) -> Vec<String> {
+    let mut options = Vec::new();
+    if let Some(uid) = desired.uid {
+        options.extend(["-u".to_string(), uid.to_string()]);
+    }
+    if let Some(group) = &desired.group {
+        options.extend(["-g".to_string(), group.clone()]);
+    }
+    if let Some(groups) = desired.groups.as_ref().filter(|groups| !groups.is_empty()) {
+        options.extend(["-G".to_string(), groups.join(",")]);
+    }
+    if let Some(shell) = &desired.shell {
+        options.extend(["-s".to_string(), shell.clone()]);
+    }
+    if let Some(home) = &desired.home {
+        options.extend(["-d".to_string(), home.clone()]);
+    }
+    options
+}
+
+fn plan_group(
+    name: &str,
+    gid: Option<u32>,
+    system: bool,
+    state: EditState,
+    current_gid: Option<u32>,
+) -> Plan {
+    let unchanged = |summary: &str| Plan {
+        summary: summary.to_string(),
+        command: None,
+    };
+    match (state, current_gid) {
+        (EditState::Absent, None) => unchanged("absent"),
+        (EditState::Absent, Some(_)) => Plan {
+            summary: "removed".to_string(),
+            command: Some(vec!["groupdel".to_string(), name.to_string()]),
+        },
+        (EditState::Present, None) => {
+            let mut command = vec!["groupadd".to_string()];
+            if system {
+                command.push("-r".to_string());
+            }
+            if let Some(gid) = gid {
+                command.extend(["-g".to_string(), gid.to_string()]);
+            }
+            command.push(name.to_string());
+            Plan {
+                summary: "created".to_string(),
+                command: Some(command),
+            }
+        }
+        (EditState::Present, Some(current)) => match gid.filter(|gid| *gid != current) {
+            Some(gid) => Plan {
+                summary: "updated gid".to_string(),
+                command: Some(vec![
+                    "groupmod".to_string(),
+                    "-g".to_string(),
+                    gid.to_string(),
+                    name.to_string(),
+                ]),
+            },
+            None => unchanged("unchanged"),
+        },
+    }
+}
+
+// Rewrites `~/.ssh/authorized_keys` when it doesn't hold exactly `keys`, and
+// prints whether it did.
+fn authorized_keys_command(name: &str, keys: &[String]) -> String {
+    format!(
+        r#"n={}; want={}
+home=$(getent passwd "$n" | cut -d: -f6); group=$(id -gn "$n")
+f="$home/.ssh/authorized_keys"
+if [ -f "$f" ] && [ "$(cat "$f")" = "$want" ]; then echo changed=false; exit 0; fi
+mkdir -p "$home/.ssh"
+printf '%s\n' "$want" > "$f"
+chmod 700 "$home/.ssh"; chmod 600 "$f"
+chown "$n:$group" "$home/.ssh" "$f"
+echo changed=true"#,
+        utils::shell_escape(name),
+        utils::shell_escape(&keys.join("\n"))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account() -> Account {
+        parse_account("passwd=app:x:990:990::/srv/app:/bin/sh\ngroup=app\ngroups=app docker\n")
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_account_reads_getent_and_id() {
+        assert_eq!(
+            account(),
+            Account {
+                uid: 990,
+                gid: 990,
+                home: "/srv/app".to_string(),
+                shell: "/bin/sh".to_string(),
+                group: "app".to_string(),
+                groups: vec!["app".to_string(), "docker".to_string()],
+            }
+        );
+        assert_eq!(parse_account("").unwrap(), None);
+        assert!(parse_account("passwd=app:x\n").is_err());
+    }
+
+    #[test]
+    fn plan_user_creates_a_missing_account() {
+        let desired = DesiredUser {
+            uid: Some(990),
+            groups: Some(vec!["docker".to_string(), "adm".to_string()]),
+            shell: Some("/usr/sbin/nologin".to_string()),
+            system: true,
+            create_home: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            shell_words::join(plan_user("app", &desired, None).command.unwrap()),
+            "useradd -r -m -u 990 -G docker,adm -s /usr/sbin/nologin app"
+        );
+    }
+
+    #[test]
+    fn plan_user_only_changes_fields_that_differ() {
+        let desired = DesiredUser {
+            uid: Some(990),
+            group: Some("990".to_string()),
+            groups: Some(vec!["docker".to_string()]),
+            home: Some("/srv/app".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            plan_user("app", &desired, Some(&account())),
+            Plan {
+                summary: "unchanged".to_string(),
+                command: None,
+            }
+        );
+
+        let desired = DesiredUser {
+            groups: Some(Vec::new()),
+            shell: Some("/bin/bash".to_string()),
+            ..Default::default()
+        };
+        let plan = plan_user("app", &desired, Some(&account()));
+        assert_eq!(plan.summary, "updated groups, shell");
+        assert_eq!(
+            shell_words::join(plan.command.unwrap()),
+            "usermod -G '' -s /bin/bash app"
+        );
+    }
+
+    #[test]
+    fn plan_group_follows_state_and_gid() {
+        let plan = plan_group("deploy", Some(1500), true, EditState::Present, None);
+        assert_eq!(
+            shell_words::join(plan.command.unwrap()),
+            "groupadd -r -g 1500 deploy"
+        );
+        let plan = plan_group("deploy", Some(1500), false, EditState::Present, Some(1500));
+        assert!(plan.command.is_none());
+        let plan = plan_group("deploy", Some(1501), false, EditState::Present, Some(1500));
+        assert_eq!(
+            shell_words::join(plan.command.unwrap()),
+            "groupmod -g 1501 deploy"
+        );
+        let plan = plan_group("deploy", None, false, EditState::Absent, None);
+        assert!(plan.command.is_none());
+        assert_eq!(parse_group("deploy:x:1500:app\n").unwrap(), Some(1500));
+        assert_eq!(parse_group("").unwrap(), None);
+    }
+
+    #[test]
+    fn names_that_look_like_options_are_refused() {
+        assert!(validate_name("app").is_ok());
+        assert!(validate_name("www-data").is_ok());
+        assert!(validate_name("-r").is_err());
+        assert!(validate_name("a b").is_err());
+        assert!(validate_name("").is_err());
+    }
+}
@@ -6,19 +6,73 @@ use ssh2::Session;
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::common::{Register, VerifyExpectation, VerifySpec};
+use crate::common::{
+    Register, RunFrom, VerifyExpectation, VerifyHttp, VerifySpec, VerifyTcp, VerifyTextExpectation,
+};
+use crate::modules::{uri, wait_for};
 use crate::utils;
 
+const DEFAULT_STATUS_CODE: u16 = 200;
+
 #[derive(Debug)]
 enum Matcher {
     Equals(String),
     Regex { source: String, compiled: Regex },
+    Contains(String),
+    NotContains(String),
+    Json { path: String, expected: Value },
+}
+
+// Everything `expect:` asks of a result. All of it has to hold.
+#[derive(Debug, Default)]
+struct Expectation {
+    stdout: Vec<Matcher>,
+    stderr: Vec<Matcher>,
+    rc: Option<i32>,
+}
+
+// What one verification attempt checks: a shell command on the target, an
+// HTTP endpoint, or a TCP port.
+#[derive(Debug)]
+enum Probe {
+    Command(String),
+    Http {
+        request: uri::Request,
+        status_codes: Vec<u16>,
+        from: RunFrom,
+    },
+    Tcp {
+        host: String,
+        port: u16,
+        from: RunFrom,
+    },
+}
+
+impl Probe {
+    fn describe(&self) -> String {
+        match self {
+            Probe::Command(command) => command.clone(),
+            Probe::Http { request, from, .. } => {
+                format!("{} {}{}", request.method, request.url, from_suffix(*from))
+            }
+            Probe::Tcp { host, port, from } => {
+                format!("tcp {}:{}{}", host, port, from_suffix(*from))
+            }
+        }
+    }
+}
+
+fn from_suffix(from: RunFrom) -> &'static str {
+    match from {
+        RunFrom::Target => "",
+        RunFrom::Control => " (from control)",
+    }
 }
 
 #[derive(Debug)]
 struct VerifyResolved {
-    command: String,
-    matcher: Option<Matcher>,
+    probe: Probe,
+    expect: Expectation,
     attempts: u32,
     delay_seconds: u64,
     max_elapsed_seconds: Option<u64>,
@@ -30,48 +84,59 @@ fn resolve(
     vars_map: &IndexMap<String, Value>,
     no_log: bool,
 ) -> Result<VerifyResolved, Box<dyn std::error::Error>> {
-    let command = utils::replace_placeholders(&spec.command, vars_map);
-    if command.trim().is_empty() {
-        return Err(format!("Task '{}': verify command must not be empty", task_name).into());
-    }
-
-    let matcher = match &spec.expect {
-        None => None,
-        Some(VerifyExpectation {
-            equals: Some(expected),
-            regex: None,
-        }) => Some(Matcher::Equals(utils::replace_placeholders(
-            expected, vars_map,
-        ))),
-        Some(VerifyExpectation {
-            equals: None,
-            regex: Some(pattern),
-        }) => {
-            let source = utils::replace_placeholders(pattern, vars_map);
-            let compiled = Regex::new(&source).map_err(|e| {
-                if no_log {
-                    format!(
-                        "Task '{}': verify expect.regex is invalid (details hidden by no_log)",
-                        task_name
-                    )
-                } else {
-                    format!(
-                        "Task '{}': verify expect.regex is invalid: {}",
-                        task_name, e
-                    )
-                }
-            })?;
-            Some(Matcher::Regex { source, compiled })
+    let probe = match (&spec.command, &spec.http, &spec.tcp) {
+        (Some(command), None, None) => {
+            let command = utils::replace_placeholders(command, vars_map);
+            if command.trim().is_empty() {
+                return Err(
+                    format!("Task '{}': verify command must not be empty", task_name).into(),
+                );
+            }
+            Probe::Command(command)
         }
-        Some(_) => {
+        (None, Some(http), None) => resolve_http(task_name, http, vars_map)?,
+        (None, None, Some(tcp)) => {
+            if spec.expect.is_some() {
+                return Err(format!(
+                    "Task '{}': verify expect does not apply to a tcp probe",
+                    task_name
+                )
+                .into());
+            }
+            resolve_tcp(tcp, vars_map)
+        }
+        _ => {
             return Err(format!(
-                "Task '{}': verify expect must set exactly one of equals or regex",
+                "Task '{}': verify takes exactly one of command, http or tcp",
                 task_name
             )
             .into())
         }
     };
 
+    let mut expect = match &spec.expect {
+        Some(expectation) => resolve_expectation(task_name, expectation, vars_map, no_log)?,
+        None => Expectation::default(),
+    };
+    if let Probe::Http { .. } = probe {
+        if expect.rc.is_some() || !expect.stderr.is_empty() {
+            return Err(format!(
+                "Task '{}': verify expect.rc and expect.stderr only apply to a command",
+                task_name
+            )
+            .into());
+        }
+        if let Some(http) = &spec.http {
+            expect.stdout.extend(resolve_json_matchers(
+                task_name,
+                "http.json",
+                http.json.as_ref(),
+                vars_map,
+                no_log,
+            )?);
+        }
+    }
+
     let (attempts, delay_seconds, max_elapsed_seconds) = spec
         .retry
         .as_ref()
@@ -99,15 +164,301 @@ fn resolve(
     }
 
     Ok(VerifyResolved {
-        command,
-        matcher,
+        probe,
+        expect,
         attempts,
         delay_seconds,
         max_elapsed_seconds,
     })
 }
 
-fn retry_wait(
+fn resolve_expectation(
+    task_name: &str,
+    expectation: &VerifyExpectation,
+    vars_map: &IndexMap<String, Value>,
+    no_log: bool,
+) -> Result<Expectation, Box<dyn std::error::Error>> {
+    let stdout = VerifyTextExpectation {
+        equals: expectation.equals.clone(),
+        regex: expectation.regex.clone(),
+        contains: expectation.contains.clone(),
+        not_contains: expectation.not_contains.clone(),
+    };
+    let mut resolved = Expectation {
+        stdout: resolve_text_matchers(task_name, "expect", &stdout, vars_map, no_log)?,
+        stderr: match &expectation.stderr {
+            Some(stderr) => {
+                resolve_text_matchers(task_name, "expect.stderr", stderr, vars_map, no_log)?
+            }
+            None => Vec::new(),
+        },
+        rc: expectation.rc,
+    };
+    resolved.stdout.extend(resolve_json_matchers(
+        task_name,
+        "expect.json",
+        expectation.json.as_ref(),
+        vars_map,
+        no_log,
+    )?);
+    if resolved.stdout.is_empty() && resolved.stderr.is_empty() && resolved.rc.is_none() {
+        return Err(format!(
+            "Task '{}': verify expect must set at least one matcher",
+            task_name
+        )
+        .into());
+    }
+    Ok(resolved)
+}
+
+// The text matchers of `expect:` or `expect.stderr:`, in a fixed order.
+fn resolve_text_matchers(
+    task_name: &str,
+    field: &str,
+    expectation: &VerifyTextExpectation,
+    vars_map: &IndexMap<String, Value>,
+    no_log: bool,
+) -> Result<Vec<Matcher>, Box<dyn std::error::Error>> {
+    let rendered = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|v| utils::replace_placeholders(v, vars_map))
+    };
+    let mut matchers = Vec::new();
+    if let Some(expected) = rendered(&expectation.equals) {
+        matchers.push(Matcher::Equals(expected));
+    }
+    if let Some(source) = rendered(&expectation.regex) {
+        let compiled = Regex::new(&source).map_err(|e| {
+            if no_log {
+                format!(
+                    "Task '{}': verify {}.regex is invalid (details hidden by no_log)",
+                    task_name, field
+                )
+            } else {
+                format!(
+                    "Task '{}': verify {}.regex is invalid: {}",
+                    task_name, field, e
+                )
+            }
+        })?;
+        matchers.push(Matcher::Regex { source, compiled });
+    }
+    if let Some(expected) = rendered(&expectation.contains) {
+        matchers.push(Matcher::Contains(expected));
+    }
+    if let Some(expected) = rendered(&expectation.not_contains) {
+        matchers.push(Matcher::NotContains(expected));
+    }
+    Ok(matchers)
+}
+
+fn resolve_json_matchers(
+    task_name: &str,
+    field: &str,
+    json: Option<&IndexMap<String, Value>>,
+    vars_map: &IndexMap<String, Value>,
+    no_log: bool,
+) -> Result<Vec<Matcher>, Box<dyn std::error::Error>> {
+    let mut matchers = Vec::new();
+    for (path, expected) in json.into_iter().flatten() {
+        if json_path(path).is_none() {
+            return Err(format!(
+                "Task '{}': verify {} path '{}' is invalid",
+                task_name, field, path
+            )
+            .into());
+        }
+        let expected = utils::replace_placeholders_value_result(expected, vars_map, no_log)
+            .map_err(|e| utils::task_error(task_name, e))?;
+        matchers.push(Matcher::Json {
+            path: path.clone(),
+            expected,
+        });
+    }
+    Ok(matchers)
+}
+
+fn resolve_http(
+    task_name: &str,
+    http: &VerifyHttp,
+    vars_map: &IndexMap<String, Value>,
+) -> Result<Probe, Box<dyn std::error::Error>> {
+    let url = utils::replace_placeholders(&http.url, vars_map);
+    if url.trim().is_empty() {
+        return Err(format!("Task '{}': verify http.url must not be empty", task_name).into());
+    }
+    Ok(Probe::Http {
+        request: uri::Request {
+            method: http
+                .method
+                .as_deref()
+                .map(|method| utils::replace_placeholders(method, vars_map).to_ascii_uppercase())
+                .unwrap_or_else(|| "GET".to_string()),
+            url,
+            headers: uri::render_headers(http.headers.as_ref(), vars_map),
+            body: None,
+            timeout: http.timeout.unwrap_or(uri::DEFAULT_TIMEOUT_SECONDS),
+        },
+        status_codes: http
+            .status_code
+            .clone()
+            .unwrap_or_else(|| vec![DEFAULT_STATUS_CODE]),
+        from: http.from,
+    })
+}
+
+fn resolve_tcp(tcp: &VerifyTcp, vars_map: &IndexMap<String, Value>) -> Probe {
+    Probe::Tcp {
+        host: tcp
+            .host
+            .as_deref()
+            .map(|host| utils::replace_placeholders(host, vars_map))
+            .unwrap_or_else(|| wait_for::DEFAULT_HOST.to_string()),
+        port: tcp.port,
+        from: tcp.from,
+    }
+}
+
+// Splits `$.checks[0].status` (the `$` is optional) into its keys and
+// indexes.
+fn json_path(path: &str) -> Option<Vec<String>> {
+    let path = path.trim();
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+    for part in path
+        .replace('[', ".[")
+        .split('.')
+        .skip_while(|p| p.is_empty())
+    {
+        let segment = match part.strip_prefix('[') {
+            Some(index) => index.strip_suffix(']')?,
+            None => part,
+        };
+        if segment.is_empty() || segment.contains(']') {
+            return None;
+        }
+        segments.push(segment.to_string());
+    }
+    Some(segments)
+}
+
+fn json_field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    json_path(path)?
+        .iter()
+        .try_fold(value, |current, segment| match current {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            Value::Object(fields) => fields.get(segment),
+            _ => None,
+        })
+}
+
+// A quoted expectation like `"200"` also matches the number or boolean it
+// spells, since template values always render as strings.
+fn json_value_matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::String(expected), Value::Number(_) | Value::Bool(_)) => {
+            serde_json::from_str::<Value>(expected).is_ok_and(|parsed| parsed == *actual)
+        }
+        _ => expected == actual,
+    }
+}
+
+// Checks an HTTP response against the expected status, then the body
+// matchers.
+fn http_mismatch(
+    response: &uri::Response,
+    status_codes: &[u16],
+    matchers: &[Matcher],
+) -> Option<String> {
+    if !status_codes.contains(&response.status) {
+        let expected: Vec<String> = status_codes.iter().map(u16::to_string).collect();
+        return Some(format!(
+            "expected status {}, got {}: {}",
+            expected.join(" or "),
+            response.status,
+            uri::excerpt(&response.body)
+        ));
+    }
+    mismatch(matchers, "body", &response.body)
+}
+
+// Checks a command's exit status, stdout and stderr. Without `rc:` any
+// non-zero exit fails on its own; with it, the status is one more matcher.
+fn command_mismatch(expect: &Expectation, stdout: &str, stderr: &str, rc: i32) -> Option<String> {
+    let mut failures = Vec::new();
+    match expect.rc {
+        None if rc != 0 => {
+            let detail = if stderr.trim().is_empty() {
+                stdout.trim()
+            } else {
+                stderr.trim()
+            };
+            return Some(if detail.is_empty() {
+                format!("command exited with status {}", rc)
+            } else {
+                format!("command exited with status {}: {}", rc, detail)
+            });
+        }
+        Some(expected) if rc != expected => {
+            failures.push(format!("expected exit status {}, got {}", expected, rc));
+        }
+        _ => {}
+    }
+    failures.extend(mismatch(&expect.stdout, "stdout", stdout));
+    failures.extend(mismatch(&expect.stderr, "stderr", stderr));
+    (!failures.is_empty()).then(|| failures.join("; "))
+}
+
+fn tcp_probe(
+    host: &str,
+    port: u16,
+    from: RunFrom,
+    is_localhost: bool,
+    session: Option<&Session>,
+) -> Result<(), String> {
+    let command = format!("{}\necho \"$r\"", wait_for::port_state_command(host, port));
+    let (stdout, stderr, rc) = run_from(&command, from, is_localhost, session)
+        .map_err(|error| format!("port check failed: {}", error))?;
+    match (rc, stdout.trim()) {
+        (0, "open") => Ok(()),
+        (0, "closed") => Err(format!(
+            "nothing accepted a connection on {}:{}",
+            host, port
+        )),
+        _ => Err(format!(
+            "port check failed: exit {}: {}",
+            rc,
+            format!("{} {}", stdout.trim(), stderr.trim()).trim()
+        )),
+    }
+}
+
+fn passed(stdout: String, stderr: String, rc: i32) -> Register {
+    Register {
+        stdout,
+        stderr,
+        rc,
+        changed: false,
+        failed: false,
+    }
+}
+
+fn run_from(
+    command: &str,
+    from: RunFrom,
+    is_localhost: bool,
+    session: Option<&Session>,
+) -> Result<(String, String, i32), Box<dyn std::error::Error>> {
+    match from {
+        RunFrom::Control => utils::run_shell_on_target(command, true, None, false, "", None),
+        RunFrom::Target => {
+            utils::run_shell_on_target(command, is_localhost, session, false, "", None)
+        }
+    }
+}
+
+pub(crate) fn retry_wait(
     delay_seconds: u64,
     max_elapsed_seconds: Option<u64>,
     elapsed: Duration,
@@ -124,21 +475,64 @@ fn retry_wait(
     }
 }
 
-fn mismatch(matcher: Option<&Matcher>, actual: &str) -> Option<String> {
-    match matcher {
-        None => None,
-        Some(Matcher::Equals(expected)) if actual != expected => Some(format!(
-            "expected stdout {}, got {}",
-            quoted(expected),
-            quoted(actual)
-        )),
-        Some(Matcher::Regex { source, compiled }) if !compiled.is_match(actual) => Some(format!(
-            "expected stdout to match regex {}, got {}",
-            quoted(source),
-            quoted(actual)
-        )),
-        Some(_) => None,
-    }
+// Every failed matcher, one after the other.
+fn mismatch(matchers: &[Matcher], subject: &str, actual: &str) -> Option<String> {
+    let mut parsed = None;
+    let failures: Vec<String> = matchers
+        .iter()
+        .filter_map(|matcher| match matcher {
+            Matcher::Equals(expected) if actual != expected => Some(format!(
+                "expected {} {}, got {}",
+                subject,
+                quoted(expected),
+                quoted(actual)
+            )),
+            Matcher::Regex { source, compiled } if !compiled.is_match(actual) => Some(format!(
+                "expected {} to match regex {}, got {}",
+                subject,
+                quoted(source),
+                quoted(actual)
+            )),
+            Matcher::Contains(expected) if !actual.contains(expected.as_str()) => Some(format!(
+                "expected {} to contain {}, got {}",
+                subject,
+                quoted(expected),
+                quoted(actual)
+            )),
+            Matcher::NotContains(unexpected) if actual.contains(unexpected.as_str()) => {
+                Some(format!(
+                    "expected {} not to contain {}, got {}",
+                    subject,
+                    quoted(unexpected),
+                    quoted(actual)
+                ))
+            }
+            Matcher::Json { path, expected } => {
+                let json = parsed.get_or_insert_with(|| serde_json::from_str::<Value>(actual));
+                match json.as_ref().map(|json| json_field(json, path)) {
+                    Err(_) => Some(format!(
+                        "expected {} json {} to be {}, but {} is not JSON: {}",
+                        subject,
+                        path,
+                        expected,
+                        subject,
+                        quoted(&uri::excerpt(actual))
+                    )),
+                    Ok(None) => Some(format!(
+                        "expected {} json {} to be {}, but it is missing",
+                        subject, path, expected
+                    )),
+                    Ok(Some(value)) if !json_value_matches(expected, value) => Some(format!(
+                        "expected {} json {} to be {}, got {}",
+                        subject, path, expected, value
+                    )),
+                    Ok(Some(_)) => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+    (!failures.is_empty()).then(|| failures.join("; "))
 }
 
 fn quoted(value: &str) -> String {
@@ -176,7 +570,10 @@ pub fn process(
     };
 
     if !no_log {
-        println!("{}", format!("> [verify] {}", resolved.command).magenta());
+        println!(
+            "{}",
+            format!("> [verify] {}", resolved.probe.describe()).magenta()
+        );
     }
 
     let mut failure = String::new();
@@ -185,44 +582,62 @@ pub fn process(
     let mut elapsed_limit_reached = false;
     for attempt in 1..=resolved.attempts {
         attempts_run = attempt;
-        match utils::run_shell_on_target_with_context(
-            &resolved.command,
-            is_localhost,
-            session,
-            become_enabled,
-            become_method,
-            become_password,
-            task_chdir,
-            login_shell,
-            environment_resolved.as_ref(),
-        ) {
-            Ok((stdout, stderr, rc)) => {
-                if rc != 0 {
-                    let detail = if stderr.trim().is_empty() {
-                        stdout.trim()
-                    } else {
-                        stderr.trim()
-                    };
-                    failure = if detail.is_empty() {
-                        format!("command exited with status {}", rc)
-                    } else {
-                        format!("command exited with status {}: {}", rc, detail)
-                    };
-                } else if let Some(detail) = mismatch(resolved.matcher.as_ref(), &stdout) {
-                    failure = detail;
-                } else {
-                    if let Some(register) = register {
-                        let value = serde_json::to_value(Register { stdout, stderr, rc })?;
-                        vars_map.insert(register.clone(), value);
-                        println!(
-                            "{}",
-                            format!("Registering output to: {}", register).yellow()
-                        );
+        let outcome = match &resolved.probe {
+            Probe::Command(command) => utils::run_shell_on_target_with_context(
+                command,
+                is_localhost,
+                session,
+                become_enabled,
+                become_method,
+                become_password,
+                task_chdir,
+                login_shell,
+                environment_resolved.as_ref(),
+            )
+            .map_err(|error| format!("command execution failed: {}", error))
+            .and_then(|(stdout, stderr, rc)| {
+                match command_mismatch(&resolved.expect, &stdout, &stderr, rc) {
+                    Some(detail) => Err(detail),
+                    None => Ok((passed(stdout, stderr, rc), None)),
+                }
+            }),
+            Probe::Http {
+                request,
+                status_codes,
+                from,
+            } => {
+                request
+                    .send(*from, is_localhost, session)
+                    .and_then(|response| {
+                        match http_mismatch(&response, status_codes, &resolved.expect.stdout) {
+                            Some(detail) => Err(detail),
+                            None => Ok((passed(String::new(), String::new(), 0), Some(response))),
+                        }
+                    })
+            }
+            Probe::Tcp { host, port, from } => tcp_probe(host, *port, *from, is_localhost, session)
+                .map(|()| (passed(String::new(), String::new(), 0), None)),
+        };
+        match outcome {
+            Ok((result, response)) => {
+                if let Some(register) = register {
+                    let mut value = serde_json::to_value(result)?;
+                    if let (Value::Object(fields), Some(response)) = (&mut value, response) {
+                        fields.insert("status".to_string(), Value::from(response.status));
+                        if let Ok(json) = serde_json::from_str::<Value>(&response.body) {
+                            fields.insert("json".to_string(), json);
+                        }
+                        fields.insert("content".to_string(), Value::from(response.body));
                     }
-                    return Ok(());
+                    vars_map.insert(register.clone(), value);
+                    println!(
+                        "{}",
+                        format!("Registering output to: {}", register).yellow()
+                    );
                 }
+                return Ok(());
             }
-            Err(error) => failure = format!("command execution failed: {}", error),
+            Err(detail) => failure = detail,
         }
 
         if attempt < resolved.attempts {
@@ -302,15 +717,23 @@ pub fn process(
 
 #[cfg(test)]
 mod tests {
-    use super::{mismatch, resolve, retry_wait, Matcher};
-    use crate::common::{VerifyExpectation, VerifyRetry, VerifySpec};
+    use super::{
+        command_mismatch, http_mismatch, json_field, mismatch, resolve, retry_wait, Matcher, Probe,
+    };
+    use crate::common::{
+        VerifyExpectation, VerifyRetry, VerifySpec, VerifyTcp, VerifyTextExpectation,
+    };
+    use crate::modules::uri::Response;
     use indexmap::IndexMap;
     use regex::Regex;
+    use serde_json::{json, Value};
     use std::time::Duration;
 
     fn spec(expect: Option<VerifyExpectation>) -> VerifySpec {
         VerifySpec {
-            command: "echo healthy".to_string(),
+            command: Some("echo healthy".to_string()),
+            http: None,
+            tcp: None,
             expect,
             retry: None,
         }
@@ -319,8 +742,9 @@ mod tests {
     #[test]
     fn equals_requires_exact_output() {
         let matcher = Matcher::Equals("healthy".to_string());
-        assert!(mismatch(Some(&matcher), "healthy").is_none());
-        assert!(mismatch(Some(&matcher), " healthy").is_some());
+        assert!(mismatch(&[matcher], "stdout", "healthy").is_none());
+        let matcher = Matcher::Equals("healthy".to_string());
+        assert!(mismatch(&[matcher], "stdout", " healthy").is_some());
     }
 
     #[test]
@@ -329,25 +753,163 @@ mod tests {
             source: "^health(y|ier)$".to_string(),
             compiled: Regex::new("^health(y|ier)$").unwrap(),
         };
-        assert!(mismatch(Some(&matcher), "healthy").is_none());
-        assert!(mismatch(Some(&matcher), "unhealthy").is_some());
+        let matchers = [matcher];
+        assert!(mismatch(&matchers, "stdout", "healthy").is_none());
+        assert!(mismatch(&matchers, "stdout", "unhealthy").is_some());
     }
 
     #[test]
-    fn resolve_rejects_both_matchers() {
+    fn mismatch_lists_every_failed_matcher() {
+        let matchers = [
+            Matcher::Contains("ready".to_string()),
+            Matcher::NotContains("panic".to_string()),
+            Matcher::Json {
+                path: "$.state".to_string(),
+                expected: json!("ready"),
+            },
+        ];
+        assert!(mismatch(&matchers, "stdout", r#"{"state":"ready"}"#).is_none());
+        assert_eq!(
+            mismatch(&matchers, "stdout", "panic: not ready").unwrap(),
+            "expected stdout not to contain 'panic', got 'panic: not ready'; \
+             expected stdout json $.state to be \"ready\", but stdout is not JSON: 'panic: not ready'"
+        );
+    }
+
+    #[test]
+    fn command_mismatch_checks_rc_and_stderr() {
+        let verify = spec(Some(VerifyExpectation {
+            rc: Some(1),
+            stderr: Some(VerifyTextExpectation {
+                contains: Some("degraded".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let expect = resolve("Example", &verify, &IndexMap::new(), false)
+            .unwrap()
+            .expect;
+        assert!(command_mismatch(&expect, "", "degraded: db", 1).is_none());
+        assert_eq!(
+            command_mismatch(&expect, "", "ok", 0).unwrap(),
+            "expected exit status 1, got 0; expected stderr to contain 'degraded', got 'ok'"
+        );
+
+        let expect = resolve("Example", &spec(None), &IndexMap::new(), false)
+            .unwrap()
+            .expect;
+        assert_eq!(
+            command_mismatch(&expect, "", "boom", 2).unwrap(),
+            "command exited with status 2: boom"
+        );
+    }
+    #[test]
+    fn json_field_follows_keys_and_indexes() {
+        let body = json!({"status": "ok", "checks": [{"name": "db", "up": true}]});
+        assert_eq!(json_field(&body, "$.status"), Some(&json!("ok")));
+        assert_eq!(json_field(&body, "checks[0].up"), Some(&json!(true)));
+        assert_eq!(json_field(&body, "$"), Some(&body));
+        assert_eq!(json_field(&body, "$.checks[1].up"), None);
+        assert_eq!(json_field(&body, "$.status.code"), None);
+        assert_eq!(json_field(&body, "$.checks[0"), None);
+    }
+
+    #[test]
+    fn http_mismatch_checks_status_then_body_then_fields() {
+        let response = Response {
+            status: 200,
+            body: r#"{"status":"ok","version":"1.2","workers":4}"#.to_string(),
+        };
+        let fields = [
+            Matcher::Json {
+                path: "$.status".to_string(),
+                expected: json!("ok"),
+            },
+            Matcher::Json {
+                path: "$.workers".to_string(),
+                expected: json!("4"),
+            },
+        ];
+        assert_eq!(http_mismatch(&response, &[200], &fields), None);
+
+        let detail = http_mismatch(&response, &[204], &fields).unwrap();
+        assert!(
+            detail.starts_with("expected status 204, got 200"),
+            "{}",
+            detail
+        );
+
+        let matchers = [Matcher::Equals("ok".to_string())];
+        let detail = http_mismatch(&response, &[200], &matchers).unwrap();
+        assert!(detail.starts_with("expected body 'ok'"), "{}", detail);
+
+        let fields = [Matcher::Json {
+            path: "$.version".to_string(),
+            expected: json!("1.3"),
+        }];
+        assert_eq!(
+            http_mismatch(&response, &[200], &fields).unwrap(),
+            "expected body json $.version to be \"1.3\", got \"1.2\""
+        );
+        let fields = [Matcher::Json {
+            path: "$.missing".to_string(),
+            expected: Value::Null,
+        }];
+        assert!(http_mismatch(&response, &[200], &fields)
+            .unwrap()
+            .ends_with("but it is missing"));
+    }
+
+    #[test]
+    fn resolve_requires_exactly_one_probe() {
+        let mut verify = spec(None);
+        verify.tcp = Some(VerifyTcp {
+            host: None,
+            port: 5432,
+            from: Default::default(),
+        });
+        let err = resolve("Example", &verify, &IndexMap::new(), false).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("exactly one of command, http or tcp"));
+
+        verify.command = None;
+        let resolved = resolve("Example", &verify, &IndexMap::new(), false).unwrap();
+        assert!(
+            matches!(resolved.probe, Probe::Tcp { ref host, port: 5432, .. } if host == "127.0.0.1")
+        );
+
+        verify.expect = Some(VerifyExpectation {
+            equals: Some("open".to_string()),
+            ..Default::default()
+        });
+        let err = resolve("Example", &verify, &IndexMap::new(), false).unwrap_err();
+        assert!(err.to_string().contains("does not apply to a tcp probe"));
+    }
+
+    #[test]
+    fn resolve_combines_matchers() {
         let verify = spec(Some(VerifyExpectation {
             equals: Some("healthy".to_string()),
             regex: Some("healthy".to_string()),
+            contains: Some("heal".to_string()),
+            ..Default::default()
         }));
+        let expect = resolve("Example", &verify, &IndexMap::new(), false)
+            .unwrap()
+            .expect;
+        assert_eq!(expect.stdout.len(), 3);
+
+        let verify = spec(Some(VerifyExpectation::default()));
         let err = resolve("Example", &verify, &IndexMap::new(), false).unwrap_err();
-        assert!(err.to_string().contains("exactly one"));
+        assert!(err.to_string().contains("at least one matcher"));
     }
 
     #[test]
     fn resolve_rejects_invalid_regex() {
         let verify = spec(Some(VerifyExpectation {
-            equals: None,
             regex: Some("[".to_string()),
+            ..Default::default()
         }));
         let err = resolve("Example", &verify, &IndexMap::new(), false).unwrap_err();
         assert!(err.to_string().contains("expect.regex is invalid"));
@@ -356,8 +918,8 @@ mod tests {
     #[test]
     fn resolve_hides_invalid_regex_details_with_no_log() {
         let verify = spec(Some(VerifyExpectation {
-            equals: None,
             regex: Some("VERIFY_SUPER_SECRET[".to_string()),
+            ..Default::default()
         }));
         let err = resolve("Example", &verify, &IndexMap::new(), true).unwrap_err();
         assert!(err.to_string().contains("details hidden by no_log"));
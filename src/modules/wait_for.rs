@@ -0,0 +1,286 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde_json::Value;
+use ssh2::Session;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::{Register, WaitForSpec, WaitForState};
+use crate::modules::verify::retry_wait;
+use crate::utils;
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 300;
+const DEFAULT_SLEEP_SECONDS: u64 = 1;
+pub(crate) const DEFAULT_HOST: &str = "127.0.0.1";
+
+#[derive(Debug, PartialEq)]
+enum Condition {
+    Port { host: String, port: u16, open: bool },
+    Path { path: String, present: bool },
+    Search { path: String, regex: String },
+}
+
+impl Condition {
+    fn describe(&self) -> String {
+        match self {
+            Condition::Port { host, port, open } => format!(
+                "port {} on {} to be {}",
+                port,
+                host,
+                if *open { "started" } else { "stopped" }
+            ),
+            Condition::Path { path, present } => format!(
+                "{} to be {}",
+                path,
+                if *present { "present" } else { "absent" }
+            ),
+            Condition::Search { path, regex } => format!("/{}/ in {}", regex, path),
+        }
+    }
+
+    // A script that prints `yes` when the condition holds and `no` otherwise.
+    // Anything else on stdout is an error, so a missing probe tool can't pass
+    // for a closed port forever.
+    fn check_command(&self) -> String {
+        match self {
+            Condition::Port { host, port, open } => format!(
+                "{}\nif [ \"$r\" = {} ]; then echo yes; else echo no; fi",
+                port_state_command(host, *port),
+                if *open { "open" } else { "closed" }
+            ),
+            Condition::Path { path, present } => format!(
+                "if [ {}-e {} ]; then echo yes; else echo no; fi",
+                if *present { "" } else { "! " },
+                utils::shell_escape(path)
+            ),
+            Condition::Search { path, regex } => format!(
+                "if grep -Eq -- {} {} 2>/dev/null; then echo yes; else echo no; fi",
+                utils::shell_escape(regex),
+                utils::shell_escape(path)
+            ),
+        }
+    }
+}
+
+// Sets `r` to `open` or `closed` for a TCP port, trying nc and then bash's
+// /dev/tcp. With neither available it prints why and exits.
+pub(crate) fn port_state_command(host: &str, port: u16) -> String {
+    format!(
+        r#"h={}; p={}
+if command -v nc >/dev/null 2>&1; then nc -z -w 2 "$h" "$p" >/dev/null 2>&1
+elif command -v bash >/dev/null 2>&1; then timeout 2 bash -c 'exec 3<>"/dev/tcp/$0/$1"' "$h" "$p" >/dev/null 2>&1
+else echo "needs nc or bash to check ports"; exit 0; fi
+rc=$?
+if [ "$rc" -eq 0 ]; then r=open; else r=closed; fi"#,
+        utils::shell_escape(host),
+        port
+    )
+}
+
+fn resolve(
+    task_name: &str,
+    spec: &WaitForSpec,
+    vars_map: &IndexMap<String, Value>,
+) -> Result<Condition, Box<dyn std::error::Error>> {
+    let rendered = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|v| utils::replace_placeholders(v, vars_map))
+    };
+    let invalid = |message: &str| -> Box<dyn std::error::Error> {
+        format!("Task '{}': wait_for {}", task_name, message).into()
+    };
+
+    if spec.sleep == Some(0) {
+        return Err(invalid("sleep must be at least 1 second"));
+    }
+
+    match (spec.port, rendered(&spec.path)) {
+        (Some(_), Some(_)) => Err(invalid("takes either port or path, not both")),
+        (None, None) => Err(invalid("requires port or path")),
+        (Some(port), None) => {
+            if spec.search_regex.is_some() {
+                return Err(invalid("search_regex requires path"));
+            }
+            let open = match spec.state {
+                None | Some(WaitForState::Started) => true,
+                Some(WaitForState::Stopped) => false,
+                Some(_) => return Err(invalid("with port uses state started or stopped")),
+            };
+            Ok(Condition::Port {
+                host: rendered(&spec.host).unwrap_or_else(|| DEFAULT_HOST.to_string()),
+                port,
+                open,
+            })
+        }
+        (None, Some(path)) => {
+            if spec.host.is_some() {
+                return Err(invalid("host requires port"));
+            }
+            let present = match spec.state {
+                None | Some(WaitForState::Present) => true,
+                Some(WaitForState::Absent) => false,
+                Some(_) => return Err(invalid("with path uses state present or absent")),
+            };
+            match rendered(&spec.search_regex) {
+                Some(_) if !present => {
+                    Err(invalid("search_regex cannot be combined with state absent"))
+                }
+                Some(regex) => Ok(Condition::Search { path, regex }),
+                None => Ok(Condition::Path { path, present }),
+            }
+        }
+    }
+}
+
+// `wait_for:` polls a condition on the target every `sleep` seconds until it
+// holds or `timeout` seconds have passed. `delay` is waited once before the
+// first check and is not counted against the timeout.
+#[allow(clippy::too_many_arguments)]
+pub fn process(
+    task_name: &str,
+    spec: &WaitForSpec,
+    is_localhost: bool,
+    session: Option<&Session>,
+    vars_map: &mut IndexMap<String, Value>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    register: Option<&String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let condition = resolve(task_name, spec, vars_map)?;
+    let timeout = spec.timeout.unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+    let sleep = spec.sleep.unwrap_or(DEFAULT_SLEEP_SECONDS);
+    println!(
+        "{}",
+        format!(
+            "> [wait_for] {} (timeout {}s)",
+            condition.describe(),
+            timeout
+        )
+        .magenta()
+    );
+
+    if let Some(delay) = spec.delay.filter(|delay| *delay > 0) {
+        thread::sleep(Duration::from_secs(delay));
+    }
+
+    let command = condition.check_command();
+    let started = Instant::now();
+    loop {
+        let (stdout, stderr, rc) = utils::run_shell_on_target(
+            &command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?;
+        match (rc, stdout.trim()) {
+            (0, "yes") => break,
+            (0, "no") => {}
+            _ => {
+                return Err(format!(
+                    "Task '{}': wait_for check failed: exit {}: {}",
+                    task_name,
+                    rc,
+                    format!("{} {}", stdout.trim(), stderr.trim()).trim()
+                )
+                .into());
+            }
+        }
+
+        let Some(wait) = retry_wait(sleep, Some(timeout), started.elapsed()) else {
+            return Err(format!(
+                "Task '{}': timed out after {} second{} waiting for {}",
+                task_name,
+                timeout,
+                if timeout == 1 { "" } else { "s" },
+                condition.describe()
+            )
+            .into());
+        };
+        thread::sleep(wait);
+    }
+
+    let elapsed = started.elapsed().as_secs();
+    println!(
+        "{}",
+        format!("Condition met after {}s", elapsed).bright_black()
+    );
+    if let Some(reg) = register {
+        let mut value = serde_json::to_value(Register {
+            stdout: String::new(),
+            stderr: String::new(),
+            rc: 0,
+            changed: false,
+            failed: false,
+        })?;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("elapsed".to_string(), Value::from(elapsed));
+        }
+        vars_map.insert(reg.clone(), value);
+        println!("{}", format!("Registering output to: {}", reg).yellow());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(yaml: &str) -> WaitForSpec {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn resolves_port_and_path_conditions() {
+        let vars = IndexMap::new();
+        assert_eq!(
+            resolve("T", &spec("port: 5432\n"), &vars).unwrap(),
+            Condition::Port {
+                host: "127.0.0.1".to_string(),
+                port: 5432,
+                open: true
+            }
+        );
+        assert_eq!(
+            resolve("T", &spec("path: /run/app.pid\nstate: absent\n"), &vars).unwrap(),
+            Condition::Path {
+                path: "/run/app.pid".to_string(),
+                present: false
+            }
+        );
+        assert_eq!(
+            resolve(
+                "T",
+                &spec("path: /var/log/app.log\nsearch_regex: ready\n"),
+                &vars
+            )
+            .unwrap(),
+            Condition::Search {
+                path: "/var/log/app.log".to_string(),
+                regex: "ready".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_keys() {
+        let vars = IndexMap::new();
+        let cases = [
+            ("port: 80\npath: /tmp/x\n", "either port or path"),
+            ("timeout: 5\n", "requires port or path"),
+            ("port: 80\nstate: present\n", "state started or stopped"),
+            ("path: /tmp/x\nstate: stopped\n", "state present or absent"),
+            ("path: /tmp/x\nhost: db\n", "host requires port"),
+            ("port: 80\nsearch_regex: x\n", "search_regex requires path"),
+            ("port: 80\nsleep: 0\n", "sleep must be at least 1 second"),
+        ];
+        for (yaml, expected) in cases {
+            let err = resolve("T", &spec(yaml), &vars).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", yaml, err);
+        }
+    }
+}
@@ -128,6 +128,94 @@ pub fn wrap_become_command(command: &str, method: &str, password: Option<&str>)
     }
 }
 
+// Printed on stderr by the `with_timeout` wrapper when its watchdog fired, so
+// a kill can be told apart from a command that exits 124 by itself.
+const TIMEOUT_MARKER: &str = "deploy-helper: timeout reached, process group killed";
+
+// The shell `with_timeout` runs the command with, so a timed command is
+// interpreted the same way as without `timeout:`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeoutShell {
+    // `sh`, as for `shell:` and anything run through `become`.
+    Sh,
+    // The user's shell, which runs a remote `command:` line.
+    User,
+    // The user's shell as an interactive login shell, for `login_shell:`.
+    Login,
+}
+
+impl TimeoutShell {
+    // The shell used when a task runs with these settings and no timeout.
+    pub fn for_task(login_shell: bool, become_enabled: bool, user_shell: bool) -> Self {
+        match (become_enabled, login_shell) {
+            (true, _) => TimeoutShell::Sh,
+            (false, true) => TimeoutShell::Login,
+            (false, false) if user_shell => TimeoutShell::User,
+            (false, false) => TimeoutShell::Sh,
+        }
+    }
+
+    fn invocation(self) -> &'static str {
+        match self {
+            TimeoutShell::Sh => "sh -c",
+            TimeoutShell::User => "\"$SHELL\" -c",
+            TimeoutShell::Login => "\"$SHELL\" -l -i -c",
+        }
+    }
+}
+
+// Runs `command` with `shell` in a session of its own under a watchdog. When
+// `seconds` pass, the watchdog sends TERM to the command's whole process
+// group, then KILL five seconds later, and the wrapper prints
+// `TIMEOUT_MARKER` and exits 124. The result is a single `sh -c` word, so it
+// behaves the same through a login shell or `become`.
+pub fn with_timeout(command: &str, seconds: u64, shell: TimeoutShell) -> String {
+    let watchdog = format!(
+        "sleep {}; rm -f \"$1\"; kill -s TERM -- \"-$2\" 2>/dev/null; sleep 5; kill -s KILL -- \"-$2\" 2>/dev/null",
+        seconds
+    );
+    let script = [
+        "marker=$(mktemp) || exit 1".to_string(),
+        "exec 3<&0".to_string(),
+        format!(
+            "setsid {} {} 0<&3 3<&- &",
+            shell.invocation(),
+            shell_escape(command)
+        ),
+        "job=$!".to_string(),
+        "exec 3<&-".to_string(),
+        format!(
+            "setsid sh -c {} deploy-helper-timeout \"$marker\" \"$job\" >/dev/null 2>&1 &",
+            shell_escape(&watchdog)
+        ),
+        "watchdog=$!".to_string(),
+        "wait \"$job\" 2>/dev/null".to_string(),
+        "rc=$?".to_string(),
+        format!(
+            "if [ -e \"$marker\" ]; then rm -f \"$marker\"; kill -s KILL -- \"-$watchdog\" 2>/dev/null; else rc=124; echo {} >&2; fi",
+            shell_escape(TIMEOUT_MARKER)
+        ),
+        "exit \"$rc\"".to_string(),
+    ]
+    .join("\n");
+    format!("sh -c {}", shell_escape(&script))
+}
+
+// Removes the `with_timeout` marker from the end of a command's output.
+// Returns whether it was there, i.e. whether the watchdog killed the command.
+pub fn take_timeout_marker(output: &mut String) -> bool {
+    let end = output.trim_end_matches(['\r', '\n']);
+    let Some(rest) = end.strip_suffix(TIMEOUT_MARKER) else {
+        return false;
+    };
+    if !rest.is_empty() && !rest.ends_with('\n') {
+        return false;
+    }
+    let kept = rest.trim_end_matches(['\r', '\n']).len();
+    output.truncate(kept);
+    true
+}
+
 pub(crate) fn template_environment() -> Environment<'static> {
     let mut env = Environment::new();
     env.set_undefined_behavior(UndefinedBehavior::Strict);
@@ -870,6 +958,42 @@ pub fn execute_local_command(
     Ok((stdout_str, stderr_str, exit_status))
 }
 
+// Creates an empty temp file on the target, owned by the login user, and
+// returns its path. Callers upload into it without become and remove it when
+// they are done.
+pub fn make_temp_file_on_target(
+    prefix: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let cmd = format!("mktemp \"${{TMPDIR:-/tmp}}/{}.XXXXXX\"", prefix);
+    let (stdout, stderr, rc) = run_shell_on_target(&cmd, is_localhost, session, false, "", None)?;
+    let path = stdout.trim();
+    if rc != 0 || path.is_empty() {
+        return Err(format!(
+            "Failed to create a temp file on the target: {}",
+            stderr.trim()
+        )
+        .into());
+    }
+    Ok(path.to_string())
+}
+
+// Removes a temp file from `make_temp_file_on_target`. A failure is only
+// reported, so it never hides the result of the task that used the file.
+pub fn remove_temp_file_on_target(path: &str, is_localhost: bool, session: Option<&Session>) {
+    let cmd = format!("rm -f {}", shell_escape(path));
+    let failure = match run_shell_on_target(&cmd, is_localhost, session, false, "", None) {
+        Ok((_, _, 0)) => return,
+        Ok((_, stderr, _)) => stderr.trim().to_string(),
+        Err(e) => e.to_string(),
+    };
+    println!(
+        "{}",
+        format!("Failed to remove {}: {}", path, failure).yellow()
+    );
+}
+
 // Returns true if `path` exists on the target (localhost or the remote session),
 // checked with `test -e`. Backs the `creates:`/`removes:` task guards.
 pub fn path_exists_on_target(
@@ -1124,6 +1248,157 @@ pub fn run_shell_on_target_with_context(
     }
 }
 
+// Reads `path` from the target through the same become plumbing as the file
+// writes. Returns None when the path does not exist. The bytes travel as `od`
+// hex so trailing newlines and binary content survive the command runners,
+// which trim output and may merge a PTY's stderr into stdout.
+pub fn read_from_target(
+    path: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let escaped = shell_escape(path);
+    let command = format!(
+        "if [ -e {p} ]; then od -An -v -tx1 {p}; else echo missing; fi",
+        p = escaped
+    );
+    let (stdout, stderr, code) = run_shell_on_target(
+        &command,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+    )?;
+    if code != 0 {
+        return Err(format!(
+            "Failed to read {}: exit {}: {}",
+            path,
+            code,
+            format!("{} {}", stdout.trim(), stderr.trim()).trim()
+        )
+        .into());
+    }
+    if stdout.trim() == "missing" {
+        return Ok(None);
+    }
+    parse_od_bytes(&stdout)
+        .map(Some)
+        .map_err(|e| format!("Failed to read {}: {}", path, e).into())
+}
+
+// Rewrites a text file on the target through the staged temp file + `mv` of
+// `write_to_target`, so a reader never sees it half-written. Without an
+// explicit `mode`, an existing file keeps its mode and a new one gets 0644.
+// An existing file also keeps its owner and group, and a symlink is written
+// through to the file it points at rather than being replaced.
+#[allow(clippy::too_many_arguments)]
+pub fn rewrite_file_on_target(
+    bytes: &[u8],
+    path: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+    mode: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let run = |command: &str| -> Result<String, Box<dyn std::error::Error>> {
+        let (stdout, stderr, code) = run_shell_on_target(
+            command,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?;
+        if code != 0 {
+            return Err(format!(
+                "Failed to write {}: exit {}: {}",
+                path,
+                code,
+                format!("{} {}", stdout.trim(), stderr.trim()).trim()
+            )
+            .into());
+        }
+        Ok(stdout)
+    };
+    // GNU `stat -c` first, then BSD `stat -f`, like the stat module.
+    let stat = |gnu: &str, bsd: &str| {
+        format!(
+            "stat -L -c '{}' -- \"$p\" 2>/dev/null || stat -L -f '{}' \"$p\"",
+            gnu, bsd
+        )
+    };
+    let output = run(&format!(
+        "p={}\nif [ ! -e \"$p\" ]; then echo missing; exit 0; fi\nif [ -L \"$p\" ]; then p=$(readlink -f -- \"$p\") || exit 1; fi\nprintf '%s %s\\n' \"$({})\" \"$p\"",
+        shell_escape(path),
+        stat("%a %u:%g", "%Lp %u:%g")
+    ))?;
+    let existing = parse_file_attributes(output.trim_end_matches(['\r', '\n']))
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    let (dest, owner, current_mode) = match existing {
+        Some((current_mode, owner, dest)) => (dest, Some(owner), current_mode),
+        None => (path, None, "0644"),
+    };
+    write_to_target(
+        bytes,
+        dest,
+        is_localhost,
+        session,
+        become_enabled,
+        become_method,
+        become_password,
+        Some(mode.unwrap_or(current_mode)),
+    )?;
+    if let Some(owner) = owner {
+        // The new file belongs to whoever wrote it; put the old owner back.
+        run(&format!(
+            "p={}; [ \"$({})\" = {} ] || chown {} \"$p\"",
+            shell_escape(dest),
+            stat("%u:%g", "%u:%g"),
+            owner,
+            owner
+        ))?;
+    }
+    Ok(())
+}
+
+// Parses `<mode> <uid>:<gid> <path>` from `rewrite_file_on_target`'s stat
+// command, or `missing`. The mode and owner are checked because they are
+// spliced into shell commands unquoted.
+fn parse_file_attributes(line: &str) -> Result<Option<(&str, &str, &str)>, String> {
+    if line == "missing" {
+        return Ok(None);
+    }
+    let mut fields = line.splitn(3, ' ');
+    let (Some(mode), Some(owner), Some(path)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(format!("unexpected stat output '{}'", line));
+    };
+    validate_mode(mode)?;
+    let numeric = |id: &str| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit());
+    match owner.split_once(':') {
+        Some((uid, gid)) if numeric(uid) && numeric(gid) && !path.is_empty() => {
+            Ok(Some((mode, owner, path)))
+        }
+        _ => Err(format!("unexpected stat output '{}'", line)),
+    }
+}
+
+fn parse_od_bytes(output: &str) -> Result<Vec<u8>, String> {
+    output
+        .split_whitespace()
+        .map(|byte| {
+            u8::from_str_radix(byte, 16).map_err(|_| format!("unexpected od output '{}'", byte))
+        })
+        .collect()
+}
+
 // Writes `bytes` to a privileged `dest` on localhost via doas+password. doas
 // needs a tty, so the bytes are staged to a user-owned temp file first and then
 // copied into place under a PTY-authenticated doas (mirrors the remote path).
@@ -1360,6 +1635,101 @@ pub fn write_to_target(
     }
 }
 
+// Copies a whole file from the target into memory, the counterpart of
+// `write_to_target`. Remote files come over SFTP; under become the file is
+// first copied to a temp file handed to the login user, since SFTP runs
+// without privileges. doas with a password reads through the PTY via
+// `read_from_target`, which is slower but needs no extra plumbing.
+pub fn read_file_from_target(
+    src: &str,
+    is_localhost: bool,
+    session: Option<&Session>,
+    become_enabled: bool,
+    become_method: &str,
+    become_password: Option<&str>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let doas_pw =
+        become_enabled && become_method == "doas" && become_password.is_some_and(|s| !s.is_empty());
+    if doas_pw {
+        return read_from_target(
+            src,
+            is_localhost,
+            session,
+            become_enabled,
+            become_method,
+            become_password,
+        )?
+        .ok_or_else(|| format!("Failed to read {}: no such file", src).into());
+    }
+
+    let cat = format!("cat -- {}", shell_escape(src));
+    if is_localhost {
+        let cmd = if become_enabled {
+            wrap_become_command(&cat, become_method, become_password)
+        } else {
+            cat
+        };
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&cmd)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Failed to spawn sh to read {}: {}", src, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to read {}: exit {}: {}",
+                src,
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        return Ok(output.stdout);
+    }
+
+    let session = session.ok_or("read_file_from_target: remote target requires session")?;
+    let sftp = session
+        .sftp()
+        .map_err(|e| format!("Failed to open SFTP session: {}", e))?;
+    if !become_enabled {
+        return Ok(sftp_read(&sftp, src)?);
+    }
+
+    // The privileged side creates the temp file itself: with
+    // fs.protected_regular, even root may not write into another user's
+    // file in /tmp.
+    let (user, stderr, code) = execute_ssh_command(session, "id -un", true, false, None, false)?;
+    if code != 0 {
+        return Err(format!("Failed to read {}: id -un failed: {}", src, stderr.trim()).into());
+    }
+    let stage = format!(
+        "umask 077 && tmp=$(mktemp /tmp/deploy-helper-fetch-XXXXXX) && {{ cat -- {src} > \"$tmp\" && chown {user} \"$tmp\" && echo \"$tmp\" || {{ rm -f \"$tmp\"; exit 1; }}; }}",
+        src = shell_escape(src),
+        user = shell_escape(user.trim())
+    );
+    let wrapped = wrap_become_command(&stage, become_method, become_password);
+    let (tmp_path, stderr, code) =
+        execute_ssh_command(session, &wrapped, true, false, None, false)?;
+    if code != 0 {
+        return Err(format!("Failed to read {}: exit {}: {}", src, code, stderr.trim()).into());
+    }
+    let tmp_path = tmp_path.trim().to_string();
+    let result = sftp_read(&sftp, &tmp_path);
+    let _ = sftp.unlink(Path::new(&tmp_path));
+    Ok(result?)
+}
+
+fn sftp_read(sftp: &ssh2::Sftp, path: &str) -> Result<Vec<u8>, String> {
+    let mut remote = sftp
+        .open(Path::new(path))
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut bytes = Vec::new();
+    remote
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok(bytes)
+}
+
 /// Recursively walk `base`, collecting remote directory paths and (local file, remote dest)
 /// pairs. The CONTENTS of `base` are placed under `dest_dir` (like `cp -r base/. dest/`).
 fn collect_dir_tree(
@@ -1520,6 +1890,42 @@ mod tests {
         assert_eq!(shell_escape("a && b | c"), "'a && b | c'");
     }
 
+    // with_timeout / take_timeout_marker
+
+    #[test]
+    fn test_with_timeout_marks_only_a_killed_command() {
+        let run = |command: &str, seconds: u64| {
+            run_shell_on_target(
+                &with_timeout(command, seconds, TimeoutShell::Sh),
+                true,
+                None,
+                false,
+                "sudo",
+                None,
+            )
+            .unwrap()
+        };
+
+        let (_, mut stderr, rc) = run("echo failing >&2; exit 124", 5);
+        assert_eq!(rc, 124);
+        assert!(!take_timeout_marker(&mut stderr));
+        assert_eq!(stderr.trim(), "failing");
+
+        let (_, mut stderr, rc) = run("echo started >&2; sleep 30", 1);
+        assert_eq!(rc, 124);
+        assert!(take_timeout_marker(&mut stderr));
+        assert_eq!(stderr.trim(), "started");
+    }
+
+    #[test]
+    fn test_take_timeout_marker_needs_a_line_of_its_own() {
+        let mut output = format!("x{}\n", TIMEOUT_MARKER);
+        assert!(!take_timeout_marker(&mut output));
+        let mut output = format!("{}\r\n", TIMEOUT_MARKER);
+        assert!(take_timeout_marker(&mut output));
+        assert_eq!(output, "");
+    }
+
     // render_env_values / env_export_lines
 
     fn env_map(pairs: &[(&str, &str)]) -> IndexMap<String, String> {
@@ -1697,6 +2103,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_file_attributes_checks_what_reaches_the_shell() {
+        assert_eq!(parse_file_attributes("missing").unwrap(), None);
+        assert_eq!(
+            parse_file_attributes("640 0:33 /etc/app/my app.conf").unwrap(),
+            Some(("640", "0:33", "/etc/app/my app.conf"))
+        );
+        for line in ["644 root:root /etc/x", "64x 0:0 /etc/x", "644 0:0", ""] {
+            assert!(parse_file_attributes(line).is_err(), "{}", line);
+        }
+    }
+
     // validate_mode
 
     #[test]
@@ -2148,4 +2566,24 @@ mod tests {
 
         fs::remove_dir_all(&base).unwrap();
     }
+
+    #[test]
+    fn test_read_from_target_keeps_exact_bytes() {
+        let base = scratch_dir("read");
+        fs::create_dir_all(&base).unwrap();
+        let file = base.join("config");
+        fs::write(&file, b"a b\n\n").unwrap();
+        let path = file.to_string_lossy().to_string();
+
+        let bytes = read_from_target(&path, true, None, false, "sudo", None).unwrap();
+        assert_eq!(bytes, Some(b"a b\n\n".to_vec()));
+        let missing = format!("{}.missing", path);
+        assert_eq!(
+            read_from_target(&missing, true, None, false, "sudo", None).unwrap(),
+            None
+        );
+        assert!(parse_od_bytes("61 zz").is_err());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
 }
@@ -0,0 +1,65 @@
+# A small HTTP stand-in for the get_url/uri tests: serves this directory's
+# files under /files/, a JSON health endpoint, an echo endpoint, and a
+# failing one. Exits on its own after the given number of seconds.
+import http.server
+import json
+import os
+import sys
+import threading
+
+ROOT = os.path.dirname(os.path.abspath(__file__))
+
+
+class Handler(http.server.BaseHTTPRequestHandler):
+    def log_message(self, *args):
+        pass
+
+    def reply(self, status, body, content_type="application/json"):
+        data = body.encode() if isinstance(body, str) else body
+        self.send_response(status)
+        self.send_header("Content-Type", content_type)
+        self.send_header("Content-Length", str(len(data)))
+        self.end_headers()
+        self.wfile.write(data)
+
+    def do_GET(self):
+        if self.path == "/health":
+            self.reply(200, json.dumps({"status": "ok", "version": "1.2"}))
+        elif self.path == "/unavailable":
+            self.reply(503, "maintenance", "text/plain")
+        elif self.path.startswith("/files/"):
+            path = os.path.join(ROOT, os.path.basename(self.path))
+            if os.path.isfile(path):
+                with open(path, "rb") as f:
+                    self.reply(200, f.read(), "application/octet-stream")
+            else:
+                self.reply(404, "not found", "text/plain")
+        elif self.path == "/private":
+            if self.headers.get("Authorization") == "Bearer s3cret":
+                self.reply(200, "private data", "text/plain")
+            else:
+                self.reply(401, "unauthorized", "text/plain")
+        else:
+            self.reply(404, "not found", "text/plain")
+
+    def do_POST(self):
+        length = int(self.headers.get("Content-Length", 0))
+        body = self.rfile.read(length).decode()
+        self.reply(
+            201,
+            json.dumps(
+                {
+                    "method": self.command,
+                    "content_type": self.headers.get("Content-Type"),
+                    "received": json.loads(body) if body.startswith("{") else body,
+                }
+            ),
+        )
+
+    do_PUT = do_POST
+
+
+port, lifetime = int(sys.argv[1]), float(sys.argv[2])
+server = http.server.ThreadingHTTPServer(("127.0.0.1", port), Handler)
+threading.Timer(lifetime, server.shutdown).start()
+server.serve_forever()
@@ -0,0 +1,2 @@
+echo "checking schema"
+exit 3
@@ -0,0 +1,6 @@
+#!/bin/bash
+set -eu
+echo "args: $*"
+echo "cwd: $(pwd)"
+echo "release: ${RELEASE}"
+echo "script: $(basename "$0")"
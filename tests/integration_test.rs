@@ -397,6 +397,70 @@ mod verify_tests {
         }
     }
 
+    #[test]
+    fn verify_combines_exit_status_stderr_and_json_matchers() {
+        setup();
+        for inventory in ["tests/servers/local.yml", "tests/servers/remote.yml"] {
+            run_test_check(
+                "test-ymls/verify/verify-matchers.yml",
+                true,
+                &[],
+                inventory,
+                |output| {
+                    assert!(
+                        output.contains("rc=1 stderr=db: connection refused"),
+                        "verify should pass a command that exits with the expected status:\n{}",
+                        output
+                    );
+                    assert!(
+                        output.contains(
+                            "expected exit status 3, got 0; \
+                             expected stdout to contain 'ready', got 'panic: out of memory'; \
+                             expected stdout not to contain 'panic', got 'panic: out of memory'"
+                        ),
+                        "verify should list every failed matcher:\n{}",
+                        output
+                    );
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn verify_probes_http_endpoints_and_tcp_ports() {
+        run_test_check(
+            "test-ymls/verify/verify-probes.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("> [verify] tcp 127.0.0.1:58233")
+                        && output.contains("GET http://127.0.0.1:58233/private (from control)"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("health status=200 version=1.2"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("nothing accepted a connection on 127.0.0.1:1"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("failed after 2 attempts")
+                        && output
+                            .contains(r#"expected body json $.version to be \"2.0\", got \"1.2\""#),
+                    "{}",
+                    output
+                );
+            },
+        );
+    }
+
     #[test]
     fn verify_no_log_hides_command_and_failure_detail() {
         setup();
@@ -1813,23 +1877,22 @@ mod file_ops {
             },
         );
     }
-}
-
-mod systemd {
-    use super::*;
 
+    // Runs on localhost only, so no Docker/SSH needed.
     #[test]
-    fn systemd_manages_and_verifies_units() {
-        setup();
+    fn lineinfile_replaces_inserts_and_removes_lines() {
         run_test_check(
-            "test-ymls/systemd/systemd.yml",
+            "test-ymls/file-ops/lineinfile.yml",
             false,
             &[],
-            "tests/servers/remote-ssh.yml",
+            "tests/servers/local.yml",
             |output| {
+                assert!(output.contains("changed=True again=False"), "{}", output);
                 assert!(
-                    output.contains("SYSTEMD_OK"),
-                    "systemd should perform every requested operation:\n{}",
+                    output.contains(
+                        "line: # sshd\nline: PermitRootLogin no\nline: Port 22\nline: ListenAddress 0.0.0.0\nkept mode=604\nlink kept\nmode=640\ncreated=yes"
+                    ),
+                    "{}",
                     output
                 );
             },
@@ -1837,19 +1900,23 @@ mod systemd {
     }
 
     #[test]
-    fn systemd_reports_unexpected_unit_result() {
-        setup();
+    fn blockinfile_replaces_and_removes_marked_blocks() {
         run_test_check(
-            "test-ymls/systemd/systemd-result-error.yml",
-            true,
+            "test-ymls/file-ops/blockinfile.yml",
+            false,
             &[],
-            "tests/servers/remote-ssh.yml",
+            "tests/servers/local.yml",
             |output| {
+                assert!(
+                    output.contains("replaced=True removed_again=False"),
+                    "{}",
+                    output
+                );
                 assert!(
                     output.contains(
-                        "systemd unit failed.service result: expected success, got failed"
+                        "line: 127.0.0.1 localhost\nline: # BEGIN DEPLOY-HELPER MANAGED BLOCK\nline: 10.0.0.7 db\nline: # END DEPLOY-HELPER MANAGED BLOCK\nline: ::1 localhost"
                     ),
-                    "systemd should report the expected and actual result:\n{}",
+                    "{}",
                     output
                 );
             },
@@ -1857,17 +1924,19 @@ mod systemd {
     }
 
     #[test]
-    fn systemd_reports_inactive_unit() {
-        setup();
+    fn ini_file_sets_and_removes_options_in_sections() {
         run_test_check(
-            "test-ymls/systemd/systemd-active-error.yml",
-            true,
+            "test-ymls/file-ops/ini-file.yml",
+            false,
             &[],
-            "tests/servers/remote-ssh.yml",
+            "tests/servers/local.yml",
             |output| {
+                assert!(output.contains("changed=True again=False"), "{}", output);
                 assert!(
-                    output.contains("systemd unit inactive.unit is not active"),
-                    "systemd should identify the inactive unit:\n{}",
+                    output.contains(
+                        "line: ; managed by hand\nline: [server]\nline: # port to bind\nline: port = 9090\nline: \nline: [client]\nline: \nline: [log]\nline: level = info\n"
+                    ),
+                    "{}",
                     output
                 );
             },
@@ -1875,17 +1944,39 @@ mod systemd {
     }
 
     #[test]
-    fn systemd_reports_disabled_unit() {
-        setup();
+    fn json_and_yaml_patch_edit_documents_in_place() {
         run_test_check(
-            "test-ymls/systemd/systemd-enabled-error.yml",
+            "test-ymls/file-ops/structured-patch.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                for line in [
+                    "changed=True again=False diffs=4",
+                    "first=/server/port 80->8080",
+                    "  services.web.image: \"app:1.0\" -> \"app:1.4.2\"",
+                    "json: {\njson:     \"name\": \"app\",\njson:     \"server\": {\njson:         \"port\": 8080\njson:     },\njson:     \"hosts\": [\njson:         \"a\",\njson:         \"b\"\njson:     ],\njson:     \"limits\": {\njson:         \"memory\": \"512M\"\njson:     }\njson: }\n",
+                    "yaml: services:\nyaml:   web:\nyaml:     image: app:1.4.2\nyaml:     environment: {}\nyaml:     ports:\nyaml:     - 8080:80\nyaml:   worker:\n",
+                    "yaml mode=604\nyaml renamed into place\nnew: {",
+                    "new: {\nnew:   \"enabled\": true\nnew: }\nmode=600",
+                ] {
+                    assert!(output.contains(line), "missing {}:\n{}", line, output);
+                }
+            },
+        );
+    }
+
+    #[test]
+    fn lineinfile_missing_file_requires_create() {
+        run_test_check(
+            "test-ymls/file-ops/lineinfile-missing-file-error.yml",
             true,
             &[],
-            "tests/servers/remote-ssh.yml",
+            "tests/servers/local.yml",
             |output| {
                 assert!(
-                    output.contains("systemd unit disabled.unit is not enabled"),
-                    "systemd should identify the disabled unit:\n{}",
+                    output.contains("does not exist (set create: true to create it)"),
+                    "{}",
                     output
                 );
             },
@@ -1893,19 +1984,21 @@ mod systemd {
     }
 
     #[test]
-    fn systemd_rejects_missing_unit_when_disabling() {
-        setup();
+    fn fetch_lays_out_files_per_host_and_in_on_failure() {
         run_test_check(
-            "test-ymls/systemd/systemd-missing-disable-error.yml",
+            "test-ymls/file-ops/fetch.yml",
             true,
             &[],
-            "tests/servers/remote-ssh.yml",
+            "tests/servers/local.yml",
             |output| {
                 assert!(
-                    output.contains(
-                        "systemd failed to determine enabled state for unit missing.unit"
-                    ),
-                    "systemd should reject an enablement inspection error:\n{}",
+                    output.contains("dest=/tmp/deploy-helper-test-fetch/by-host/test/tmp/deploy-helper-test-fetch-src/app.log changed=True again=False"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("./by-host/test/tmp/deploy-helper-test-fetch-src/app.log\n./diagnostics/app-failure.log\n./flat/app.log\nidentical"),
+                    "{}",
                     output
                 );
             },
@@ -1913,116 +2006,428 @@ mod systemd {
     }
 
     #[test]
-    fn systemd_rejects_static_unit_as_enabled() {
-        setup();
+    fn fetch_missing_src_fails() {
         run_test_check(
-            "test-ymls/systemd/systemd-static-enabled-error.yml",
+            "test-ymls/file-ops/fetch-missing-src-error.yml",
             true,
             &[],
-            "tests/servers/remote-ssh.yml",
+            "tests/servers/local.yml",
             |output| {
                 assert!(
-                    output.contains("systemd unit static.unit is not enabled (state: static)"),
-                    "systemd should not treat a static unit as enabled:\n{}",
+                    output.contains("fetch source not found on target"),
+                    "{}",
                     output
                 );
             },
         );
     }
-}
-
-mod tags {
-    use super::*;
 
     #[test]
-    fn tags_filter_runs_only_matching() {
-        setup();
-        run_test_with_flags_both_inventories(
-            "test-ymls/tags/tags-filter.yml",
+    fn stat_registers_file_metadata() {
+        run_test_check(
+            "test-ymls/file-ops/stat.yml",
             false,
             &[],
-            &["--tags", "build"],
-            None,
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("schema type=file size=3 mode=0640 mtime=1700000000 sha256=ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\n"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains(
+                        "current type=link target=/tmp/deploy-helper-test-stat/releases/42\n"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("missing exists=False"), "{}", output);
+                assert!(
+                    output.contains("schema is older than a day\n"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("Skipping task: Only when the schema changed"),
+                    "{}",
+                    output
+                );
+            },
         );
     }
 
+    // SFTP runs as the login user, so a root-only file is staged by sudo
+    // before the transfer. Runs against the remote container.
     #[test]
-    fn skip_tags_excludes_matches() {
+    fn fetch_with_become() {
         setup();
-        run_test_with_flags_both_inventories(
-            "test-ymls/tags/skip-tags.yml",
+        run_test_check(
+            "test-ymls/file-ops/fetch-become.yml",
             false,
-            &[],
-            &["--skip-tags", "drop"],
-            None,
+            &["become_password="],
+            "tests/servers/become-nopass.yml",
+            |output| {
+                assert!(
+                    output.contains("fetched_to=/tmp/deploy-helper-test-fetch-become/cert.pem"),
+                    "{}",
+                    output
+                );
+                let fetched =
+                    std::fs::read_to_string("/tmp/deploy-helper-test-fetch-become/cert.pem")
+                        .expect("fetched file should exist locally");
+                assert_eq!(fetched, "secret cert\n");
+            },
         );
     }
 
+    // Reads and writes go through sudo, so a root-owned 0600 file can be
+    // edited and keeps its owner and mode.
     #[test]
-    fn skip_tags_wins_over_tags_flag() {
+    fn lineinfile_and_blockinfile_with_become() {
         setup();
-        run_test_with_flags_both_inventories(
-            "test-ymls/tags/tags-and-skip-tags.yml",
+        run_test_check(
+            "test-ymls/file-ops/lineinfile-become.yml",
             false,
-            &[],
-            &["--tags", "web", "--skip-tags", "tls"],
-            None,
+            &["become_password="],
+            "tests/servers/become-nopass.yml",
+            |output| {
+                assert!(output.contains("root 600"), "{}", output);
+                assert!(output.contains("a=2"), "{}", output);
+                assert!(output.contains("b=3"), "{}", output);
+            },
         );
     }
+}
+
+mod systemd {
+    use super::*;
 
     #[test]
-    fn always_tag_bypasses_tags_filter() {
+    fn systemd_manages_and_verifies_units() {
         setup();
-        run_test_with_flags_both_inventories(
-            "test-ymls/tags/always-tag.yml",
+        run_test_check(
+            "test-ymls/systemd/systemd.yml",
             false,
             &[],
-            &["--tags", "tls"],
-            None,
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("SYSTEMD_OK"),
+                    "systemd should perform every requested operation:\n{}",
+                    output
+                );
+            },
         );
     }
 
     #[test]
-    fn never_tag_hidden_by_default() {
+    fn systemd_installs_unit_files_and_reloads_on_change() {
         setup();
-        run_test_with_flags(
-            "test-ymls/tags/never-tag.yml",
+        run_test_check(
+            "test-ymls/systemd/systemd-unit-files.yml",
             false,
             &[],
-            "tests/servers/local.yml",
-            &[],
-            None,
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("SYSTEMD_UNIT_FILES_OK"),
+                    "systemd should install unit files and reload only on change:\n{}",
+                    output
+                );
+                assert!(
+                    output.contains("> [systemd] 3 units (3 unit files changed, daemon reload)"),
+                    "systemd should report the changed unit files:\n{}",
+                    output
+                );
+            },
         );
     }
 
     #[test]
-    fn never_tag_opt_in_via_other_tag() {
+    fn systemd_manages_user_units() {
         setup();
-        run_test_with_flags(
-            "test-ymls/tags/never-tag-optin.yml",
+        run_test_check(
+            "test-ymls/systemd/systemd-user.yml",
             false,
             &[],
-            "tests/servers/local.yml",
-            &["--tags", "nuke"],
-            None,
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("SYSTEMD_USER_OK"),
+                    "systemd should use the user manager and unit directory:\n{}",
+                    output
+                );
+            },
         );
     }
 
     #[test]
-    fn tags_inheritance_flows_from_include() {
+    fn systemd_includes_journal_in_failures() {
         setup();
-        run_test_with_flags_both_inventories(
-            "test-ymls/tags/tags-inheritance.yml",
-            false,
+        run_test_check(
+            "test-ymls/systemd/systemd-journal-error.yml",
+            true,
             &[],
-            &["--tags", "nginx"],
-            None,
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("last 5 journal lines for inactive.unit:"),
+                    "systemd should introduce the journal excerpt:\n{}",
+                    output
+                );
+                assert!(
+                    output.contains("inactive.unit: journal entry one"),
+                    "systemd should include the unit's journal lines:\n{}",
+                    output
+                );
+            },
         );
     }
-}
 
-mod execution {
-    use super::*;
+    #[test]
+    fn systemd_waits_for_units_and_runs_timers() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-timers.yml",
+            false,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("SYSTEMD_TIMERS_OK"),
+                    "systemd should poll until active and trigger the timer's service:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn systemd_stops_waiting_for_failed_unit() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-wait-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "systemd unit crashing.service failed while waiting for it to become active"
+                    ),
+                    "systemd should fail as soon as the unit fails:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn systemd_reports_unscheduled_timer() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-schedule-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("systemd timer unscheduled.timer has no next elapse scheduled"),
+                    "systemd should report the timer without a schedule:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn systemd_reports_unexpected_unit_result() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-result-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "systemd unit failed.service result: expected success, got failed"
+                    ),
+                    "systemd should report the expected and actual result:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn systemd_reports_inactive_unit() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-active-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("systemd unit inactive.unit is not active"),
+                    "systemd should identify the inactive unit:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn systemd_reports_disabled_unit() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-enabled-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("systemd unit disabled.unit is not enabled"),
+                    "systemd should identify the disabled unit:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn systemd_rejects_missing_unit_when_disabling() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-missing-disable-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "systemd failed to determine enabled state for unit missing.unit"
+                    ),
+                    "systemd should reject an enablement inspection error:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn systemd_rejects_static_unit_as_enabled() {
+        setup();
+        run_test_check(
+            "test-ymls/systemd/systemd-static-enabled-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("systemd unit static.unit is not enabled (state: static)"),
+                    "systemd should not treat a static unit as enabled:\n{}",
+                    output
+                );
+            },
+        );
+    }
+}
+
+mod tags {
+    use super::*;
+
+    #[test]
+    fn tags_filter_runs_only_matching() {
+        setup();
+        run_test_with_flags_both_inventories(
+            "test-ymls/tags/tags-filter.yml",
+            false,
+            &[],
+            &["--tags", "build"],
+            None,
+        );
+    }
+
+    #[test]
+    fn skip_tags_excludes_matches() {
+        setup();
+        run_test_with_flags_both_inventories(
+            "test-ymls/tags/skip-tags.yml",
+            false,
+            &[],
+            &["--skip-tags", "drop"],
+            None,
+        );
+    }
+
+    #[test]
+    fn skip_tags_wins_over_tags_flag() {
+        setup();
+        run_test_with_flags_both_inventories(
+            "test-ymls/tags/tags-and-skip-tags.yml",
+            false,
+            &[],
+            &["--tags", "web", "--skip-tags", "tls"],
+            None,
+        );
+    }
+
+    #[test]
+    fn always_tag_bypasses_tags_filter() {
+        setup();
+        run_test_with_flags_both_inventories(
+            "test-ymls/tags/always-tag.yml",
+            false,
+            &[],
+            &["--tags", "tls"],
+            None,
+        );
+    }
+
+    #[test]
+    fn never_tag_hidden_by_default() {
+        setup();
+        run_test_with_flags(
+            "test-ymls/tags/never-tag.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            &[],
+            None,
+        );
+    }
+
+    #[test]
+    fn never_tag_opt_in_via_other_tag() {
+        setup();
+        run_test_with_flags(
+            "test-ymls/tags/never-tag-optin.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            &["--tags", "nuke"],
+            None,
+        );
+    }
+
+    #[test]
+    fn tags_inheritance_flows_from_include() {
+        setup();
+        run_test_with_flags_both_inventories(
+            "test-ymls/tags/tags-inheritance.yml",
+            false,
+            &[],
+            &["--tags", "nginx"],
+            None,
+        );
+    }
+}
+
+mod execution {
+    use super::*;
 
     #[test]
     fn start_at_task_skips_before_match() {
@@ -2169,6 +2574,861 @@ mod execution {
             },
         );
     }
+    #[test]
+    fn resume_continues_after_last_completed_task() {
+        let yml_file = "test-ymls/execution/resume.yml";
+        let state_file = format!("{}.run-state.json", yml_file);
+        let _ = fs::remove_file(&state_file);
+
+        run_test_check(
+            yml_file,
+            true,
+            &["flaky=broken"],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(output.contains("build-42"), "{}", output);
+            },
+        );
+        assert!(
+            fs::metadata(&state_file).is_ok(),
+            "failed run should leave {}",
+            state_file
+        );
+
+        run_test_check_with_flags(
+            yml_file,
+            false,
+            &["flaky=recovered"],
+            "tests/servers/local.yml",
+            &["--resume"],
+            |output| {
+                assert!(
+                    output.contains("Resuming on host test after task: Name release"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("Skipping task: Build (completed before resume)"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("Skipping task: Name release (completed before resume)"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("release-build-42 from build-42"),
+                    "restored vars should reach later tasks:\n{}",
+                    output
+                );
+            },
+        );
+        assert!(
+            fs::metadata(&state_file).is_err(),
+            "successful run should remove {}",
+            state_file
+        );
+    }
+
+    #[test]
+    fn resume_without_run_state_fails() {
+        run_test_check_with_flags(
+            "test-ymls/execution/resume-no-state.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            &["--resume"],
+            |output| {
+                assert!(output.contains("--resume: no run state at"), "{}", output);
+            },
+        );
+    }
+}
+
+mod modifiers {
+    use super::*;
+
+    #[test]
+    fn ignore_errors_keeps_going_with_registered_failure() {
+        run_test_check(
+            "test-ymls/modifiers/ignore-errors.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("Ignoring error: Command execution failed with exit status: 3"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("Ignoring error: Command execution failed with exit status: 6"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("rc=3 stdout=partial failed=True"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("after_ignored_ran"), "{}", output);
+                assert!(
+                    output.contains(
+                        "Ignoring error: Command execution failed with exit status: 5.\n"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains(
+                        "Ignoring error: Command timed out after 1 second and its process group was killed.\n"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("Stopping further tasks"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn failed_when_overrides_exit_status() {
+        run_test_check(
+            "test-ymls/modifiers/failed-when.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(output.contains("needle_missing failed=False"), "{}", output);
+                assert!(
+                    output.contains("Task 'Fail on output': failed_when condition matched"),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("after_failed_when_ran"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn changed_when_sets_registered_changed() {
+        run_test_check(
+            "test-ymls/modifiers/changed-when.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(output.contains("noop=False update=True"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn failed_when_requires_register() {
+        run_test_check(
+            "test-ymls/modifiers/failed-when-without-register-error.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("Task 'Missing register': failed_when: requires register:"),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("never_ran"), "{}", output);
+            },
+        );
+    }
+}
+
+mod retries {
+    use super::*;
+
+    #[test]
+    fn retries_rerun_failed_attempts_and_until_waits_for_result() {
+        run_test_check(
+            "test-ymls/retries/retries.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "Attempt 2/4 failed: Command execution failed with exit status: 1"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("Retrying in 1 second\n"), "{}", output);
+                assert!(output.contains("Attempt 3/4"), "{}", output);
+                assert!(!output.contains("Attempt 4/4"), "{}", output);
+                assert!(
+                    output.contains("Attempt 3/6 failed: until condition not met"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("counter=4 attempts=4"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn until_reports_last_error_after_retries_run_out() {
+        run_test_check(
+            "test-ymls/retries/until-exhausted.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "Task 'Never ready': failed after 2 attempts: until condition not met"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("after_exhausted_ran"), "{}", output);
+            },
+        );
+    }
+}
+
+mod async_jobs {
+    use super::*;
+
+    #[test]
+    fn timeout_kills_the_process_group() {
+        run_test_check(
+            "test-ymls/async/timeout.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("Ignoring error: Command timed out after 1 second and its process group was killed"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("child gone"), "{}", output);
+                assert!(output.contains("finished in time"), "{}", output);
+                assert!(output.contains("same shell under timeout"), "{}", output);
+                assert!(output.contains("bash construct ran"), "{}", output);
+                assert!(
+                    output
+                        .contains("Ignoring error: Command execution failed with exit status: 124"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("Error: Command timed out after 1 second"),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("should not run"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn async_jobs_are_polled_or_waited_on_with_async_status() {
+        run_test_check(
+            "test-ymls/async/async.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("polled=restored finished=True"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("started finished=False"), "{}", output);
+                assert!(output.contains("waited=migrated rc=0"), "{}", output);
+                assert!(
+                    output
+                        .lines()
+                        .any(|line| line.contains("Ignoring error: Async job")
+                            && line.contains(
+                                "timed out after 1 second and its process group was killed"
+                            )),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("timed out after 1 second and its process group was killed"),
+                    "{}",
+                    output
+                );
+            },
+        );
+    }
+}
+
+mod script {
+    use super::*;
+
+    #[test]
+    fn script_uploads_runs_and_cleans_up() {
+        run_test_check(
+            "test-ymls/script/script.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("> [script] report.sh --env 'demo prod' (via /bin/bash)"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("args: --env demo prod\ncwd: /tmp\nrelease: 42\nscript: deploy-helper-script."), "{}", output);
+                assert!(output.contains("python demo one two"), "{}", output);
+                assert!(
+                    output.contains("grep -c deploy-helper-script || true\n0\n"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("rc=3 stdout=checking schema"), "{}", output);
+            },
+        );
+    }
+}
+
+mod user {
+    use super::*;
+
+    #[test]
+    fn user_and_group_are_created_updated_and_removed() {
+        setup();
+        run_test_check(
+            "test-ymls/user/user.yml",
+            false,
+            &[],
+            "tests/servers/remote.yml",
+            |output| {
+                for line in [
+                    "> [group] dh-deploy (created)",
+                    "> [group] dh-deploy (unchanged)",
+                    "> [user] dh-svc (created, authorized_keys updated)",
+                    "> [user] dh-svc (unchanged)",
+                    "> [user] dh-svc (updated shell)",
+                    "> [user] dh-svc (removed)",
+                ] {
+                    assert!(output.contains(line), "missing {}:\n{}", line, output);
+                }
+                assert!(
+                    output.contains("group=True,False user=True,False"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("passwd=4712:/bin/false groups=dh-svc dh-deploy"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("keys=600 deploy@ci"), "{}", output);
+                assert!(output.contains("accounts removed"), "{}", output);
+            },
+        );
+    }
+}
+
+mod cron {
+    use super::*;
+
+    #[test]
+    fn cron_entries_are_added_updated_and_removed_by_name() {
+        setup();
+        run_test_check(
+            "test-ymls/cron/cron.yml",
+            false,
+            &[],
+            "tests/servers/remote.yml",
+            |output| {
+                for line in [
+                    "> [cron] rotate logs in crontab (added)",
+                    "> [cron] rotate logs in crontab (unchanged)",
+                    "> [cron] rotate logs in crontab (updated)",
+                    "> [cron] nightly sync in /etc/cron.d/deploy-helper-test (added)",
+                    "added=True again=False",
+                    "crontab: MAILTO=ops@example.com\ncrontab: */5 * * * * /usr/local/bin/ping\n\
+                     crontab: # deploy-helper: rotate logs\ncrontab: APP_ENV=production\n\
+                     crontab: 0 4 * * * /srv/app/bin/rotate\ncrontab: # deploy-helper end: rotate logs\n",
+                    "cron.d: # deploy-helper: nightly sync\ncron.d: @daily nobody /srv/app/bin/sync\n\
+                     cron.d: # deploy-helper end: nightly sync\n",
+                    "cron.d mode: 644",
+                    "left: MAILTO=ops@example.com\nleft: */5 * * * * /usr/local/bin/ping\n",
+                    "cron file removed",
+                ] {
+                    assert!(output.contains(line), "missing {}:\n{}", line, output);
+                }
+                assert_eq!(output.matches("crontab: 0 ").count(), 1, "{}", output);
+            },
+        );
+    }
+}
+
+mod sysctl {
+    use super::*;
+
+    #[test]
+    fn sysctl_persists_and_applies_parameters() {
+        setup();
+        run_test_check(
+            "test-ymls/sysctl/sysctl.yml",
+            false,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                for line in [
+                    "changed=True again=False rmem_again=False",
+                    "line: # tuned for the test\nline: vm.swappiness = 10\nline: net.ipv4.ip_forward = 1\nline: net.ipv4.tcp_rmem = 4096 87380 6291456\n",
+                    "mode=644",
+                    "writes=2",
+                ] {
+                    assert!(output.contains(line), "missing {}:\n{}", line, output);
+                }
+            },
+        );
+    }
+}
+
+mod compose {
+    use super::*;
+
+    #[test]
+    fn compose_manages_projects_and_registers_containers() {
+        setup();
+        run_test_check(
+            "test-ymls/compose/compose.yml",
+            false,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("COMPOSE_OK"),
+                    "compose should run every operation and register containers:\n{}",
+                    output
+                );
+                assert!(
+                    output.contains("starting-web-1 (running, healthy)"),
+                    "compose should wait until the container is healthy:\n{}",
+                    output
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn compose_rejects_unhealthy_containers() {
+        setup();
+        run_test_check(
+            "test-ymls/compose/compose-unhealthy-error.yml",
+            true,
+            &[],
+            "tests/servers/remote-ssh.yml",
+            |output| {
+                assert!(
+                    output.contains("compose unhealthy containers: unhealthy-web-1"),
+                    "compose should name the unhealthy container:\n{}",
+                    output
+                );
+            },
+        );
+    }
+}
+
+mod http {
+    use super::*;
+
+    #[test]
+    fn get_url_and_uri_against_a_local_server() {
+        run_test_check(
+            "test-ymls/http/http.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("first=True second=False mode=755 private=private data"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("get_url checksum mismatch: expected sha256:0000"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("no partial download"), "{}", output);
+                assert!(
+                    output.contains("private.txt (downloaded, URL hidden by no_log)"),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("/private ->"), "{}", output);
+                assert!(
+                    output.contains("health=ok status=201 type=application/json release=1.2"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("returned status 503, expected 200: maintenance"),
+                    "{}",
+                    output
+                );
+            },
+        );
+    }
+}
+
+mod archive {
+    use super::*;
+
+    #[test]
+    fn unarchive_and_archive_round_trip() {
+        run_test_check(
+            "test-ymls/archive/archive.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("releases/1.2 (3 top-level entries)"),
+                    "{}",
+                    output
+                );
+                for entry in ["bin", "logs", "VERSION"] {
+                    assert!(
+                        output.lines().any(|line| line.starts_with("entries=")
+                            && line["entries=".len()..].split(',').any(|e| e == entry)),
+                        "{}",
+                        output
+                    );
+                }
+                assert!(output.contains("VERSION=1.2 mode=750"), "{}", output);
+                assert!(
+                    output.contains("Skipping task: Skip extraction when already there"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("restored=1.2 files=VERSION bin  backup_mode=600"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("zip entries=app-1.2"), "{}", output);
+                assert!(
+                    output.contains(
+                        "archive entry '../app-1.2/VERSION' would be extracted outside dest"
+                    ),
+                    "{}",
+                    output
+                );
+            },
+        );
+    }
+}
+
+mod git {
+    use super::*;
+
+    #[test]
+    fn git_clones_updates_and_refuses_to_lose_work() {
+        run_test_check(
+            "test-ymls/git/git.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("clone changed=True before=None"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("app (unchanged at "), "{}", output);
+                assert!(
+                    output.contains("code moved to two, again changed=False"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("has local modifications; set force: true to discard them"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("app (reset local changes at "),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("VERSION=one"), "{}", output);
+                assert!(
+                    output.contains("git dest /tmp/deploy-helper-test-git/work/VERSION exists and is not a git checkout"),
+                    "{}",
+                    output
+                );
+            },
+        );
+    }
+}
+
+mod assert {
+    use super::*;
+
+    #[test]
+    fn assert_passes_and_registers_ignored_failures() {
+        run_test_check(
+            "test-ymls/assert/assert.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("> [assert] 2 conditions passed\nDeploying to staging"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("prod_check failed=True msg=not production: staging"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("Skipping task: Stop on the wrong branch"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("preflight passed"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn fail_stops_the_run_with_its_message() {
+        run_test_check(
+            "test-ymls/assert/assert.yml",
+            true,
+            &["branch=feature"],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "Task 'Stop on the wrong branch': refusing to deploy from feature"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("preflight passed"), "{}", output);
+            },
+        );
+    }
+}
+
+mod pause {
+    use super::*;
+
+    #[test]
+    fn pause_sleeps_and_uses_the_default_without_a_terminal() {
+        run_test_check(
+            "test-ymls/pause/pause.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(output.contains("> [pause] 1 second"), "{}", output);
+                assert!(
+                    output.contains("(no terminal, using the default)"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("migrating staging"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn pause_prompt_fails_without_a_terminal() {
+        run_test_check(
+            "test-ymls/pause/pause-no-terminal-error.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "pause needs a terminal to answer 'Migrate the production database?'"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("migrated"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn hidden_pause_answer_is_not_written_to_run_state() {
+        let yml_file = "test-ymls/pause/pause-hidden-run-state.yml";
+        let state_file = format!("{}.run-state.json", yml_file);
+        let _ = fs::remove_file(&state_file);
+
+        run_test_check(yml_file, true, &[], "tests/servers/local.yml", |output| {
+            assert!(!output.contains("hidden-pause-answer"), "{}", output);
+        });
+        let state = fs::read_to_string(&state_file).expect("failed run should leave a run state");
+        let _ = fs::remove_file(&state_file);
+        assert!(!state.contains("hidden-pause-answer"), "{}", state);
+        assert!(state.contains("\"vault\""), "{}", state);
+    }
+}
+
+mod wait_for {
+    use super::*;
+
+    #[test]
+    fn wait_for_ports_paths_and_log_lines() {
+        run_test_check(
+            "test-ymls/wait-for/wait-for.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(output.contains("port ready, waited="), "{}", output);
+                assert!(
+                    output.contains("> [wait_for] port 58231 on 127.0.0.1 to be stopped (timeout 10s)\nCondition met after"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("timed out after 2 seconds waiting for /tmp/deploy-helper-test-wait-for-never to be present"),
+                    "{}",
+                    output
+                );
+            },
+        );
+    }
+}
+
+mod block {
+    use super::*;
+
+    #[test]
+    fn rescue_recovers_and_children_inherit_block_settings() {
+        run_test_check(
+            "test-ymls/block/rescue.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(output.contains("target=r42 pwd=/tmp"), "{}", output);
+                assert!(!output.contains("block_after_failure"), "{}", output);
+                assert!(
+                    output.contains("Running rescue tasks for: Migrate database"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("rolled_back r42"), "{}", output);
+                assert!(output.contains("lock_released"), "{}", output);
+                assert!(
+                    output.contains("Skipping task: Skipped block"),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("skipped_block_ran"), "{}", output);
+                assert!(output.contains("after_block_ran"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn rescue_failure_reports_both_errors_and_runs_always() {
+        run_test_check(
+            "test-ymls/block/rescue-failure.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains(
+                        "block tasks failed: Command execution failed with exit status: 3"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains(
+                        "rescue tasks failed: Command execution failed with exit status: 4"
+                    ),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("lock_released"), "{}", output);
+                assert!(!output.contains("after_block_ran"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn failure_without_rescue_runs_always_and_stops() {
+        run_test_check(
+            "test-ymls/block/no-rescue.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(output.contains("lock_released"), "{}", output);
+                assert!(output.contains("exit status: 3"), "{}", output);
+                assert!(!output.contains("after_block_ran"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn loop_on_block_is_rejected() {
+        run_test_check(
+            "test-ymls/block/loop-error.yml",
+            true,
+            &[],
+            "tests/servers/local.yml",
+            |output| {
+                assert!(
+                    output.contains("Task 'Looped block': loop: cannot be used on a block"),
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("child_ran"), "{}", output);
+            },
+        );
+    }
+
+    #[test]
+    fn list_tasks_nests_block_sections() {
+        run_test_check_with_flags(
+            "test-ymls/block/list-tasks.yml",
+            false,
+            &[],
+            "tests/servers/local.yml",
+            &["--list-tasks", "--tags", "db"],
+            |output| {
+                assert!(
+                    output.contains("    Apply migration        TAGS: [db]"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("    [rescue] Roll back     TAGS: [db, always]"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("    [always] Release lock  TAGS: [db, always]"),
+                    "{}",
+                    output
+                );
+            },
+        );
+    }
 }
 
 mod recovery {